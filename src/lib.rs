@@ -0,0 +1,32 @@
+//! LoggyXP: watch, tail, filter and search log files.
+//!
+//! The library exposes the pieces the `loggyxp` binary is built from, so other services can
+//! embed tailing and notifications without running the web server:
+//!
+//! - [`LogWatcher`]: follows files and broadcasts their lines as [`LogEvent`]s
+//! - [`LogContextData`]: per-file filters and notifications applied to every new line
+//! - [`search_string`] / [`search_input_pattern`]: substring and regex search over log contents
+//!
+//! ```no_run
+//! use loggyxp::{LogEvent, LogWatcher};
+//!
+//! let watcher = LogWatcher::start();
+//! watcher.context().lock().unwrap()
+//!     .set_notification("/var/log/app.log".into(), "error".into());
+//!
+//! let mut events = watcher.subscribe();
+//! watcher.watch("/var/log/app.log");
+//! while let Ok(event) = events.blocking_recv() {
+//!     if let LogEvent::Log { line, .. } = event {
+//!         println!("{}", line);
+//!     }
+//! }
+//! ```
+
+pub mod log_mgr;
+
+pub use log_mgr::log_context_data::LogContextData;
+pub use log_mgr::log_watcher::LogWatcher;
+pub use log_mgr::rust_server::WsEventTx as LogEvent;
+pub use log_mgr::search_engine::{search_input_pattern, search_string};
+pub use log_mgr::{get_content, run};
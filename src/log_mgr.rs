@@ -1,94 +1,270 @@
 use std::path::{Path, PathBuf};
-use std::thread;
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use serde_json::Value;
 use tokio::sync::broadcast;
-use search_engine::search_input_pattern;
-use log_monitoring::start_watcher_manager;
-use crate::log_mgr::log_monitoring::LogContextData;
 use crate::log_mgr::rust_server::WsEventTx;
+use crate::log_mgr::path_sandbox::PathSandbox;
+use crate::log_mgr::container_discovery::ContainerLogs;
+use crate::log_mgr::log_watcher::LogWatcher;
+use crate::log_mgr::log_context_data::LogContextData;
+use crate::log_mgr::source_rules::{SourceOptions, SourceRules};
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::live_source::LiveSources;
+use crate::log_mgr::syslog::SyslogListener;
+use crate::log_mgr::command_source::CommandSources;
+use crate::log_mgr::notification_sink::NotificationSinks;
+use crate::log_mgr::alert_history::AlertHistory;
+use crate::log_mgr::silence::Silences;
+use crate::log_mgr::metrics::LogMetrics;
+use crate::log_mgr::volume::VolumeQuery;
+use crate::log_mgr::templates::LiveTemplates;
+use crate::log_mgr::anomaly::PatternAlerts;
+use tracing::{info, info_span, warn};
 
 pub mod log_monitoring;
 pub mod search_engine;
 pub mod rust_server;
 pub mod log_context_data;
+pub mod config;
+pub mod path_sandbox;
+pub mod diagnostics;
+pub mod log_watcher;
+pub mod log_record;
+pub mod line_buffer;
+pub mod encoding;
+pub mod compression;
+pub mod rotation;
+pub mod live_source;
+pub mod syslog;
+pub mod command_source;
+pub mod stream_source;
+pub mod multiline;
+pub mod source_rules;
+pub mod log_format;
+pub mod journal;
+pub mod timestamp;
+pub mod timeline;
+pub mod notification;
+pub mod notification_sink;
+pub mod alert_history;
+pub mod silence;
+pub mod metrics;
+pub mod volume;
+pub mod templates;
+pub mod anomaly;
+pub mod container_discovery;
 
-// Entry point for the application
-pub fn main() {
-    println!("main");
-
-    // Create channels for watcher commands and log events
-    let (cmd_tx, cmd_rx) = std::sync::mpsc::channel();
-    let (log_tx, _log_rx) = tokio::sync::broadcast::channel::<WsEventTx>(8192);
-
-    // Shared context for filters and notifications
-    let context = Arc::new(Mutex::new(LogContextData {
-        filters: HashMap::new(),
-        notifies: HashMap::new(),
-    }));
-
-    let context_for_watcher = Arc::clone(&context);
-    let context_for_server = Arc::clone(&context);
-
-    // Start the server in a separate thread
-    thread::spawn({
-        let cmd_tx = cmd_tx.clone();
-        let log_tx = log_tx.clone();
-        move || {
-            rust_server::run_server(cmd_tx, log_tx, context_for_server);
+/// Runs the complete application: loads the config, starts the watcher manager and the
+/// web server, and returns after a graceful shutdown
+pub fn run() {
+    // Load configuration, then set up diagnostics at the configured verbosity
+    let config_path = config::config_path_from_env();
+    let config = match config::load_config(&config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-    });
+    };
+    let recent_errors = diagnostics::init_tracing(&config.log_level);
+    if config_path.exists() {
+        info!(path = %config_path.display(), "Loaded configuration");
+    } else {
+        info!(path = %config_path.display(), "No config file, using defaults");
+    }
+
+    let containers = config.containers.as_ref().map(ContainerLogs::new);
+    let sandbox = Arc::new(PathSandbox::with_containers(&config.sandbox, containers));
+    let rules = Arc::new(SourceRules::new(&config.sources).with_sandbox(Arc::clone(&sandbox)));
+
+    // Start the file watcher manager with a fresh filter/notification context
+    let mut context = LogContextData::with_rules(notification::rules_from_config(&config.notifications));
+    context.set_sinks(NotificationSinks::start(&config.sinks));
+    context.set_alert_history(AlertHistory::new(&config.alerts));
+    context.set_silences(Silences::from_config(&config.silences));
+    context.set_metrics(LogMetrics::from_config(&config.metrics));
+    context.set_templates(LiveTemplates::new(&config.templates));
+    context.set_pattern_alerts(PatternAlerts::new(&config.templates));
+    let watcher = LogWatcher::with_source_rules(Arc::new(Mutex::new(context)), rules);
+
+    // Sources that are not files feed the same pipeline
+    let live = Arc::new(LiveSources::new(
+        watcher.log_tx.clone(),
+        Arc::clone(&watcher.context),
+        Arc::clone(&watcher.status),
+        &config.live,
+    ));
+    let syslog = config.syslog.as_ref().map(|c| SyslogListener::start(c, Arc::clone(&live)));
+    let commands = CommandSources::start(&config.commands, Arc::clone(&live));
+    for fifo in &config.fifos {
+        stream_source::start_fifo(fifo, Arc::clone(&live));
+    }
+    if config::stdin_requested() {
+        stream_source::start_stdin(Arc::clone(&live));
+    }
+
+    // Serve until a shutdown signal was handled (or the server failed to start)
+    rust_server::run_server(&watcher, live, sandbox, config.server, config.timeline, recent_errors);
+
+    // Stop receiving, then stop the watcher manager and wait until its watchers are released
+    if let Some(syslog) = syslog {
+        syslog.stop();
+    }
+    commands.stop();
+    let context = Arc::clone(&watcher.context);
+    watcher.shutdown();
+    context.lock().unwrap().alert_history_mut().close();
+
+    info!("Shutdown complete");
+}
 
-    // Start the file watcher manager
-    let _ = start_watcher_manager(cmd_rx, log_tx, context_for_watcher);
+/// Loads the contents of a log file, pretty-printing JSON files.
+/// Compressed files (gzip, zstd, bzip2, xz) are decompressed; the text is decoded as UTF-8
+/// (UTF-16 if it starts with a byte order mark) with invalid bytes shown as escapes.
+pub fn get_content(path: &Path) -> String
+{
+    read_content(path, &SourceOptions::default())
+}
 
-    // Park the main thread to keep the process alive
-    loop {
-        std::thread::park();
+// Loads the contents of a log file using its source options, pretty-printing JSON files
+fn read_content(path: &Path, options: &SourceOptions) -> String
+{
+    if !path.exists() {
+        return String::new();
     }
+
+    let content = match log_monitoring::load_log_contents(path, options) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!(path = %path.display(), "Failed to read: {}", e);
+            return String::new();
+        }
+    };
+    if log_monitoring::is_json_document(path, options) {
+        let v: Value = serde_json::from_str(&content).unwrap_or_default();
+        serde_json::to_string_pretty(&v).unwrap_or_default()
+    } else {
+        content
+    }
+}
+
+// Loads a log file and splits it into records using the source's format and multi-line rule
+fn get_records(path: &Path, rules: &SourceRules) -> Vec<LogRecord>
+{
+    get_records_and_lines(path, rules).0
+}
+
+// Loads a log file into records; also returns its number of lines, where tailing continues the numbering
+fn get_records_and_lines(path: &Path, rules: &SourceRules) -> (Vec<LogRecord>, usize)
+{
+    let options = rules.options_for(path);
+    let content = read_content(path, &options);
+    (options.records(&content), content.lines().count())
+}
+
+// Loads the files of a merged timeline and orders their records by timestamp
+fn get_merged_records(paths: &[PathBuf], rules: &SourceRules) -> Vec<LogRecord>
+{
+    get_merged_records_and_lines(paths, rules).0
 }
 
-// Loads the contents of the first path in the list, pretty-prints JSON if needed
-pub fn get_content(paths: &Vec<PathBuf>) -> String
+// Loads the files of a merged timeline into records in timeline order; also returns the number
+// of lines of each file
+fn get_merged_records_and_lines(paths: &[PathBuf], rules: &SourceRules) -> (Vec<LogRecord>, Vec<usize>)
 {
-    let log_path = Path::new(paths[0].to_str().unwrap());
-    let content = log_monitoring::load_log_contents(log_path);
-
-    if paths[0].exists() {
-        let text = if paths[0].extension().and_then(|e| e.to_str()) == Some("json") {
-            let v: Value = serde_json::from_str(&content).unwrap_or_default();
-            serde_json::to_string_pretty(&v).unwrap_or_default()
-        } else {
-            content
-        };
-        return text;
+    let (sources, lines): (Vec<_>, Vec<_>) = timeline::origins(paths).into_iter()
+        .zip(paths)
+        .map(|(origin, path)| {
+            let (records, lines) = get_records_and_lines(path, rules);
+            ((origin, records), lines)
+        })
+        .unzip();
+    (timeline::merge_records(sources), lines)
+}
+
+// Sends the existing records of a merged timeline to clients in batches; returns the number of
+// lines of each file, so the watcher manager does not read them again to start tailing
+fn send_merged_history(log_tx: &broadcast::Sender<WsEventTx>, name: &str, paths: &[PathBuf], rules: &SourceRules) -> Vec<usize>
+{
+    let _span = info_span!("merge", name = %name).entered();
+    let (records, lines) = get_merged_records_and_lines(paths, rules);
+    info!(records = records.len(), "Sending merged history");
+    for chunk in records.chunks(200) {
+        let _ = log_tx.send(WsEventTx::log_batch(name, chunk));
     }
-    String::new()
+    lines
+}
+
+// Searches the files of a merged timeline and sends the results, in timeline order, to clients
+fn search_merged(log_tx: &broadcast::Sender<WsEventTx>, pattern: &str, field: Option<&str>, regex: bool, name: &str, paths: &[PathBuf], rules: &SourceRules)
+{
+    let _span = info_span!("search", path = %name, regex).entered();
+    let records = get_merged_records(paths, rules);
+    let lines = if regex {
+        search_engine::search_records_pattern(&records, pattern, field)
+    } else {
+        search_engine::search_records(&records, pattern, field)
+    };
+    info!(matches = lines.len(), "Search finished");
+    let _ = log_tx.send(WsEventTx::SearchResult {
+        path: name.to_string(),
+        lines,
+    });
+}
+
+// Sends the record counts per time bucket of existing records to clients; returns the bucket size
+fn send_volume(log_tx: &broadcast::Sender<WsEventTx>, name: &str, records: &[LogRecord], query: &VolumeQuery, bucket_ms: Option<i64>) -> i64
+{
+    let _span = info_span!("volume", path = %name).entered();
+    let volume = volume::histogram(name, records, query, bucket_ms);
+    let bucket_ms = volume.bucket_ms;
+    info!(buckets = volume.buckets.len(), bucket_ms, "Volume computed");
+    let _ = log_tx.send(WsEventTx::Volume(volume));
+    bucket_ms
 }
 
 // Searches for a string pattern in the log file and sends results to clients
-fn call_search_string(log_tx: &broadcast::Sender<WsEventTx>, pattern: &String, paths: Vec<PathBuf>)
+fn call_search_string(log_tx: &broadcast::Sender<WsEventTx>, pattern: &str, field: Option<&str>, path: &Path, rules: &SourceRules)
 {
-    let content = get_content(&paths);
-    let lines = search_engine::search_string(&content, &pattern);
+    let _span = info_span!("search", path = %path.display(), regex = false).entered();
+    let records = get_records(path, rules);
+    let lines = search_engine::search_records(&records, pattern, field);
+    info!(matches = lines.len(), "Search finished");
     let _ = log_tx.send(WsEventTx::SearchResult {
-        path: paths[0].to_string_lossy().to_string(),
-        lines: lines.clone(),
+        path: path.to_string_lossy().to_string(),
+        lines,
+    });
+}
+
+// Searches the history of a live source (spooled or in memory) and sends results to clients
+fn search_live_source(log_tx: &broadcast::Sender<WsEventTx>, pattern: &str, field: Option<&str>, regex: bool, name: &str, live: &LiveSources)
+{
+    let _span = info_span!("search", path = %name, regex).entered();
+    let records = live.history(name);
+    let lines = if regex {
+        search_engine::search_records_pattern(&records, pattern, field)
+    } else {
+        search_engine::search_records(&records, pattern, field)
+    };
+    info!(matches = lines.len(), "Search finished");
+    let _ = log_tx.send(WsEventTx::SearchResult {
+        path: name.to_string(),
+        lines,
     });
 }
 
 // Searches for a regex pattern in the log file and sends results to clients
-fn get_search_input_with_regex(log_tx: &broadcast::Sender<WsEventTx>, re_pattern: &String, paths: Vec<PathBuf>)
+fn get_search_input_with_regex(log_tx: &broadcast::Sender<WsEventTx>, re_pattern: &str, field: Option<&str>, path: &Path, rules: &SourceRules)
 {
-    let content = get_content(&paths);
+    let _span = info_span!("search", path = %path.display(), regex = true).entered();
+    let records = get_records(path, rules);
 
-    let  matches = search_input_pattern(&content,&re_pattern);
+    let  matches = search_engine::search_records_pattern(&records, re_pattern, field);
+    info!(matches = matches.len(), "Search finished");
 
     let _ = log_tx.send(WsEventTx::SearchResult {
-        path: paths[0].to_string_lossy().to_string(),
+        path: path.to_string_lossy().to_string(),
         lines: matches,
     });
 
 }
-
@@ -0,0 +1,416 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::log_mgr::config::AlertsConfig;
+use crate::log_mgr::notification::{Notification, NotificationKind, Severity};
+use crate::log_mgr::timestamp;
+
+/// How long the history file waits for further changes before they are written together
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest silence of an alert; longer ones are shortened to it
+pub const MAX_SILENCE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Where an alert stands in the on-call workflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Acknowledged,
+    Resolved,
+}
+
+/// The notifications of one rule on one source, from the first one until the alert is resolved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub id: u64,
+    pub rule_id: String,
+    pub severity: Severity,
+    pub path: String,
+    pub state: AlertState,
+    /// Latest matching record
+    pub line: String,
+    /// Latest message of a threshold, ratio, absence, new pattern or anomaly rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub count: u64,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_at: Option<i64>,
+    /// Who resolved the alert; not set when its rule recovered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub silenced_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub silenced_until: Option<i64>,
+    /// Matches recorded while a silence or maintenance window was active
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed: u64,
+    /// The silence that suppressed the latest of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suppressed_by: Option<String>,
+}
+
+impl Alert {
+    /// Whether the alert is still firing or acknowledged
+    pub fn is_open(&self) -> bool {
+        self.state != AlertState::Resolved
+    }
+
+    /// Whether notifications of the alert are held back at `now` (Unix milliseconds)
+    pub fn is_silenced(&self, now: i64) -> bool {
+        self.silenced_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Contents of the history file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredAlerts {
+    next_id: u64,
+    alerts: VecDeque<Alert>,
+}
+
+/// Contents waiting to be written, and whether the writer should stop
+#[derive(Debug, Default)]
+struct Pending {
+    stored: Option<StoredAlerts>,
+    stop: bool,
+}
+
+/// Writes the history file on its own thread, so recording an alert never waits for the disk.
+/// Changes within SAVE_DELAY of each other are written once; dropping the writer writes what
+/// is left.
+#[derive(Debug)]
+struct HistoryWriter {
+    pending: Arc<(Mutex<Pending>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HistoryWriter {
+    fn start(path: PathBuf) -> HistoryWriter {
+        let pending = Arc::new((Mutex::new(Pending::default()), Condvar::new()));
+        let shared = Arc::clone(&pending);
+        let thread = thread::spawn(move || write_pending(&path, &shared));
+        HistoryWriter { pending, thread: Some(thread) }
+    }
+
+    // Replaces the contents waiting to be written
+    fn save(&self, stored: StoredAlerts) {
+        let (lock, changed) = &*self.pending;
+        lock.lock().unwrap().stored = Some(stored);
+        changed.notify_one();
+    }
+}
+
+impl Drop for HistoryWriter {
+    fn drop(&mut self) {
+        let (lock, changed) = &*self.pending;
+        lock.lock().unwrap().stop = true;
+        changed.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Writes the contents handed to a writer until it is dropped
+fn write_pending(path: &Path, pending: &(Mutex<Pending>, Condvar)) {
+    let (lock, changed) = pending;
+    loop {
+        let mut guard = changed.wait_while(lock.lock().unwrap(), |p| p.stored.is_none() && !p.stop).unwrap();
+        if !guard.stop {
+            guard = changed.wait_timeout_while(guard, SAVE_DELAY, |p| !p.stop).unwrap().0;
+        }
+        let (stored, stop) = (guard.stored.take(), guard.stop);
+        drop(guard);
+        if let Some(stored) = stored {
+            write_file(path, &stored);
+        }
+        if stop {
+            return;
+        }
+    }
+}
+
+// Writes the history to a temporary file and moves it over the old one, so a crash never
+// leaves a half-written history
+fn write_file(path: &Path, stored: &StoredAlerts) {
+    let tmp = path.with_extension("tmp");
+    let written = serde_json::to_string(stored)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&tmp, json).map_err(|e| e.to_string()))
+        .and_then(|_| fs::rename(&tmp, path).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        warn!(path = %path.display(), "Cannot save alert history: {}", e);
+    }
+}
+
+/// Alerts raised by notifications, kept (optionally in a file) so dashboards that connect later,
+/// e.g. at an on-call handover, see what already fired and who is on it
+#[derive(Debug)]
+pub struct AlertHistory {
+    writer: Option<HistoryWriter>,
+    max_alerts: usize,
+    replay: usize,
+    next_id: u64,
+    alerts: VecDeque<Alert>,
+}
+
+impl Default for AlertHistory {
+    fn default() -> Self {
+        AlertHistory::new(&AlertsConfig::default())
+    }
+}
+
+impl AlertHistory {
+    /// Creates the history, loading the alerts kept in the configured file
+    pub fn new(config: &AlertsConfig) -> AlertHistory {
+        let stored = config.path.as_ref()
+            .filter(|path| path.exists())
+            .map(|path| {
+                let loaded = fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| serde_json::from_str::<StoredAlerts>(&text).map_err(|e| e.to_string()));
+                match loaded {
+                    Ok(stored) => {
+                        info!(path = %path.display(), alerts = stored.alerts.len(), "Loaded alert history");
+                        stored
+                    }
+                    Err(e) => {
+                        warn!(path = %path.display(), "Cannot load alert history: {}", e);
+                        StoredAlerts::default()
+                    }
+                }
+            })
+            .unwrap_or_default();
+
+        AlertHistory {
+            writer: config.path.clone().map(HistoryWriter::start),
+            max_alerts: config.max_alerts.max(1),
+            replay: config.replay,
+            next_id: stored.next_id.max(1),
+            alerts: stored.alerts,
+        }
+    }
+
+    /// Adds a notification to the open alert of its rule and source, or opens a new one;
+    /// a recovery resolves the open alert. `suppressed_by` names the silence that kept the
+    /// notification from being sent. Returns the changed alert.
+    pub fn record(&mut self, notification: &Notification, suppressed_by: Option<&str>) -> Option<Alert> {
+        let now = timestamp::now_millis();
+        let open = self.alerts.iter().rposition(|alert| {
+            alert.is_open() && alert.rule_id == notification.rule_id && alert.path == notification.path
+        });
+
+        let alert = match (open, notification.kind) {
+            (None, NotificationKind::Recovery) => return None,
+            (Some(idx), NotificationKind::Recovery) => {
+                let alert = &mut self.alerts[idx];
+                alert.state = AlertState::Resolved;
+                alert.resolved_at = Some(now);
+                alert.message = notification.message.clone();
+                alert.clone()
+            }
+            (Some(idx), _) => {
+                let alert = &mut self.alerts[idx];
+                alert.count += notification.count;
+                alert.last_seen = seen_or(notification.last_seen, now);
+                alert.line = notification.line.clone();
+                alert.message = notification.message.clone();
+                if let Some(silence) = suppressed_by {
+                    alert.suppressed += notification.count.max(1);
+                    alert.suppressed_by = Some(silence.to_string());
+                }
+                alert.clone()
+            }
+            (None, _) => {
+                // A silence outlives the alert it was set on
+                let silence = self.alerts.iter()
+                    .rev()
+                    .find(|alert| alert.rule_id == notification.rule_id && alert.path == notification.path)
+                    .filter(|alert| alert.is_silenced(now))
+                    .map(|alert| (alert.silenced_by.clone(), alert.silenced_until));
+                let (silenced_by, silenced_until) = silence.unwrap_or_default();
+
+                let alert = Alert {
+                    id: self.next_id,
+                    rule_id: notification.rule_id.clone(),
+                    severity: notification.severity,
+                    path: notification.path.clone(),
+                    state: AlertState::Firing,
+                    line: notification.line.clone(),
+                    message: notification.message.clone(),
+                    count: notification.count,
+                    first_seen: seen_or(notification.first_seen, now),
+                    last_seen: seen_or(notification.last_seen, now),
+                    acknowledged_by: None,
+                    acknowledged_at: None,
+                    resolved_by: None,
+                    resolved_at: None,
+                    silenced_by,
+                    silenced_until,
+                    suppressed: if suppressed_by.is_some() { notification.count.max(1) } else { 0 },
+                    suppressed_by: suppressed_by.map(str::to_string),
+                };
+                self.next_id += 1;
+                self.alerts.push_back(alert.clone());
+                while self.alerts.len() > self.max_alerts {
+                    self.alerts.pop_front();
+                }
+                alert
+            }
+        };
+        self.save();
+        Some(alert)
+    }
+
+    /// Marks an open alert as being taken care of; it keeps counting but no longer reaches the sinks
+    pub fn acknowledge(&mut self, id: u64, by: &str) -> Result<Alert, String> {
+        self.update(id, |alert, now| {
+            if !alert.is_open() {
+                return Err(format!("alert {} is already resolved", id));
+            }
+            alert.state = AlertState::Acknowledged;
+            alert.acknowledged_by = Some(by.to_string());
+            alert.acknowledged_at = Some(now);
+            Ok(())
+        })
+    }
+
+    /// Closes an alert by hand; the next notification of its rule and source opens a new one
+    pub fn resolve(&mut self, id: u64, by: &str) -> Result<Alert, String> {
+        self.update(id, |alert, now| {
+            if !alert.is_open() {
+                return Err(format!("alert {} is already resolved", id));
+            }
+            alert.state = AlertState::Resolved;
+            alert.resolved_by = Some(by.to_string());
+            alert.resolved_at = Some(now);
+            Ok(())
+        })
+    }
+
+    /// Holds back the notifications of an alert's rule and source for a while (at most
+    /// MAX_SILENCE); they are still recorded
+    pub fn silence(&mut self, id: u64, duration: Duration, by: &str) -> Result<Alert, String> {
+        let millis = duration.min(MAX_SILENCE).as_millis() as i64;
+        self.update(id, |alert, now| {
+            alert.silenced_by = Some(by.to_string());
+            alert.silenced_until = Some(now.saturating_add(millis));
+            Ok(())
+        })
+    }
+
+    /// Writes the changes not saved yet and stops saving; for shutdown
+    pub fn close(&mut self) {
+        self.writer = None;
+    }
+
+    /// The latest `limit` alerts, oldest first
+    pub fn latest(&self, limit: usize) -> Vec<Alert> {
+        self.alerts.iter().skip(self.alerts.len().saturating_sub(limit)).cloned().collect()
+    }
+
+    /// The alerts sent to a newly connected dashboard
+    pub fn replay(&self) -> Vec<Alert> {
+        self.latest(self.replay)
+    }
+
+    // Changes one alert and keeps the file in step
+    fn update(&mut self, id: u64, change: impl FnOnce(&mut Alert, i64) -> Result<(), String>) -> Result<Alert, String> {
+        let alert = self.alerts.iter_mut()
+            .find(|alert| alert.id == id)
+            .ok_or_else(|| format!("no alert {}", id))?;
+        change(alert, timestamp::now_millis())?;
+        let alert = alert.clone();
+        self.save();
+        Ok(alert)
+    }
+
+    // Hands the history to the writer of the configured file
+    fn save(&self) {
+        if let Some(writer) = &self.writer {
+            writer.save(StoredAlerts { next_id: self.next_id, alerts: self.alerts.clone() });
+        }
+    }
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+// Absence alerts have no matching record, so they are dated when they fire
+fn seen_or(seen: i64, now: i64) -> i64 {
+    if seen > 0 { seen } else { now }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(kind: NotificationKind) -> Notification {
+        Notification {
+            rule_id: String::from("errors"),
+            severity: Severity::Critical,
+            kind,
+            path: String::from("/var/log/app.log"),
+            line: String::from("1: ERROR disk full"),
+            message: None,
+            count: 1,
+            first_seen: 1000,
+            last_seen: 1000,
+        }
+    }
+
+    #[test]
+    fn notifications_join_the_open_alert_until_it_recovers() {
+        let mut history = AlertHistory::default();
+        let first = history.record(&notification(NotificationKind::Match), None).unwrap();
+        let second = history.record(&notification(NotificationKind::Match), None).unwrap();
+        assert_eq!((first.id, second.id, second.count), (1, 1, 2));
+        let resolved = history.record(&notification(NotificationKind::Recovery), None).unwrap();
+        assert_eq!(resolved.state, AlertState::Resolved);
+        assert_eq!(history.record(&notification(NotificationKind::Match), None).unwrap().id, 2);
+    }
+
+    #[test]
+    fn silence_is_capped() {
+        let mut history = AlertHistory::default();
+        let id = history.record(&notification(NotificationKind::Match), None).unwrap().id;
+        let alert = history.silence(id, Duration::MAX, "alice").unwrap();
+        let until = alert.silenced_until.unwrap();
+        assert!(until > timestamp::now_millis());
+        assert!(until <= timestamp::now_millis() + MAX_SILENCE.as_millis() as i64);
+    }
+
+    #[test]
+    fn history_is_saved_and_loaded() {
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-alerts.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = AlertsConfig { path: Some(path.clone()), ..AlertsConfig::default() };
+
+        let mut history = AlertHistory::new(&config);
+        let id = history.record(&notification(NotificationKind::Match), None).unwrap().id;
+        history.acknowledge(id, "alice").unwrap();
+        history.close();
+
+        let loaded = AlertHistory::new(&config);
+        let _ = fs::remove_file(&path);
+        let alerts = loaded.replay();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Acknowledged);
+        assert_eq!(alerts[0].acknowledged_by.as_deref(), Some("alice"));
+        assert_eq!(loaded.next_id, 2);
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::log_mgr::config::TemplatesConfig;
+use crate::log_mgr::notification::{deadline_after, Notification, NotificationKind, NotificationRule, Severity, Trigger};
+use crate::log_mgr::templates::TemplateMiner;
+use crate::log_mgr::timestamp;
+
+/// Weight of the latest window in a baseline; older windows fade out over about ten windows
+const BASELINE_WEIGHT: f64 = 0.2;
+
+/// Baselines below this with no records in a window are forgotten
+const MIN_BASELINE: f64 = 0.01;
+
+/// Usual count of a template (or of all matching records) in a window
+#[derive(Debug, Clone, Copy)]
+struct Baseline {
+    usual: f64,
+    // Windows in which the template had records
+    windows: u32,
+}
+
+/// Key of the count of all matching records, next to the template ids (which start at 1)
+const ALL_RECORDS: u64 = 0;
+
+/// Templates and rates of one new pattern or anomaly rule on one source
+#[derive(Debug)]
+struct PatternState {
+    rule_id: String,
+    severity: Severity,
+    trigger: Trigger,
+    cooldown: Duration,
+    miner: TemplateMiner,
+    started: Instant,
+    line: String,
+    first_seen: i64,
+    last_seen: i64,
+    // New patterns found during the cooldown after the last notification, and the latest of them
+    until: Option<Instant>,
+    pending: u64,
+    latest: String,
+    // Counts of the current window, usual counts (per template and of all records) and windows counted
+    window_start: Instant,
+    counts: HashMap<u64, u64>,
+    baselines: HashMap<u64, Baseline>,
+    windows: u32,
+    firing: bool,
+}
+
+impl PatternState {
+    fn new(rule: &NotificationRule, config: &TemplatesConfig, now: Instant) -> PatternState {
+        PatternState {
+            rule_id: rule.id.clone(),
+            severity: rule.severity,
+            trigger: rule.trigger.clone(),
+            cooldown: rule.cooldown,
+            miner: TemplateMiner::new(config),
+            started: now,
+            line: String::new(),
+            first_seen: 0,
+            last_seen: 0,
+            until: None,
+            pending: 0,
+            latest: String::new(),
+            window_start: now,
+            counts: HashMap::new(),
+            baselines: HashMap::new(),
+            windows: 0,
+            firing: false,
+        }
+    }
+
+    fn notify(&self, kind: NotificationKind, message: String, count: u64, path: &str) -> Notification {
+        Notification {
+            rule_id: self.rule_id.clone(),
+            severity: self.severity,
+            kind,
+            path: path.to_string(),
+            line: self.line.clone(),
+            message: Some(message),
+            count,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        }
+    }
+
+    // Sends the first new pattern at once; later ones during the cooldown are sent together
+    fn new_pattern(&mut self, template: String, now: Instant, path: &str) -> Option<Notification> {
+        if self.until.is_some_and(|until| now < until) {
+            self.pending += 1;
+            self.latest = template;
+            return None;
+        }
+        self.until = Some(deadline_after(now, self.cooldown));
+        Some(self.notify(NotificationKind::Match, format!("new message pattern: {}", template), 1, path))
+    }
+
+    // Sends the new patterns counted during a cooldown that ended
+    fn flush_pending(&mut self, now: Instant, path: &str) -> Option<Notification> {
+        if self.pending == 0 || self.until.is_none_or(|until| now < until) {
+            return None;
+        }
+        let message = format!("{} new message patterns, latest: {}", self.pending, self.latest);
+        let notification = self.notify(NotificationKind::Match, message, self.pending, path);
+        self.pending = 0;
+        self.until = Some(deadline_after(now, self.cooldown));
+        Some(notification)
+    }
+
+    // Closes the windows that ended before `now`; returns the alerts and recoveries they caused.
+    // Windows after the counted one had no records: only the first and last of them are
+    // compared, and the baselines fade over those in between in one step.
+    fn roll(&mut self, now: Instant, path: &str) -> Vec<Notification> {
+        let Trigger::Anomaly { window, .. } = self.trigger else {
+            return Vec::new();
+        };
+        let end = deadline_after(self.window_start, window);
+        if now < end {
+            return Vec::new();
+        }
+        let mut notifications: Vec<Notification> = self.close_window(path).into_iter().collect();
+        let elapsed = now.duration_since(end);
+        let empty = elapsed.as_nanos() / window.as_nanos();
+        self.window_start = now - Duration::from_nanos((elapsed.as_nanos() % window.as_nanos()) as u64);
+        if empty >= 1 {
+            notifications.extend(self.close_window(path));
+        }
+        if empty >= 2 {
+            self.fade(empty - 2);
+            notifications.extend(self.close_window(path));
+        }
+        notifications
+    }
+
+    // Moves the baselines as `windows` windows without records would
+    fn fade(&mut self, windows: u128) {
+        let windows = u32::try_from(windows).unwrap_or(u32::MAX);
+        let kept = (1.0 - BASELINE_WEIGHT).powi(i32::try_from(windows).unwrap_or(i32::MAX));
+        for baseline in self.baselines.values_mut() {
+            baseline.usual *= kept;
+        }
+        self.baselines.retain(|_, baseline| baseline.usual >= MIN_BASELINE);
+        self.windows = self.windows.saturating_add(windows);
+    }
+
+    // Compares the counts of a window with the baselines, then moves the baselines towards them
+    fn close_window(&mut self, path: &str) -> Option<Notification> {
+        let Trigger::Anomaly { window, factor, min_count, learning_windows } = self.trigger else {
+            return None;
+        };
+        let learned = self.windows >= learning_windows;
+        let first = self.windows == 0;
+        let ids: BTreeSet<u64> = self.baselines.keys().chain(self.counts.keys()).copied().collect();
+        let mut unusual = Vec::new();
+        for id in ids {
+            let count = self.counts.get(&id).copied().unwrap_or_default();
+            // A template first seen after the first window usually had no records
+            let baseline = self.baselines.entry(id)
+                .or_insert(Baseline { usual: if first { count as f64 } else { 0.0 }, windows: 0 });
+            let spike = count >= min_count && count as f64 > baseline.usual * factor;
+            // A one-off burst is not a usual rate, so only templates seen in enough windows can drop
+            let drop = baseline.windows >= learning_windows
+                && baseline.usual >= min_count as f64
+                && (count as f64) < baseline.usual / factor;
+            if learned && (spike || drop) {
+                let what = match id {
+                    ALL_RECORDS => String::from("all records"),
+                    id => self.miner.template(id).map_or_else(|| format!("template {}", id), |t| format!("\"{}\"", t.template)),
+                };
+                unusual.push(format!("{}: {} in {:?}, usually {:.1}", what, count, window, baseline.usual));
+            }
+            baseline.usual += BASELINE_WEIGHT * (count as f64 - baseline.usual);
+            if count > 0 {
+                baseline.windows = baseline.windows.saturating_add(1);
+            }
+        }
+        self.baselines.retain(|id, baseline| baseline.usual >= MIN_BASELINE || self.counts.contains_key(id));
+        let total = self.counts.get(&ALL_RECORDS).copied().unwrap_or_default();
+        self.counts.clear();
+        self.windows = self.windows.saturating_add(1);
+
+        match (unusual.is_empty(), self.firing) {
+            (false, false) => {
+                self.firing = true;
+                Some(self.notify(NotificationKind::Alert, format!("unusual rates: {}", unusual.join("; ")), total, path))
+            }
+            (true, true) => {
+                self.firing = false;
+                Some(self.notify(NotificationKind::Recovery, String::from("rates back to usual"), total, path))
+            }
+            _ => None,
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let pending = self.until.filter(|_| self.pending > 0);
+        let window = match self.trigger {
+            Trigger::Anomaly { window, .. } => Some(deadline_after(self.window_start, window)),
+            _ => None,
+        };
+        pending.into_iter().chain(window).min()
+    }
+}
+
+/// New pattern and anomaly rules: message templates learned per rule and source, raising a
+/// notification for records of templates not seen while learning, and an alert when the count
+/// of a template (or of all matching records) in a window strays far from its usual count
+#[derive(Debug, Default)]
+pub struct PatternAlerts {
+    config: TemplatesConfig,
+    states: HashMap<(String, String), PatternState>,
+}
+
+impl PatternAlerts {
+    /// Learns templates with the configured settings
+    pub fn new(config: &TemplatesConfig) -> PatternAlerts {
+        PatternAlerts { config: config.clone(), states: HashMap::new() }
+    }
+
+    /// Learns a record of a source the rule applies to; returns the notifications it caused
+    pub fn on_record(&mut self, rule: &NotificationRule, path: &Path, line: &str, content: &str, now: Instant) -> Vec<Notification> {
+        if !matches!(rule.trigger, Trigger::NewPattern { .. } | Trigger::Anomaly { .. }) || !rule.matches(content) {
+            return Vec::new();
+        }
+        let path = path.to_string_lossy().to_string();
+        let state = self.states.entry((rule.id.clone(), path.clone()))
+            .or_insert_with(|| PatternState::new(rule, &self.config, now));
+        let mut notifications = state.roll(now, &path);
+
+        let seen = timestamp::now_millis();
+        if state.first_seen == 0 {
+            state.first_seen = seen;
+        }
+        state.last_seen = seen;
+        state.line = line.to_string();
+        let id = state.miner.add(content, Some(seen));
+
+        match state.trigger {
+            Trigger::NewPattern { learning } => {
+                let template = id.and_then(|id| state.miner.template(id))
+                    .filter(|template| template.count == 1)
+                    .map(|template| template.template.clone());
+                if let Some(template) = template
+                    && now >= deadline_after(state.started, learning) {
+                    notifications.extend(state.new_pattern(template, now, &path));
+                }
+            }
+            Trigger::Anomaly { .. } => {
+                *state.counts.entry(ALL_RECORDS).or_default() += 1;
+                if let Some(id) = id {
+                    *state.counts.entry(id).or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+        notifications
+    }
+
+    /// Forgets what a rule learned about a source
+    pub fn remove(&mut self, rule_id: &str, path: &Path) {
+        self.states.remove(&(rule_id.to_string(), path.to_string_lossy().to_string()));
+    }
+
+    /// Forgets what was learned about a source that is no longer watched
+    pub fn forget_source(&mut self, path: &Path) {
+        let path = path.to_string_lossy();
+        self.states.retain(|(_, source), _| *source != path);
+    }
+
+    /// When the next window ends or the next counted new patterns are due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.states.values().filter_map(PatternState::deadline).min()
+    }
+
+    /// Closes the windows that ended and sends the new patterns of cooldowns that ended
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        for ((_, path), state) in self.states.iter_mut() {
+            notifications.extend(state.roll(now, path));
+            notifications.extend(state.flush_pending(now, path));
+        }
+        notifications
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_mgr::config::AnomalyConfig;
+
+    fn anomaly_rule(window_ms: u64) -> Result<NotificationRule, String> {
+        let mut rule = NotificationRule::new("rates", "", false)?;
+        let config = AnomalyConfig { window_ms, factor: 5.0, min_count: 10, learning_windows: 3 };
+        rule.set_trigger(None, None, None, None, Some(&config))?;
+        Ok(rule)
+    }
+
+    #[test]
+    fn anomaly_windows_are_at_least_a_second() {
+        assert!(anomaly_rule(999).is_err());
+        assert!(anomaly_rule(u64::MAX).is_err());
+        assert!(anomaly_rule(1000).is_ok());
+    }
+
+    #[test]
+    fn long_silence_drops_and_recovers_without_a_window_per_step() {
+        let rule = anomaly_rule(1000).unwrap();
+        let mut alerts = PatternAlerts::new(&TemplatesConfig::default());
+        let path = Path::new("/var/log/app.log");
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        for window in 0..5 {
+            for _ in 0..20 {
+                alerts.on_record(&rule, path, "tick", "tick", start + second * window);
+            }
+        }
+
+        // Closes the last counted window, then alerts on the drop in the first empty one and
+        // recovers once the baselines faded, after about a century of one-second windows
+        let notifications = alerts.flush_expired(start + Duration::from_secs(100 * 365 * 24 * 60 * 60));
+        let kinds: Vec<NotificationKind> = notifications.iter().map(|n| n.kind).collect();
+        assert_eq!(kinds, [NotificationKind::Alert, NotificationKind::Recovery]);
+        let state = alerts.states.values().next().unwrap();
+        assert!(state.baselines.is_empty());
+        assert!(alerts.deadline().unwrap() > start + Duration::from_secs(100 * 365 * 24 * 60 * 60));
+    }
+}
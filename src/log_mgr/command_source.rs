@@ -0,0 +1,253 @@
+use std::io::{BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, info_span, warn};
+use crate::log_mgr::config::{CommandConfig, CommandLine};
+use crate::log_mgr::live_source::LiveSources;
+use crate::log_mgr::stream_source;
+
+/// How often a supervisor checks whether its command exited or it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Shortest delay before a restart, so a command that exits at once cannot spin
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Names of the two live sources a command feeds
+fn stream_names(name: &str) -> (String, String) {
+    (format!("command://{}/stdout", name), format!("command://{}/stderr", name))
+}
+
+/// Supervisors of the configured commands; stopping them kills the running commands
+pub struct CommandSources {
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl CommandSources {
+    /// Starts one supervisor thread per configured command
+    pub fn start(configs: &[CommandConfig], live: Arc<LiveSources>) -> CommandSources {
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = configs.iter()
+            .cloned()
+            .map(|config| {
+                let (stop, live) = (Arc::clone(&stop), Arc::clone(&live));
+                thread::spawn(move || {
+                    let _span = info_span!("command", name = %config.name).entered();
+                    supervise(&config, &live, &stop);
+                })
+            })
+            .collect();
+
+        CommandSources { stop, threads }
+    }
+
+    /// Kills the running commands and waits for their supervisors
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Runs the command until it should stop, restarting it with exponential backoff
+fn supervise(config: &CommandConfig, live: &Arc<LiveSources>, stop: &AtomicBool) {
+    let (_, stderr_name) = stream_names(&config.name);
+    let (initial, max) = backoff_bounds(config);
+    let mut backoff = initial;
+
+    while !stop.load(Ordering::Relaxed) {
+        let started = Instant::now();
+        let outcome = run_once(config, live, stop);
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+
+        // Report why the output stopped in the status (not as output the command never wrote)
+        let reason = match outcome {
+            Ok(status) => format!("command exited ({})", status),
+            Err(e) => format!("cannot start command: {}", e),
+        };
+        live.source_failed(&stderr_name, &reason);
+
+        if !config.restart {
+            warn!("{}", reason);
+            break;
+        }
+
+        // A command that ran for a while starts over with the initial delay
+        if started.elapsed() >= max {
+            backoff = initial;
+        }
+        warn!("{}, restarting in {:?}", reason, backoff);
+        sleep_unless_stopped(backoff, stop);
+        backoff = backoff.saturating_mul(2).min(max);
+    }
+}
+
+// Initial and longest restart delay of a command, at least `MIN_BACKOFF` each
+fn backoff_bounds(config: &CommandConfig) -> (Duration, Duration) {
+    let initial = Duration::from_millis(config.backoff_initial_ms).max(MIN_BACKOFF);
+    (initial, Duration::from_millis(config.backoff_max_ms).max(initial))
+}
+
+// Runs the command once and forwards its output; kills it when asked to stop
+fn run_once(config: &CommandConfig, live: &Arc<LiveSources>, stop: &AtomicBool) -> std::io::Result<String> {
+    let mut command = build_command(&config.command)?;
+    if let Some(cwd) = &config.cwd {
+        command.current_dir(cwd);
+    }
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    info!(pid = child.id(), "Command started");
+
+    let (stdout_name, stderr_name) = stream_names(&config.name);
+    let readers = [
+        child.stdout.take().map(|out| forward_lines(out, stdout_name, Arc::clone(live))),
+        child.stderr.take().map(|err| forward_lines(err, stderr_name, Arc::clone(live))),
+    ];
+
+    let status = wait_or_kill(&mut child, stop)?;
+
+    // Forward the remaining output before restarting. When stopping, don't wait: processes
+    // started by the command may still hold the pipes open.
+    if !stop.load(Ordering::Relaxed) {
+        for reader in readers.into_iter().flatten() {
+            let _ = reader.join();
+        }
+    }
+    Ok(status)
+}
+
+/// Prepares a configured command line: a shell string runs with `sh -c`
+pub(crate) fn build_command(line: &CommandLine) -> std::io::Result<Command> {
+    Ok(match line {
+        CommandLine::Shell(line) => {
+            let mut c = Command::new("sh");
+            c.arg("-c").arg(line);
+            c
+        }
+        CommandLine::Argv(argv) => {
+            let Some((program, args)) = argv.split_first() else {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "empty command"));
+            };
+            let mut c = Command::new(program);
+            c.args(args);
+            c
+        }
+    })
+}
+
+// Waits for the command to exit, killing it if the supervisor is stopped first
+fn wait_or_kill(child: &mut Child, stop: &AtomicBool) -> std::io::Result<String> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.to_string());
+        }
+        if stop.load(Ordering::Relaxed) {
+            let _ = child.kill();
+            return child.wait().map(|status| status.to_string());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Reads one output stream line by line into a live source until it closes
+fn forward_lines(stream: impl Read + Send + 'static, name: String, live: Arc<LiveSources>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = stream_source::forward_lines(BufReader::new(stream), &name, &live) {
+            warn!(source = %name, "Failed to read command output: {}", e);
+        }
+    })
+}
+
+// Sleeps for `duration` in short steps so a stop request is noticed quickly
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    // A delay too long to represent is slept until stopped
+    let until = Instant::now().checked_add(duration);
+    while !stop.load(Ordering::Relaxed) {
+        let left = until.map_or(POLL_INTERVAL, |until| until.saturating_duration_since(Instant::now()));
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::broadcast;
+    use crate::log_mgr::config::LiveConfig;
+    use crate::log_mgr::diagnostics::StatusData;
+    use crate::log_mgr::log_context_data::LogContextData;
+
+    fn config(restart: bool, backoff_initial_ms: u64, backoff_max_ms: u64) -> CommandConfig {
+        CommandConfig {
+            name: String::from("test"),
+            command: CommandLine::Argv(vec![String::from("echo"), String::from("started")]),
+            cwd: None,
+            restart,
+            backoff_initial_ms,
+            backoff_max_ms,
+        }
+    }
+
+    fn live_sources() -> Arc<LiveSources> {
+        let (log_tx, _) = broadcast::channel(16);
+        let context = Arc::new(Mutex::new(LogContextData::new()));
+        let status = Arc::new(Mutex::new(StatusData::default()));
+        Arc::new(LiveSources::new(log_tx, context, status, &LiveConfig::default()))
+    }
+
+    #[test]
+    fn restart_delays_are_bounded() {
+        assert_eq!(backoff_bounds(&config(true, 0, 0)), (MIN_BACKOFF, MIN_BACKOFF));
+        assert_eq!(backoff_bounds(&config(true, 500, 200)), (Duration::from_millis(500), Duration::from_millis(500)));
+
+        // Doubling the longest representable delay saturates instead of overflowing
+        let (initial, max) = backoff_bounds(&config(true, 1000, u64::MAX));
+        assert_eq!(initial, Duration::from_secs(1));
+        assert_eq!(Duration::MAX.saturating_mul(2).min(max), max);
+    }
+
+    #[test]
+    fn exited_commands_restart_with_backoff() {
+        let live = live_sources();
+        let stop = AtomicBool::new(false);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            // Restarts after 100, 200 and 400 ms: four runs within the first second
+            let supervisor = scope.spawn(|| supervise(&config(true, 0, 400), &live, &stop));
+            thread::sleep(Duration::from_millis(1000));
+            stop.store(true, Ordering::Relaxed);
+            supervisor.join().unwrap();
+        });
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        let runs = live.history("command://test/stdout");
+        assert!((2..=5).contains(&runs.len()), "{} runs", runs.len());
+        assert!(runs.iter().all(|record| record.text == "started"));
+    }
+
+    #[test]
+    fn commands_without_restart_run_once() {
+        let live = live_sources();
+        let stop = AtomicBool::new(false);
+        supervise(&config(false, 0, 0), &live, &stop);
+        assert_eq!(live.history("command://test/stdout").len(), 1);
+
+        let mut missing = config(false, 0, 0);
+        missing.command = CommandLine::Argv(Vec::new());
+        supervise(&missing, &live, &stop);
+        assert_eq!(live.names(), vec![String::from("command://test/stdout")]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+
+/// Compression formats recognised by their magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Recognises the format from the first bytes of a file; `None` for plain files
+    pub fn detect(head: &[u8]) -> Option<Compression> {
+        if head.starts_with(&[0x1F, 0x8B]) {
+            Some(Compression::Gzip)
+        } else if head.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(Compression::Zstd)
+        } else if head.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if head.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Recognises the format of a file; unreadable files count as plain
+    pub fn detect_file(path: &Path) -> Option<Compression> {
+        let mut head = [0u8; 6];
+        let read = File::open(path).and_then(|mut f| f.read(&mut head)).unwrap_or(0);
+        Compression::detect(&head[..read])
+    }
+
+    /// Decompresses a complete file, including files made of several concatenated streams
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Compression::Gzip => {
+                MultiGzDecoder::new(data).read_to_end(&mut out)?;
+            }
+            Compression::Bzip2 => {
+                MultiBzDecoder::new(data).read_to_end(&mut out)?;
+            }
+            Compression::Zstd => {
+                let mut input = data;
+                while !input.is_empty() {
+                    let mut decoder = StreamingDecoder::new(&mut input)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    decoder.read_to_end(&mut out)?;
+                }
+            }
+            Compression::Xz => {
+                let mut input = io::BufReader::new(data);
+                lzma_rs::xz_decompress(&mut input, &mut out)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Reads a file, transparently decompressing it when it is compressed
+pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    match Compression::detect(&data) {
+        Some(compression) => compression.decompress(&data),
+        None => Ok(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gzip_files_are_decompressed() {
+        // logrotate may append to a compressed rotation, leaving several streams in one file
+        let mut data = gzip(b"first line\n");
+        data.extend(gzip(b"second line\n"));
+        assert_eq!(Compression::detect(&data), Some(Compression::Gzip));
+
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-app.log.1.gz", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        assert_eq!(Compression::detect_file(&path), Some(Compression::Gzip));
+        assert_eq!(read_file(&path).unwrap(), b"first line\nsecond line\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn zstd_files_are_decompressed() {
+        let mut data = compress_to_vec(&b"first line\n"[..], CompressionLevel::Fastest);
+        data.extend(compress_to_vec(&b"second line\n"[..], CompressionLevel::Fastest));
+        assert_eq!(Compression::detect(&data), Some(Compression::Zstd));
+        assert_eq!(Compression::Zstd.decompress(&data).unwrap(), b"first line\nsecond line\n");
+    }
+
+    #[test]
+    fn plain_files_are_read_as_they_are() {
+        assert_eq!(Compression::detect(b"plain text"), None);
+        assert_eq!(Compression::detect(b""), None);
+
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-plain.log", std::process::id()));
+        fs::write(&path, b"just text\n").unwrap();
+        assert_eq!(read_file(&path).unwrap(), b"just text\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_input_is_an_error() {
+        let mut gz = gzip(b"some log line\n");
+        let len = gz.len();
+        gz.truncate(len / 2);
+        assert!(Compression::Gzip.decompress(&gz).is_err());
+
+        let mut zst = compress_to_vec(&b"some log line\n"[..], CompressionLevel::Fastest);
+        zst.truncate(zst.len() / 2);
+        assert!(Compression::Zstd.decompress(&zst).is_err());
+        assert!(Compression::Zstd.decompress(&[0x28, 0xB5, 0x2F, 0xFD, 0xFF, 0xFF]).is_err());
+
+        assert!(Compression::Bzip2.decompress(b"BZh9 not really bzip2").is_err());
+        assert!(Compression::Xz.decompress(&[0xFD, b'7', b'z', b'X', b'Z', 0x00, 1, 2, 3]).is_err());
+    }
+}
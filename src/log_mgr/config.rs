@@ -0,0 +1,730 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::log_mgr::notification::Severity;
+
+/// Default location of the configuration file, relative to the working directory
+pub const DEFAULT_CONFIG_PATH: &str = "loggyxp.json";
+
+/// Application configuration, loaded once at startup from a JSON file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Verbosity of internal diagnostics (e.g. "info", "debug", "loggyxp=trace"); `RUST_LOG` overrides it
+    pub log_level: String,
+    pub server: ServerConfig,
+    pub sandbox: SandboxConfig,
+    /// Per-source processing rules, matched by glob against the absolute path
+    pub sources: Vec<SourceConfig>,
+    /// Settings shared by sources that are not files (syslog, ...)
+    pub live: LiveConfig,
+    /// Receive syslog messages over the network when present
+    pub syslog: Option<SyslogConfig>,
+    /// Commands whose output is captured as live sources
+    pub commands: Vec<CommandConfig>,
+    /// Named pipes read as live sources
+    pub fifos: Vec<FifoConfig>,
+    /// Discover container logs and open them by container or pod name when present
+    pub containers: Option<ContainersConfig>,
+    /// Settings for merged timelines of several files
+    pub timeline: TimelineConfig,
+    /// Notification rules applied to every record of the sources they match
+    pub notifications: Vec<NotificationRuleConfig>,
+    /// Destinations outside the dashboard that notification rules send to
+    pub sinks: Vec<SinkConfig>,
+    /// Where fired alerts are kept for later dashboards
+    pub alerts: AlertsConfig,
+    /// Times in which matching notifications are only recorded, e.g. deploys and batch jobs
+    pub silences: Vec<SilenceConfig>,
+    /// Metrics computed from log records, served at `/metrics`
+    pub metrics: Vec<MetricConfig>,
+    /// Settings for mining message templates from records
+    pub templates: TemplatesConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_level: String::from("info"),
+            server: ServerConfig::default(),
+            sandbox: SandboxConfig::default(),
+            sources: Vec::new(),
+            live: LiveConfig::default(),
+            syslog: None,
+            commands: Vec::new(),
+            fifos: Vec::new(),
+            containers: None,
+            timeline: TimelineConfig::default(),
+            notifications: Vec::new(),
+            sinks: Vec::new(),
+            alerts: AlertsConfig::default(),
+            silences: Vec::new(),
+            metrics: Vec::new(),
+            templates: TemplatesConfig::default(),
+        }
+    }
+}
+
+/// Settings for live (non-file) sources
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LiveConfig {
+    /// Append every live record to a file in this directory so it can be searched and replayed
+    pub spool_dir: Option<PathBuf>,
+    /// How many of the latest records per source search and replay read: from the spool files,
+    /// or kept in memory without a spool directory
+    pub history_lines: usize,
+    /// Size at which a spool file is renamed to `<file>.1`, replacing the previous one, and started over
+    pub max_spool_bytes: u64,
+    /// Most syslog sources kept; beyond it the one quiet longest is forgotten, with its spool file
+    pub max_syslog_sources: usize,
+}
+
+impl Default for LiveConfig {
+    fn default() -> Self {
+        LiveConfig {
+            spool_dir: None,
+            history_lines: 10_000,
+            max_spool_bytes: 64 * 1024 * 1024,
+            max_syslog_sources: 1000,
+        }
+    }
+}
+
+/// A notification rule
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationRuleConfig {
+    /// Identifies the rule in notifications
+    pub id: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Case-insensitive substring, or a regex when `regex` is set; empty matches every record
+    #[serde(default)]
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+    /// Globs of the sources (paths or live source names) the rule applies to; all when empty
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Identical messages within this time are counted and sent together
+    #[serde(default = "default_cooldown_ms")]
+    pub cooldown_ms: u64,
+    /// Alert on bursts of matches instead of single records
+    #[serde(default)]
+    pub threshold: Option<ThresholdConfig>,
+    /// Alert when matches make up too large a share of the records
+    #[serde(default)]
+    pub ratio: Option<RatioConfig>,
+    /// Alert when no record matches for a while
+    #[serde(default)]
+    pub absence: Option<AbsenceConfig>,
+    /// Notify when a record fits none of the message templates learned from the source
+    #[serde(default)]
+    pub new_pattern: Option<NewPatternConfig>,
+    /// Alert when the rate of a message template strays far from its usual rate
+    #[serde(default)]
+    pub anomaly: Option<AnomalyConfig>,
+    /// Names of the sinks the rule's notifications are also sent to
+    #[serde(default)]
+    pub sinks: Vec<String>,
+}
+
+/// Alert when more than `count` records match within the window
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThresholdConfig {
+    pub count: u64,
+    pub window_ms: u64,
+}
+
+/// Alert when more than `percent` of the records within the window match
+#[derive(Debug, Clone, Deserialize)]
+pub struct RatioConfig {
+    pub percent: f64,
+    pub window_ms: u64,
+    /// Only count records matching this pattern (substring, or regex like the rule's pattern)
+    #[serde(default)]
+    pub of: Option<String>,
+    /// Fewer records than this in the window never raise an alert
+    #[serde(default = "default_min_records")]
+    pub min_records: u64,
+}
+
+/// Alert when no record matches within `within_ms`, recover with the next one that does
+#[derive(Debug, Clone, Deserialize)]
+pub struct AbsenceConfig {
+    pub within_ms: u64,
+}
+
+/// Notify about records of new message templates once the templates of a source have been
+/// learned for `learning_ms`
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewPatternConfig {
+    #[serde(default = "default_learning_ms")]
+    pub learning_ms: u64,
+}
+
+/// Alert when a template's count in a window is more than `factor` times its baseline (and at
+/// least `min_count`), or less than its baseline divided by `factor`; recover when all are back
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnomalyConfig {
+    #[serde(default = "default_anomaly_window_ms")]
+    pub window_ms: u64,
+    #[serde(default = "default_anomaly_factor")]
+    pub factor: f64,
+    /// Smaller counts are never unusual: a spike must reach it, a drop must come from it
+    #[serde(default = "default_min_records")]
+    pub min_count: u64,
+    /// Windows counted before the baselines are trusted
+    #[serde(default = "default_learning_windows")]
+    pub learning_windows: u32,
+}
+
+/// A destination for notifications outside the dashboard, referred to by name from rules
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    /// Further attempts after a failed delivery
+    #[serde(default = "default_sink_retries")]
+    pub retries: u32,
+    /// Delay before the first retry; doubles after each further failure
+    #[serde(default = "default_sink_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    /// Whether rules set from the dashboard may send to this sink
+    #[serde(default)]
+    pub dashboard: bool,
+}
+
+/// How a sink delivers notifications. Templates replace `{{field}}` with a field of the
+/// notification (`rule_id`, `severity`, `kind`, `path`, `line`, `message`, `count`, `first_seen`, `last_seen`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkKind {
+    /// HTTP POST of a JSON body
+    Webhook {
+        url: String,
+        /// JSON template of the body; the notification itself when not set
+        #[serde(default)]
+        body: Option<Value>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default = "default_sink_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// Runs a command with the notification in `LOGGYXP_*` environment variables
+    Command {
+        command: CommandLine,
+        #[serde(default = "default_sink_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// Appends the notification as one JSON line to a file
+    File {
+        path: PathBuf,
+    },
+    /// Sends an email
+    Smtp(SmtpConfig),
+}
+
+/// Mail server and message of an SMTP sink
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// 25, 587 or 465 by default, depending on `security`
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Template of the subject
+    #[serde(default = "default_smtp_subject")]
+    pub subject: String,
+    /// Template of the text body
+    #[serde(default = "default_smtp_body")]
+    pub body: String,
+    #[serde(default = "default_sink_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// Transport security of an SMTP connection
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain text, e.g. for a relay on localhost
+    #[default]
+    None,
+    /// Upgrade with STARTTLS
+    Starttls,
+    /// TLS from the start
+    Tls,
+}
+
+fn default_sink_retries() -> u32 {
+    3
+}
+
+fn default_sink_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_sink_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_smtp_subject() -> String {
+    String::from("[loggyxp] {{severity}} {{rule_id}} on {{path}}")
+}
+
+fn default_smtp_body() -> String {
+    String::from("{{kind}} of rule {{rule_id}} ({{severity}}) on {{path}}\n\n{{message}}\n{{line}}\n\nmatches: {{count}}")
+}
+
+fn default_cooldown_ms() -> u64 {
+    60_000
+}
+
+fn default_min_records() -> u64 {
+    10
+}
+
+fn default_learning_ms() -> u64 {
+    600_000
+}
+
+fn default_anomaly_window_ms() -> u64 {
+    60_000
+}
+
+fn default_anomaly_factor() -> f64 {
+    5.0
+}
+
+fn default_learning_windows() -> u32 {
+    10
+}
+
+/// Settings for the alert history
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertsConfig {
+    /// JSON file the alert history is kept in across restarts; in memory only when not set
+    pub path: Option<PathBuf>,
+    /// How many alerts are kept; the oldest are dropped first
+    pub max_alerts: usize,
+    /// How many of the latest alerts a newly connected dashboard receives
+    pub replay: usize,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig { path: None, max_alerts: 1000, replay: 200 }
+    }
+}
+
+/// Settings of the template miner
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    /// Share of equal tokens (0 to 1) a record needs to join a template
+    pub similarity: f64,
+    /// Leading tokens that must be equal (numbers aside) for records to share a template
+    pub prefix_tokens: usize,
+    /// Templates kept per source; records that fit none of them once reached are only counted
+    pub max_templates: usize,
+    /// Example records kept per template
+    pub examples: usize,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        TemplatesConfig { similarity: 0.5, prefix_tokens: 2, max_templates: 1000, examples: 3 }
+    }
+}
+
+/// A silence: notifications it matches while it is active are recorded as suppressed instead of sent.
+/// Empty criteria match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SilenceConfig {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Ids of the rules silenced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    /// Globs of the sources silenced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// Only notifications whose record contains this (case-insensitive), or matches it as a regex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub regex: bool,
+    /// RFC 3339 time the silence starts; immediately when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<String>,
+    /// RFC 3339 time the silence ends; never when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<String>,
+    /// Only active during this daily window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleConfig>,
+}
+
+/// A daily window such as a nightly batch run; `end` before `start` spans midnight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    /// `HH:MM`
+    pub start: String,
+    /// `HH:MM`
+    pub end: String,
+    /// Days the window starts on (`mon` … `sun`); every day when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// Offset of the times from UTC, e.g. `+02:00`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<String>,
+}
+
+/// A metric computed from the records matching a regex
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricConfig {
+    /// Prometheus metric name, e.g. `apache_log_lines_total`
+    pub name: String,
+    #[serde(default)]
+    pub help: String,
+    #[serde(rename = "type", default)]
+    pub kind: MetricKind,
+    /// Regex a record has to match; its named groups provide labels and values
+    pub pattern: String,
+    /// Globs of the sources (paths or live source names) counted; all when empty
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Label names mapped to the named capture groups that provide their values
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Label holding the source of the record, when set
+    #[serde(default)]
+    pub source_label: Option<String>,
+    /// Named capture group with the number a histogram observes
+    #[serde(default)]
+    pub value: Option<String>,
+    /// Upper bounds of the histogram buckets
+    #[serde(default = "default_buckets")]
+    pub buckets: Vec<f64>,
+    /// Label combinations kept; records adding more are not counted
+    #[serde(default = "default_max_series")]
+    pub max_series: usize,
+}
+
+/// How a metric aggregates the matching records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// Number of matching records
+    #[default]
+    Counter,
+    /// Distribution of the numbers captured by `value`
+    Histogram,
+}
+
+fn default_buckets() -> Vec<f64> {
+    vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+}
+
+fn default_max_series() -> usize {
+    1000
+}
+
+/// Settings for merged timelines
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimelineConfig {
+    /// How long new records are held back so that slightly late writers are still put in order
+    pub reorder_window_ms: u64,
+}
+
+impl Default for TimelineConfig {
+    fn default() -> Self {
+        TimelineConfig { reorder_window_ms: 2000 }
+    }
+}
+
+/// Where container runtimes keep their logs; `null` disables a location.
+/// Only the container log files in them may be opened (see `ContainerLogs::is_log_file`), not the
+/// rest of these directories.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ContainersConfig {
+    /// Docker json-file logs, opened as `docker://<container name>`
+    pub docker_dir: Option<PathBuf>,
+    /// Kubernetes pod logs, opened as `k8s://<namespace>/<pod>/<container>`
+    pub pods_dir: Option<PathBuf>,
+}
+
+impl Default for ContainersConfig {
+    fn default() -> Self {
+        ContainersConfig {
+            docker_dir: Some(PathBuf::from("/var/lib/docker/containers")),
+            pods_dir: Some(PathBuf::from("/var/log/pods")),
+        }
+    }
+}
+
+/// A named pipe read as the source `fifo://<name>`; reopened whenever its writer closes it
+#[derive(Debug, Clone, Deserialize)]
+pub struct FifoConfig {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Syslog receiver (RFC 3164 and RFC 5424)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SyslogConfig {
+    /// UDP address to listen on, e.g. `0.0.0.0:5514`
+    pub udp: Option<String>,
+    /// TCP address to listen on; both newline-delimited and octet-counted framing are accepted
+    pub tcp: Option<String>,
+    /// Most TCP senders connected at once; further connections are closed right away
+    pub max_connections: usize,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        SyslogConfig {
+            udp: None,
+            tcp: None,
+            max_connections: 64,
+        }
+    }
+}
+
+/// Processing options for the sources whose absolute path matches `path`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceConfig {
+    /// Glob pattern, e.g. `/var/log/app/*.log` or `**/catalina.out`
+    pub path: String,
+    /// Group continuation lines (stack traces etc.) into one record
+    #[serde(default)]
+    pub multiline: Option<MultilineConfig>,
+    /// While tailing, emit a line still missing its newline after this long without new data
+    #[serde(default)]
+    pub partial_line_timeout_ms: Option<u64>,
+    /// Character encoding of the file; UTF-8 (or UTF-16 when a byte order mark says so) if unset
+    #[serde(default)]
+    pub encoding: Option<SourceEncoding>,
+    /// Show and search rotations (`app.log.1`, `app.log.2.gz`, ...) together with the file, oldest first
+    #[serde(default)]
+    pub rotated: bool,
+    /// Record format; detected from the first line when unset
+    #[serde(default)]
+    pub format: Option<SourceFormat>,
+}
+
+/// Record formats a source can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceFormat {
+    /// One record per line as written
+    Plain,
+    /// Docker json-file driver: `{"log":"...","stream":"stdout","time":"..."}`
+    Docker,
+    /// CRI (containerd, CRI-O): `<time> <stream> <P|F> <message>`
+    Cri,
+    /// systemd journal export format (`journalctl -o export`)
+    Journal,
+    /// systemd journal as JSON lines (`journalctl -o json`)
+    JournalJson,
+}
+
+/// Character encodings a source can declare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SourceEncoding {
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    #[serde(rename = "latin-1", alias = "latin1", alias = "iso-8859-1")]
+    Latin1,
+    /// Byte order taken from the byte order mark, little endian without one
+    #[serde(rename = "utf-16", alias = "utf16")]
+    Utf16,
+    #[serde(rename = "utf-16le")]
+    Utf16Le,
+    #[serde(rename = "utf-16be")]
+    Utf16Be,
+}
+
+/// How lines are grouped into multi-line records
+#[derive(Debug, Clone, Deserialize)]
+pub struct MultilineConfig {
+    #[serde(flatten)]
+    pub mode: MultilineMode,
+    /// Upper bound on lines per record
+    #[serde(default = "default_multiline_max_lines")]
+    pub max_lines: usize,
+    /// While tailing, emit a pending record after this long without new lines
+    #[serde(default = "default_multiline_flush_timeout_ms")]
+    pub flush_timeout_ms: u64,
+}
+
+/// What marks the first line of a record
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum MultilineMode {
+    /// Lines matching `pattern` start a record, all others continue it
+    StartPattern { pattern: String },
+    /// Lines starting with whitespace continue the previous record
+    Indent,
+    /// Lines starting with a recognised timestamp start a record
+    Timestamp,
+}
+
+fn default_multiline_max_lines() -> usize {
+    500
+}
+
+fn default_multiline_flush_timeout_ms() -> u64 {
+    1000
+}
+
+/// Where and how the HTTP/WebSocket server listens
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub address: String,
+    /// Serve HTTPS/WSS instead of plain HTTP/WS when present
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: String::from("127.0.0.1:3000"),
+            tls: None,
+        }
+    }
+}
+
+/// A command run by loggyxp whose stdout and stderr become the sources
+/// `command://<name>/stdout` and `command://<name>/stderr`
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandConfig {
+    pub name: String,
+    pub command: CommandLine,
+    /// Working directory of the command
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Start the command again when it exits
+    #[serde(default = "default_restart")]
+    pub restart: bool,
+    /// Delay before the first restart; doubles after each quick exit
+    #[serde(default = "default_backoff_initial_ms")]
+    pub backoff_initial_ms: u64,
+    /// Upper bound of the restart delay
+    #[serde(default = "default_backoff_max_ms")]
+    pub backoff_max_ms: u64,
+}
+
+/// A command given as a shell string (run with `sh -c`) or as program and arguments
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+fn default_restart() -> bool {
+    true
+}
+
+fn default_backoff_initial_ms() -> u64 {
+    1000
+}
+
+fn default_backoff_max_ms() -> u64 {
+    60_000
+}
+
+/// Certificate settings for TLS
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain
+    pub cert_path: Option<PathBuf>,
+    /// PEM encoded private key
+    pub key_path: Option<PathBuf>,
+    /// Generate a throwaway self-signed certificate for localhost when no paths are given
+    pub self_signed: bool,
+}
+
+/// Which files clients are allowed to open
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Directories whose contents (recursively) may be opened
+    pub allowed_roots: Vec<PathBuf>,
+    /// Glob patterns matched against the canonical absolute path of a file
+    pub allowed_globs: Vec<String>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        // Without a config file only files below the working directory are reachable
+        SandboxConfig {
+            allowed_roots: vec![PathBuf::from(".")],
+            allowed_globs: Vec::new(),
+        }
+    }
+}
+
+/// Returns the config file path: `--config <path>`, then `LOGGYXP_CONFIG`, then the default
+pub fn config_path_from_env() -> PathBuf {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config"
+            && let Some(path) = args.next() {
+            return PathBuf::from(path);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return PathBuf::from(path);
+        }
+    }
+
+    match std::env::var("LOGGYXP_CONFIG") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(DEFAULT_CONFIG_PATH),
+    }
+}
+
+/// Whether standard input should be read as a source: `--stdin`, or input piped into loggyxp
+pub fn stdin_requested() -> bool {
+    if std::env::args().skip(1).any(|arg| arg == "--stdin") {
+        return true;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        fs::metadata("/dev/stdin").is_ok_and(|m| m.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+/// Loads the configuration file; a missing file yields the defaults, an invalid one is an error
+pub fn load_config(path: &Path) -> Result<Config, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("cannot read config {}: {}", path.display(), e)),
+    };
+
+    serde_json::from_str(&text)
+        .map_err(|e| format!("invalid config {}: {}", path.display(), e))
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use tracing::debug;
+use crate::log_mgr::config::ContainersConfig;
+
+/// Name prefixes of container log labels; they resolve to the container's current log file
+pub const CONTAINER_SCHEMES: [&str; 2] = ["docker://", "k8s://"];
+
+/// Whether a client-supplied name is a container label rather than a path
+pub fn is_container_label(name: &str) -> bool {
+    CONTAINER_SCHEMES.iter().any(|scheme| name.starts_with(scheme))
+}
+
+/// A discovered container log file and the label it is opened by
+#[derive(Debug, Clone)]
+pub struct ContainerLog {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+/// Finds container logs in the Docker and Kubernetes log directories.
+///
+/// Containers come and go, so the directories are scanned on every lookup.
+#[derive(Debug)]
+pub struct ContainerLogs {
+    docker_dir: Option<PathBuf>,
+    pods_dir: Option<PathBuf>,
+}
+
+impl ContainerLogs {
+    pub fn new(config: &ContainersConfig) -> ContainerLogs {
+        ContainerLogs { docker_dir: config.docker_dir.clone(), pods_dir: config.pods_dir.clone() }
+    }
+
+    /// Whether a canonical path is a container log file: `<docker_dir>/<id>/<id>-json.log` or
+    /// `<pods_dir>/<pod>/<container>/<restart>.log`. Nothing else in these directories is,
+    /// e.g. the `config.v2.json` that holds a container's environment.
+    pub fn is_log_file(&self, canonical: &Path) -> bool {
+        let name = canonical.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let parent = canonical.parent().and_then(Path::file_name).and_then(|n| n.to_str()).unwrap_or_default();
+        let docker = name.strip_suffix("-json.log").is_some_and(|id| !id.is_empty() && id == parent);
+        let pod = name.strip_suffix(".log").is_some_and(|restart| restart.parse::<u32>().is_ok());
+        (docker && is_nested(canonical, self.docker_dir.as_deref(), 2))
+            || (pod && is_nested(canonical, self.pods_dir.as_deref(), 3))
+    }
+
+    /// All container logs currently present, sorted by label
+    pub fn discover(&self) -> Vec<ContainerLog> {
+        let mut logs = Vec::new();
+        if let Some(dir) = &self.docker_dir {
+            logs.extend(docker_logs(dir));
+        }
+        if let Some(dir) = &self.pods_dir {
+            logs.extend(pod_logs(dir));
+        }
+        logs.sort_by(|a, b| a.label.cmp(&b.label));
+        logs
+    }
+
+    /// The log file of a container label, if the container exists
+    pub fn resolve(&self, label: &str) -> Option<PathBuf> {
+        self.discover().into_iter()
+            .find(|log| log.label == label)
+            .map(|log| log.path)
+    }
+}
+
+// Whether a canonical path lies exactly `depth` components below a directory
+fn is_nested(canonical: &Path, dir: Option<&Path>, depth: usize) -> bool {
+    dir.and_then(|dir| fs::canonicalize(dir).ok())
+        .is_some_and(|dir| canonical.strip_prefix(dir).is_ok_and(|rest| rest.components().count() == depth))
+}
+
+// Names of the entries in a directory; an unreadable directory has none
+fn dir_entries(dir: &Path) -> Vec<String> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().filter_map(|e| e.file_name().into_string().ok()).collect(),
+        Err(e) => {
+            debug!(dir = %dir.display(), "Cannot list container logs: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// `<docker_dir>/<id>/<id>-json.log`, labelled `docker://<name>` from the container's
+// `config.v2.json`, or by its short id when that cannot be read
+fn docker_logs(dir: &Path) -> Vec<ContainerLog> {
+    dir_entries(dir).into_iter()
+        .filter_map(|id| {
+            let path = dir.join(&id).join(format!("{}-json.log", id));
+            if !path.is_file() {
+                return None;
+            }
+            let name = fs::read(dir.join(&id).join("config.v2.json")).ok()
+                .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+                .and_then(|config| config["Name"].as_str().map(|n| n.trim_start_matches('/').to_string()))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| id.chars().take(12).collect());
+            Some(ContainerLog { label: format!("docker://{}", name), path })
+        })
+        .collect()
+}
+
+// `<pods_dir>/<namespace>_<pod>_<uid>/<container>/<restart>.log`, labelled
+// `k8s://<namespace>/<pod>/<container>`; the log of the latest restart is used
+fn pod_logs(dir: &Path) -> Vec<ContainerLog> {
+    let mut logs = Vec::new();
+    for pod_dir in dir_entries(dir) {
+        // Namespaces and pod names cannot contain `_`
+        let mut parts = pod_dir.splitn(3, '_');
+        let (Some(namespace), Some(pod), Some(_uid)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+
+        for container in dir_entries(&dir.join(&pod_dir)) {
+            let container_dir = dir.join(&pod_dir).join(&container);
+            let latest = dir_entries(&container_dir).into_iter()
+                .filter_map(|file| file.strip_suffix(".log")?.parse::<u64>().ok())
+                .max();
+            if let Some(restart) = latest {
+                logs.push(ContainerLog {
+                    label: format!("k8s://{}/{}/{}", namespace, pod, container),
+                    path: container_dir.join(format!("{}.log", restart)),
+                });
+            }
+        }
+    }
+    logs
+}
@@ -0,0 +1,238 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use std::io::IsTerminal;
+use tracing::{Event, Level, Subscriber};
+use tracing::span::{Attributes, Id};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::filter::{EnvFilter, filter_fn};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// How many warnings/errors the status endpoint keeps
+const RECENT_ERRORS_CAPACITY: usize = 50;
+
+/// Seconds since the Unix epoch, used for all status timestamps
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// A warning or error captured from the tracing output
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorEntry {
+    pub time: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Ring buffer of the latest warnings and errors, filled by the tracing layer
+pub type RecentErrors = Arc<Mutex<VecDeque<ErrorEntry>>>;
+
+/// Live state of a watched source as seen by the watcher manager
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStatus {
+    pub path: String,
+    pub offset: u64,
+    pub lines: usize,
+    pub watched_since: u64,
+    pub last_update: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Shared runtime state reported by the `/status` endpoint
+#[derive(Debug, Default)]
+pub struct StatusData {
+    sources: HashMap<PathBuf, SourceStatus>,
+    clients: usize,
+    next_client_id: u64,
+}
+
+impl StatusData {
+    // Register a newly watched source
+    pub fn source_added(&mut self, path: &Path, offset: u64, lines: usize) {
+        self.sources.insert(path.to_path_buf(), SourceStatus {
+            path: path.to_string_lossy().to_string(),
+            offset,
+            lines,
+            watched_since: unix_now(),
+            last_update: None,
+            last_error: None,
+        });
+    }
+
+    // Forget a source that is no longer watched
+    pub fn source_removed(&mut self, path: &Path) {
+        self.sources.remove(path);
+    }
+
+    // Record a successful read from a source
+    pub fn source_read(&mut self, path: &Path, offset: u64, lines: usize) {
+        if let Some(source) = self.sources.get_mut(path) {
+            source.offset = offset;
+            source.lines = lines;
+            source.last_update = Some(unix_now());
+            source.last_error = None;
+        }
+    }
+
+    // Record a failed read from a source
+    pub fn source_failed(&mut self, path: &Path, error: String) {
+        if let Some(source) = self.sources.get_mut(path) {
+            source.last_error = Some(error);
+        }
+    }
+
+    // Register a connected WebSocket client, returning its id
+    pub fn client_connected(&mut self) -> u64 {
+        self.clients += 1;
+        self.next_client_id += 1;
+        self.next_client_id
+    }
+
+    // Unregister a disconnected WebSocket client
+    pub fn client_disconnected(&mut self) {
+        self.clients = self.clients.saturating_sub(1);
+    }
+}
+
+/// Snapshot returned as JSON by `/status`
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub uptime_secs: u64,
+    pub sources: Vec<SourceStatus>,
+    pub clients: usize,
+    pub channel_depth: usize,
+    pub recent_errors: Vec<ErrorEntry>,
+}
+
+/// Builds the status report from the shared state; the channel depth comes from the caller
+pub fn status_report(
+    status: &StatusData,
+    recent_errors: &RecentErrors,
+    started_at: u64,
+    channel_depth: usize,
+) -> StatusReport {
+    let mut sources: Vec<SourceStatus> = status.sources.values().cloned().collect();
+    sources.sort_by(|a, b| a.path.cmp(&b.path));
+
+    StatusReport {
+        uptime_secs: unix_now().saturating_sub(started_at),
+        sources,
+        clients: status.clients,
+        channel_depth,
+        recent_errors: recent_errors.lock().unwrap().iter().cloned().collect(),
+    }
+}
+
+/// Installs the global tracing subscriber: formatted output at the configured verbosity
+/// (`RUST_LOG` overrides it) plus a layer that always keeps recent warnings and errors.
+pub fn init_tracing(level: &str) -> RecentErrors {
+    let recent_errors: RecentErrors = Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_ERRORS_CAPACITY)));
+
+    let env_filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(level))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer()
+            .with_ansi(std::io::stdout().is_terminal())
+            .with_filter(env_filter))
+        .with(RecentErrorsLayer { errors: recent_errors.clone() }.with_filter(filter_fn(|meta| {
+            // Spans are needed for context, events only from warnings up
+            meta.is_span() || *meta.level() <= Level::WARN
+        })))
+        .init();
+
+    recent_errors
+}
+
+/// Tracing layer copying warnings and errors (with their span context) into the ring buffer
+struct RecentErrorsLayer {
+    errors: RecentErrors,
+}
+
+impl<S> Layer<S> for RecentErrorsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // Keep a plain-text copy of the span fields for error messages
+        let mut visitor = MessageVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields.join(" ")));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        // Prefix the message with the enclosing spans, e.g. "tail{path=/var/log/app.log}: ..."
+        let mut message = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                match extensions.get::<SpanFields>() {
+                    Some(SpanFields(fields)) if !fields.is_empty() => {
+                        let _ = write!(message, "{}{{{}}}: ", span.name(), fields);
+                    }
+                    _ => {
+                        let _ = write!(message, "{}: ", span.name());
+                    }
+                }
+            }
+        }
+        message.push_str(&visitor.message);
+        if !visitor.fields.is_empty() {
+            let _ = write!(message, " {}", visitor.fields.join(" "));
+        }
+
+        let metadata = event.metadata();
+        let level = if *metadata.level() == Level::ERROR { "error" } else { "warn" };
+
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == RECENT_ERRORS_CAPACITY {
+            errors.pop_front();
+        }
+        errors.push_back(ErrorEntry {
+            time: unix_now(),
+            level: level.to_string(),
+            target: metadata.target().to_string(),
+            message,
+        });
+    }
+}
+
+/// Fields of a span, stored in its extensions when the span is created
+struct SpanFields(String);
+
+/// Collects the message and any extra fields of an event or span
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: Vec<String>,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push(format!("{}={}", field.name(), value));
+        }
+    }
+}
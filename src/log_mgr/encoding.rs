@@ -0,0 +1,195 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crate::log_mgr::config::SourceEncoding;
+
+/// Encoding a source is decoded with, resolved from its configuration and byte order mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl TextEncoding {
+    /// Resolves the encoding from the configured one and the first bytes of the file.
+    /// A UTF-16 byte order mark decides the byte order for `utf-16` and for sources without a
+    /// configured encoding; an explicit encoding always wins.
+    pub fn detect(configured: Option<SourceEncoding>, head: &[u8]) -> TextEncoding {
+        let bom = if head.starts_with(&[0xFF, 0xFE]) {
+            Some(TextEncoding::Utf16Le)
+        } else if head.starts_with(&[0xFE, 0xFF]) {
+            Some(TextEncoding::Utf16Be)
+        } else {
+            None
+        };
+
+        match configured {
+            None => bom.unwrap_or(TextEncoding::Utf8),
+            Some(SourceEncoding::Utf16) => bom.unwrap_or(TextEncoding::Utf16Le),
+            Some(SourceEncoding::Utf8) => TextEncoding::Utf8,
+            Some(SourceEncoding::Latin1) => TextEncoding::Latin1,
+            Some(SourceEncoding::Utf16Le) => TextEncoding::Utf16Le,
+            Some(SourceEncoding::Utf16Be) => TextEncoding::Utf16Be,
+        }
+    }
+
+    /// Resolves the encoding of a file by looking at its first bytes
+    pub fn detect_file(configured: Option<SourceEncoding>, path: &Path) -> TextEncoding {
+        let mut head = [0u8; 2];
+        let read = File::open(path).and_then(|mut f| f.read(&mut head)).unwrap_or(0);
+        TextEncoding::detect(configured, &head[..read])
+    }
+
+    /// Length of the byte order mark at the start of `bytes`, 0 if there is none
+    pub fn bom_len(self, bytes: &[u8]) -> usize {
+        let bom: &[u8] = match self {
+            TextEncoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            TextEncoding::Latin1 => &[],
+            TextEncoding::Utf16Le => &[0xFF, 0xFE],
+            TextEncoding::Utf16Be => &[0xFE, 0xFF],
+        };
+        if bytes.starts_with(bom) { bom.len() } else { 0 }
+    }
+
+    /// Finds the first line terminator, returning where the line ends and the terminator length
+    pub fn find_newline(self, bytes: &[u8]) -> Option<(usize, usize)> {
+        let unit: [u8; 2] = match self {
+            TextEncoding::Utf8 | TextEncoding::Latin1 => {
+                return bytes.iter().position(|&b| b == b'\n').map(|pos| (pos, 1));
+            }
+            TextEncoding::Utf16Le => [b'\n', 0],
+            TextEncoding::Utf16Be => [0, b'\n'],
+        };
+        bytes.chunks_exact(2).position(|c| c == unit).map(|idx| (idx * 2, 2))
+    }
+
+    /// Decodes bytes into text. Nothing is rejected: invalid sequences and control characters
+    /// other than tab and line breaks are shown as escapes such as `\xFF`.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let mut out = String::with_capacity(bytes.len());
+        match self {
+            TextEncoding::Utf8 => {
+                for chunk in bytes.utf8_chunks() {
+                    push_escaped(&mut out, chunk.valid());
+                    for b in chunk.invalid() {
+                        let _ = write!(out, "\\x{:02X}", b);
+                    }
+                }
+            }
+            TextEncoding::Latin1 => {
+                for &b in bytes {
+                    push_char(&mut out, char::from(b));
+                }
+            }
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+                let units = bytes.chunks_exact(2).map(|c| match self {
+                    TextEncoding::Utf16Le => u16::from_le_bytes([c[0], c[1]]),
+                    _ => u16::from_be_bytes([c[0], c[1]]),
+                });
+                for decoded in char::decode_utf16(units) {
+                    match decoded {
+                        Ok(c) => push_char(&mut out, c),
+                        Err(e) => {
+                            let _ = write!(out, "\\u{{{:04X}}}", e.unpaired_surrogate());
+                        }
+                    }
+                }
+                // A dangling byte of an incomplete code unit
+                if let [b] = bytes.chunks_exact(2).remainder() {
+                    let _ = write!(out, "\\x{:02X}", b);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Decodes a complete file, skipping its byte order mark
+pub fn decode_file(configured: Option<SourceEncoding>, bytes: &[u8]) -> String {
+    let encoding = TextEncoding::detect(configured, bytes);
+    encoding.decode(&bytes[encoding.bom_len(bytes)..])
+}
+
+// Appends valid text, escaping control characters that would garble the display
+fn push_escaped(out: &mut String, text: &str) {
+    if text.bytes().any(is_escaped_byte) {
+        text.chars().for_each(|c| push_char(out, c));
+    } else {
+        out.push_str(text);
+    }
+}
+
+fn push_char(out: &mut String, c: char) {
+    if c.is_ascii() && is_escaped_byte(c as u8) {
+        let _ = write!(out, "\\x{:02X}", c as u32);
+    } else {
+        out.push(c);
+    }
+}
+
+fn is_escaped_byte(b: u8) -> bool {
+    (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r')) || b == 0x7F
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn utf16be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    #[test]
+    fn byte_order_mark_decides_unless_configured() {
+        assert_eq!(TextEncoding::detect(None, &[0xFF, 0xFE, b'a', 0]), TextEncoding::Utf16Le);
+        assert_eq!(TextEncoding::detect(None, &[0xFE, 0xFF]), TextEncoding::Utf16Be);
+        assert_eq!(TextEncoding::detect(None, b"plain"), TextEncoding::Utf8);
+        assert_eq!(TextEncoding::detect(Some(SourceEncoding::Utf16), &[0xFE, 0xFF]), TextEncoding::Utf16Be);
+        assert_eq!(TextEncoding::detect(Some(SourceEncoding::Utf16), b"ab"), TextEncoding::Utf16Le);
+        assert_eq!(TextEncoding::detect(Some(SourceEncoding::Utf8), &[0xFF, 0xFE]), TextEncoding::Utf8);
+        assert_eq!(TextEncoding::detect(None, &[0xFF]), TextEncoding::Utf8);
+    }
+
+    #[test]
+    fn invalid_and_control_bytes_are_escaped() {
+        assert_eq!(TextEncoding::Utf8.decode(b"ok \xFF\xFE end"), "ok \\xFF\\xFE end");
+        assert_eq!(TextEncoding::Utf8.decode(b"\x1b[31mred\x7f\ttab"), "\\x1B[31mred\\x7F\ttab");
+        assert_eq!(TextEncoding::Utf8.decode("caf\u{e9}".as_bytes()), "café");
+        // A multi-byte character cut short
+        assert_eq!(TextEncoding::Utf8.decode(&"é".as_bytes()[..1]), "\\xC3");
+        assert_eq!(TextEncoding::Latin1.decode(b"caf\xE9\x01"), "café\\x01");
+    }
+
+    #[test]
+    fn utf16() {
+        assert_eq!(TextEncoding::Utf16Le.decode(&utf16le("héllo 😀")), "héllo 😀");
+        assert_eq!(TextEncoding::Utf16Be.decode(&utf16be("héllo 😀")), "héllo 😀");
+        // An unpaired surrogate and a dangling byte
+        assert_eq!(TextEncoding::Utf16Le.decode(&[0x3D, 0xD8, b'a', 0]), "\\u{D83D}a");
+        assert_eq!(TextEncoding::Utf16Le.decode(&[b'a', 0, b'b']), "a\\x62");
+    }
+
+    #[test]
+    fn utf16_newlines_are_whole_code_units() {
+        assert_eq!(TextEncoding::Utf16Le.find_newline(&utf16le("ab\ncd")), Some((4, 2)));
+        assert_eq!(TextEncoding::Utf16Be.find_newline(&utf16be("ab\ncd")), Some((4, 2)));
+        // U+0A05 U+4E00 in little endian contain the bytes 0A 00 across the two code units
+        assert_eq!(TextEncoding::Utf16Le.find_newline(&utf16le("\u{0A05}\u{4E00}")), None);
+        assert_eq!(TextEncoding::Utf8.find_newline(b"ab\r\ncd"), Some((3, 1)));
+    }
+
+    #[test]
+    fn decode_file_skips_the_byte_order_mark() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(utf16le("line\n"));
+        assert_eq!(decode_file(None, &bytes), "line\n");
+        assert_eq!(decode_file(None, b"\xEF\xBB\xBFline"), "line");
+    }
+}
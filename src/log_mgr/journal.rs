@@ -0,0 +1,249 @@
+use serde_json::Value;
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::syslog::SEVERITIES;
+use crate::log_mgr::timestamp;
+
+/// Start of a journal export (`journalctl -o export`) dump; every entry begins with its cursor
+const EXPORT_START: &[u8] = b"__CURSOR=";
+
+/// One journal entry: its fields in the order they were written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalEntry {
+    fields: Vec<(String, String)>,
+}
+
+impl JournalEntry {
+    /// Adds a field; repeated fields keep their first value
+    pub fn push(&mut self, name: &str, value: String) {
+        if self.get(name).is_none() {
+            self.fields.push((name.to_string(), value));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// The record shown for this entry: `timestamp priority unit[pid]: message`.
+    /// Newlines in the message are escaped so an entry stays one record.
+    pub fn record(&self) -> String {
+        let time = self.time();
+        let mut record = format!("{} {} {}", time.as_deref().unwrap_or("-"), self.priority().unwrap_or("-"), self.unit().unwrap_or("-"));
+        if let Some(pid) = self.get("_PID") {
+            record.push_str(&format!("[{}]", pid));
+        }
+        record.push_str(": ");
+        record.push_str(&self.message());
+        record
+    }
+
+    /// The record of this entry with the fields it is made of: `time`, `priority` (its name),
+    /// `unit`, `pid` and `message`, each when the entry has it
+    pub fn log_record(&self, line_number: usize) -> LogRecord {
+        let mut record = LogRecord::new(line_number, self.record());
+        let fields = [
+            ("time", self.time()),
+            ("priority", self.priority().map(String::from)),
+            ("unit", self.unit().map(String::from)),
+            ("pid", self.get("_PID").map(String::from)),
+            ("message", self.get("MESSAGE").map(|_| self.message())),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                record = record.with_field(name, value);
+            }
+        }
+        record
+    }
+
+    fn time(&self) -> Option<String> {
+        self.get("__REALTIME_TIMESTAMP")
+            .and_then(|micros| micros.parse::<i64>().ok())
+            .map(timestamp::format_micros)
+    }
+
+    fn priority(&self) -> Option<&'static str> {
+        self.get("PRIORITY")
+            .and_then(|p| p.parse::<usize>().ok())
+            .and_then(|p| SEVERITIES.get(p))
+            .copied()
+    }
+
+    fn unit(&self) -> Option<&str> {
+        self.get("_SYSTEMD_UNIT")
+            .or_else(|| self.get("SYSLOG_IDENTIFIER"))
+            .or_else(|| self.get("_COMM"))
+    }
+
+    fn message(&self) -> String {
+        self.get("MESSAGE").unwrap_or_default().trim_end_matches('\n').replace('\n', "\\n")
+    }
+}
+
+/// Whether a file starts like a journal export dump
+pub fn is_export(head: &[u8]) -> bool {
+    head.starts_with(EXPORT_START)
+}
+
+/// Whether a line looks like one entry of `journalctl -o json`
+pub fn is_json_entry(line: &str) -> bool {
+    line.starts_with('{') && parse_json_entry(line).is_some_and(|entry| {
+        entry.get("__CURSOR").is_some() || (entry.get("MESSAGE").is_some() && entry.get("__REALTIME_TIMESTAMP").is_some())
+    })
+}
+
+/// Parses one line of `journalctl -o json`. Binary values are arrays of bytes; fields that
+/// occur more than once are arrays of values, of which the first is kept.
+pub fn parse_json_entry(line: &str) -> Option<JournalEntry> {
+    let Value::Object(object) = serde_json::from_str(line).ok()? else {
+        return None;
+    };
+    let mut entry = JournalEntry::default();
+    for (name, value) in object {
+        if let Some(value) = json_value(&value) {
+            entry.push(&name, value);
+        }
+    }
+    Some(entry)
+}
+
+// A field value: a string, a byte array, or the first of several values
+fn json_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Array(items) if items.iter().all(Value::is_u64) => {
+            let bytes: Vec<u8> = items.iter().filter_map(Value::as_u64).map(|b| b as u8).collect();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        Value::Array(items) => items.first().and_then(json_value),
+        _ => None,
+    }
+}
+
+/// Parses one `NAME=value` line of the export format; other lines yield `None`
+pub fn parse_export_field(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once('=')?;
+    let valid = !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_');
+    valid.then_some((name, value))
+}
+
+/// Rewrites binary fields of an export dump (`NAME\n<u64 LE length><data>\n`) as text fields
+/// with newlines escaped, so the dump can be read line by line like the rest of the format.
+pub fn export_to_text(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        let line = &rest[..end];
+        rest = &rest[(end + 1).min(rest.len())..];
+
+        // A name without `=` is followed by the length and data of a binary value
+        let binary = !line.is_empty() && !line.contains(&b'=') && rest.len() >= 8;
+        if !binary {
+            out.extend_from_slice(line);
+            out.push(b'\n');
+            continue;
+        }
+        let len = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let len = usize::try_from(len).unwrap_or(usize::MAX).min(rest.len() - 8);
+        let data = &rest[8..8 + len];
+        rest = &rest[(8 + len + 1).min(rest.len())..];
+
+        out.extend_from_slice(line);
+        out.push(b'=');
+        for &b in data {
+            match b {
+                b'\n' => out.extend_from_slice(b"\\n"),
+                _ => out.push(b),
+            }
+        }
+        out.push(b'\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_entries() {
+        let line = r#"{"__CURSOR":"s=1","__REALTIME_TIMESTAMP":"1714557600123456","PRIORITY":"3","_SYSTEMD_UNIT":"nginx.service","_PID":"42","MESSAGE":[104,105,10,116,104,101,114,101,10]}"#;
+        assert!(is_json_entry(line));
+        let entry = parse_json_entry(line).unwrap();
+        assert_eq!(entry.get("MESSAGE"), Some("hi\nthere\n"));
+        assert_eq!(entry.record(), "2024-05-01T10:00:00.123456Z err nginx.service[42]: hi\\nthere");
+
+        // Repeated fields are arrays of values, of which the first counts
+        let repeated = parse_json_entry(r#"{"MESSAGE":["first","second"],"_PID":7}"#).unwrap();
+        assert_eq!((repeated.get("MESSAGE"), repeated.get("_PID")), (Some("first"), Some("7")));
+        assert_eq!(repeated.record(), "- - -[7]: first");
+
+        assert!(!is_json_entry(r#"{"level":"info","msg":"not a journal"}"#));
+        assert!(!is_json_entry("[1, 2]"));
+        assert!(parse_json_entry("[1, 2]").is_none());
+    }
+
+    #[test]
+    fn unit_falls_back_to_identifier_and_command() {
+        let mut entry = JournalEntry::default();
+        entry.push("_COMM", String::from("bash"));
+        entry.push("MESSAGE", String::from("x"));
+        assert_eq!(entry.record(), "- - bash: x");
+        entry.push("SYSLOG_IDENTIFIER", String::from("sshd"));
+        entry.push("MESSAGE", String::from("ignored"));
+        assert_eq!(entry.record(), "- - sshd: x");
+    }
+
+    #[test]
+    fn record_fields() {
+        let mut entry = JournalEntry::default();
+        entry.push("__REALTIME_TIMESTAMP", String::from("0"));
+        entry.push("PRIORITY", String::from("6"));
+        entry.push("_SYSTEMD_UNIT", String::from("cron.service"));
+        entry.push("MESSAGE", String::from("a\nb\n"));
+        let record = entry.log_record(5);
+        assert_eq!(record.line_number, 5);
+        let fields: Vec<(&str, &str)> = record.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(fields, [
+            ("message", "a\\nb"),
+            ("priority", "info"),
+            ("time", "1970-01-01T00:00:00.000000Z"),
+            ("unit", "cron.service"),
+        ]);
+        // An out of range priority is left out
+        let mut odd = JournalEntry::default();
+        odd.push("PRIORITY", String::from("9"));
+        assert!(odd.log_record(1).fields.is_empty());
+    }
+
+    #[test]
+    fn export_fields() {
+        assert!(is_export(b"__CURSOR=s=1\n"));
+        assert!(!is_export(b"MESSAGE=x\n"));
+        assert_eq!(parse_export_field("MESSAGE=a=b"), Some(("MESSAGE", "a=b")));
+        assert_eq!(parse_export_field("_PID=1"), Some(("_PID", "1")));
+        assert_eq!(parse_export_field("message=x"), None);
+        assert_eq!(parse_export_field("=x"), None);
+        assert_eq!(parse_export_field("no field"), None);
+    }
+
+    #[test]
+    fn binary_export_fields_become_text() {
+        let mut dump = b"__CURSOR=c\nMESSAGE\n".to_vec();
+        dump.extend(5u64.to_le_bytes());
+        dump.extend(b"a\nb=c\n_PID=1\n\n");
+        assert_eq!(export_to_text(&dump), b"__CURSOR=c\nMESSAGE=a\\nb=c\n_PID=1\n\n");
+
+        // A length beyond the data takes what is there
+        let mut short = b"MESSAGE\n".to_vec();
+        short.extend(100u64.to_le_bytes());
+        short.extend(b"abc");
+        assert_eq!(export_to_text(&short), b"MESSAGE=abc\n");
+    }
+}
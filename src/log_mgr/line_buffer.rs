@@ -0,0 +1,182 @@
+use std::time::{Duration, Instant};
+use crate::log_mgr::encoding::TextEncoding;
+use crate::log_mgr::log_record::LogRecord;
+
+/// A fragment longer than this is emitted as a line even without its newline
+const MAX_PARTIAL_LINE: usize = 1024 * 1024;
+
+/// Splits bytes read from a file into complete lines, holding back a trailing fragment
+/// until its newline arrives or the flush timeout passes
+#[derive(Debug)]
+pub struct LineBuffer {
+    partial: Vec<u8>,
+    line_number: usize,
+    last_data_at: Instant,
+    timeout: Duration,
+    encoding: TextEncoding,
+    // The last line was flushed without its newline, so a newline arriving next only terminates it
+    flushed_unterminated: bool,
+}
+
+impl LineBuffer {
+    /// Creates a buffer that continues numbering after `line_number`
+    pub fn new(line_number: usize, timeout: Duration, encoding: TextEncoding) -> LineBuffer {
+        LineBuffer {
+            partial: Vec::new(),
+            line_number,
+            last_data_at: Instant::now(),
+            timeout,
+            encoding,
+            flushed_unterminated: false,
+        }
+    }
+
+    /// Number of the last line emitted
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Adds freshly read bytes and returns the lines they completed
+    pub fn push(&mut self, data: &[u8]) -> Vec<LogRecord> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        self.partial.extend_from_slice(data);
+        self.last_data_at = Instant::now();
+
+        // Drop the newline (or CR LF) that terminates a line already flushed by timeout
+        if std::mem::take(&mut self.flushed_unterminated)
+            && let Some((end, len)) = self.encoding.find_newline(&self.partial)
+            && self.encoding.decode(&self.partial[..end]).trim_end_matches('\r').is_empty() {
+            self.partial.drain(..end + len);
+        }
+
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while let Some((end, len)) = self.encoding.find_newline(&self.partial[start..]) {
+            lines.push(self.decode_line(start, start + end));
+            start += end + len;
+        }
+        self.partial.drain(..start);
+
+        if self.partial.len() >= MAX_PARTIAL_LINE {
+            lines.extend(self.flush());
+        }
+        lines
+    }
+
+    /// Emits the held-back fragment as a line, if there is one
+    pub fn flush(&mut self) -> Option<LogRecord> {
+        if self.partial.is_empty() {
+            return None;
+        }
+        self.flushed_unterminated = true;
+        let record = self.decode_line(0, self.partial.len());
+        self.partial.clear();
+        Some(record)
+    }
+
+    /// When the held-back fragment should be flushed if its newline does not arrive
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.partial.is_empty() {
+            None
+        } else {
+            Some(self.last_data_at + self.timeout)
+        }
+    }
+
+    /// Drops any fragment and restarts numbering, e.g. after the file was truncated
+    pub fn reset(&mut self) {
+        self.partial.clear();
+        self.line_number = 0;
+        self.flushed_unterminated = false;
+    }
+
+    fn decode_line(&mut self, start: usize, end: usize) -> LogRecord {
+        let mut bytes = &self.partial[start..end];
+        if self.line_number == 0 {
+            bytes = &bytes[self.encoding.bom_len(bytes)..];
+        }
+        let mut text = self.encoding.decode(bytes);
+        if text.ends_with('\r') {
+            text.pop();
+        }
+        self.line_number += 1;
+        LogRecord::new(self.line_number, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer() -> LineBuffer {
+        LineBuffer::new(0, Duration::from_secs(1), TextEncoding::Utf8)
+    }
+
+    fn texts(records: Vec<LogRecord>) -> Vec<(usize, String)> {
+        records.into_iter().map(|record| (record.line_number, record.text)).collect()
+    }
+
+    #[test]
+    fn lines_split_across_reads() {
+        let mut lines = buffer();
+        assert!(lines.push(b"ab").is_empty());
+        assert!(lines.deadline().is_some());
+        assert_eq!(texts(lines.push(b"c\r\nde")), [(1, String::from("abc"))]);
+        assert_eq!(texts(lines.push(b"\n\nf\n")), [(2, String::from("de")), (3, String::new()), (4, String::from("f"))]);
+        assert!(lines.deadline().is_none());
+        assert_eq!(lines.line_number(), 4);
+    }
+
+    #[test]
+    fn newline_after_a_flushed_fragment_only_ends_it() {
+        let mut lines = buffer();
+        lines.push(b"partial");
+        assert_eq!(lines.flush().map(|record| record.text), Some(String::from("partial")));
+        assert!(lines.flush().is_none());
+        assert!(lines.push(b"\r\n").is_empty());
+        assert_eq!(texts(lines.push(b"next\n")), [(2, String::from("next"))]);
+
+        // Data that does not start with the newline is a line of its own
+        lines.push(b"again");
+        lines.flush();
+        assert_eq!(texts(lines.push(b"more\n")), [(4, String::from("more"))]);
+    }
+
+    #[test]
+    fn byte_order_mark_only_on_the_first_line() {
+        let mut lines = buffer();
+        assert_eq!(texts(lines.push(b"\xEF\xBB\xBFone\n\xEF\xBB\xBFtwo\n")), [(1, String::from("one")), (2, String::from("\u{FEFF}two"))]);
+        let mut continued = LineBuffer::new(10, Duration::from_secs(1), TextEncoding::Utf8);
+        assert_eq!(texts(continued.push(b"\xEF\xBB\xBFx\n")), [(11, String::from("\u{FEFF}x"))]);
+    }
+
+    #[test]
+    fn utf16_lines() {
+        let mut lines = LineBuffer::new(0, Duration::from_secs(1), TextEncoding::Utf16Le);
+        let bytes: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain("hi\r\nyo\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        // Split in the middle of a code unit
+        assert_eq!(texts(lines.push(&bytes[..11])), [(1, String::from("hi"))]);
+        assert_eq!(texts(lines.push(&bytes[11..])), [(2, String::from("yo"))]);
+    }
+
+    #[test]
+    fn overlong_fragment_is_emitted() {
+        let mut lines = buffer();
+        let records = lines.push(&vec![b'x'; MAX_PARTIAL_LINE]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].text.len(), MAX_PARTIAL_LINE);
+    }
+
+    #[test]
+    fn reset_restarts_numbering() {
+        let mut lines = buffer();
+        lines.push(b"a\nb");
+        lines.reset();
+        assert!(lines.flush().is_none());
+        assert_eq!(texts(lines.push(b"c\n")), [(1, String::from("c"))]);
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use crate::log_mgr::config::LiveConfig;
+use crate::log_mgr::diagnostics::StatusData;
+use crate::log_mgr::encoding::TextEncoding;
+use crate::log_mgr::log_context_data::LogContextData;
+use crate::log_mgr::log_record::{line_records, LogRecord};
+use crate::log_mgr::rust_server::WsEventTx;
+
+/// Name prefixes of live sources; such names are not file paths and bypass the path sandbox
+pub const LIVE_SCHEMES: [&str; 4] = ["syslog://", "command://", "stdin://", "fifo://"];
+
+/// Prefix of the syslog sources, whose names the senders choose
+const SYSLOG_SCHEME: &str = "syslog://";
+
+/// Size of the chunks spool files are read in
+const SPOOL_CHUNK: usize = 64 * 1024;
+
+/// Whether a client-supplied name refers to a live source rather than a file
+pub fn is_live_name(name: &str) -> bool {
+    LIVE_SCHEMES.iter().any(|scheme| name.starts_with(scheme))
+}
+
+/// Records of one live source seen so far
+#[derive(Debug)]
+struct LiveSource {
+    line_number: usize,
+    // Sequence number of the latest record among all sources, to find the one quiet longest
+    last_record: u64,
+    spool: Option<(PathBuf, File)>,
+    spooled_bytes: u64,
+    // Latest records, kept when there is no spool file to read them back from
+    history: VecDeque<LogRecord>,
+}
+
+/// Registry of sources that are not files (syslog senders, command output, stdin, FIFOs).
+///
+/// Their records go through the same filter, notification and broadcast pipeline as tailed
+/// files, named like `syslog://host/app`. With a spool directory every record is also appended
+/// to a file there, which is what searching and replaying a live source reads; otherwise the
+/// latest records are kept in memory. A spool file that reaches `max_spool_bytes` is renamed to
+/// `<file>.1` and started over, so each source keeps at most two. Syslog senders choose their source names, so at most
+/// `max_syslog_sources` of them are kept: a new one replaces the one quiet longest.
+pub struct LiveSources {
+    log_tx: broadcast::Sender<WsEventTx>,
+    context: Arc<Mutex<LogContextData>>,
+    status: Arc<Mutex<StatusData>>,
+    spool_dir: Option<PathBuf>,
+    history_lines: usize,
+    max_spool_bytes: u64,
+    max_syslog_sources: usize,
+    sources: Mutex<HashMap<String, LiveSource>>,
+    records: AtomicU64,
+}
+
+impl LiveSources {
+    pub fn new(
+        log_tx: broadcast::Sender<WsEventTx>,
+        context: Arc<Mutex<LogContextData>>,
+        status: Arc<Mutex<StatusData>>,
+        config: &LiveConfig,
+    ) -> LiveSources {
+        let spool_dir = config.spool_dir.clone().filter(|dir| match fs::create_dir_all(dir) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(dir = %dir.display(), "Cannot create spool directory, live sources are not spooled: {}", e);
+                false
+            }
+        });
+
+        LiveSources {
+            log_tx,
+            context,
+            status,
+            spool_dir,
+            history_lines: config.history_lines,
+            max_spool_bytes: config.max_spool_bytes.max(1),
+            max_syslog_sources: config.max_syslog_sources.max(1),
+            sources: Mutex::new(HashMap::new()),
+            records: AtomicU64::new(0),
+        }
+    }
+
+    /// Feeds one record of a live source through filters and notifications to all clients
+    pub fn push(&self, name: &str, text: &str) {
+        // A live record is always a single line, also in the spool file
+        let text = text.trim_end_matches(['\r', '\n']).replace('\n', "\\n");
+
+        let mut evicted = None;
+        let record = {
+            let mut sources = self.sources.lock().unwrap();
+            if !sources.contains_key(name) && name.starts_with(SYSLOG_SCHEME) {
+                evicted = self.evict_syslog_source(&mut sources);
+            }
+            let source = sources.entry(name.to_string()).or_insert_with(|| self.open_source(name));
+
+            source.last_record = self.records.fetch_add(1, Ordering::Relaxed);
+            source.line_number += 1;
+            if let Some((path, file)) = source.spool.as_mut() {
+                let line = format!("{}\n", text);
+                if source.spooled_bytes > 0 && source.spooled_bytes + line.len() as u64 > self.max_spool_bytes {
+                    match rotate_spool(path) {
+                        Ok(fresh) => {
+                            *file = fresh;
+                            source.spooled_bytes = 0;
+                        }
+                        Err(e) => warn!(source = %name, spool = %path.display(), "Cannot rotate spool file: {}", e),
+                    }
+                }
+                match file.write_all(line.as_bytes()) {
+                    Ok(()) => source.spooled_bytes += line.len() as u64,
+                    Err(e) => {
+                        warn!(source = %name, spool = %path.display(), "Failed to spool: {}", e);
+                        self.status.lock().unwrap().source_failed(Path::new(name), e.to_string());
+                    }
+                }
+            }
+            self.status.lock().unwrap().source_read(Path::new(name), source.spooled_bytes, source.line_number);
+
+            let record = LogRecord::new(source.line_number, text);
+            if source.spool.is_none() && self.history_lines > 0 {
+                if source.history.len() == self.history_lines {
+                    source.history.pop_front();
+                }
+                source.history.push_back(record.clone());
+            }
+            record
+        };
+
+        let mut ctx = self.context.lock().unwrap();
+        if let Some(evicted) = evicted {
+            ctx.source_stopped(Path::new(&evicted));
+        }
+        ctx.on_event_modified(Path::new(name), &record, &self.log_tx);
+    }
+
+    /// Shows a problem of a live source (e.g. its command exited) as its last error in the status
+    pub fn source_failed(&self, name: &str, error: &str) {
+        self.status.lock().unwrap().source_failed(Path::new(name), error.to_string());
+    }
+
+    /// Names of the live sources seen so far
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.sources.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The spool file of a live source, if spooling is enabled
+    pub fn spool_path(&self, name: &str) -> Option<PathBuf> {
+        self.spool_dir.as_ref().map(|dir| dir.join(spool_file_name(name)))
+    }
+
+    /// Records of a live source available for search and replay: the latest `history_lines`
+    /// records, read from the end of its spool files or kept in memory
+    pub fn history(&self, name: &str) -> Vec<LogRecord> {
+        let Some(spool) = self.spool_path(name) else {
+            return self.sources.lock().unwrap()
+                .get(name)
+                .map(|source| source.history.iter().cloned().collect())
+                .unwrap_or_default();
+        };
+
+        // The current spool file, then the one before it if that is not enough
+        let mut bytes = Vec::new();
+        for file in [spool.clone(), rotated_spool(&spool)] {
+            let wanted = self.history_lines.saturating_sub(count_lines(&bytes));
+            if wanted == 0 {
+                break;
+            }
+            match read_tail(&file, wanted) {
+                Ok(mut tail) => {
+                    tail.append(&mut bytes);
+                    bytes = tail;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!(source = %name, spool = %file.display(), "Failed to read spool: {}", e),
+            }
+        }
+
+        // Number the records like the live ones: the last is the latest line of the source
+        let records = line_records(&TextEncoding::Utf8.decode(&bytes));
+        let total = self.sources.lock().unwrap()
+            .get(name)
+            .map(|source| source.line_number)
+            .unwrap_or_else(|| spooled_lines(&spool));
+        let first = total.saturating_sub(records.len());
+        records.into_iter()
+            .map(|record| LogRecord::new(first + record.line_number, record.text))
+            .collect()
+    }
+
+    /// Sends the history of a live source to clients, returns how many records were sent
+    pub fn send_history(&self, name: &str) -> usize {
+        let mut sent = 0;
+        let records = self.history(name);
+        for chunk in records.chunks(200) {
+            sent += chunk.len();
+            let _ = self.log_tx.send(WsEventTx::log_batch(name, chunk));
+        }
+        sent
+    }
+
+    // Makes room for a new syslog source by forgetting the one quiet longest, spool file included;
+    // returns its name
+    fn evict_syslog_source(&self, sources: &mut HashMap<String, LiveSource>) -> Option<String> {
+        let syslog = sources.keys().filter(|name| name.starts_with(SYSLOG_SCHEME)).count();
+        if syslog < self.max_syslog_sources {
+            return None;
+        }
+        let name = sources.iter()
+            .filter(|(name, _)| name.starts_with(SYSLOG_SCHEME))
+            .min_by_key(|(_, source)| source.last_record)
+            .map(|(name, _)| name.clone())?;
+        let source = sources.remove(&name)?;
+        if let Some((path, file)) = source.spool {
+            drop(file);
+            for path in [rotated_spool(&path), path] {
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        warn!(source = %name, spool = %path.display(), "Cannot remove spool file: {}", e);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        warn!(source = %name, limit = self.max_syslog_sources, "Too many syslog sources, forgot the one quiet longest");
+        self.status.lock().unwrap().source_removed(Path::new(&name));
+        Some(name)
+    }
+
+    // Creates the state of a new live source, continuing the numbering of existing spool files
+    fn open_source(&self, name: &str) -> LiveSource {
+        let mut source = LiveSource { line_number: 0, last_record: 0, spool: None, spooled_bytes: 0, history: VecDeque::new() };
+
+        if let Some(path) = self.spool_path(name) {
+            source.line_number = spooled_lines(&path);
+            source.spooled_bytes = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => source.spool = Some((path, file)),
+                Err(e) => warn!(source = %name, spool = %path.display(), "Cannot open spool file: {}", e),
+            }
+        }
+
+        info!(source = %name, "New live source");
+        self.status.lock().unwrap().source_added(Path::new(name), source.spooled_bytes, source.line_number);
+        source
+    }
+}
+
+// The previous spool file of a source, renamed when the current one got too large
+fn rotated_spool(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+// Renames a full spool file to its rotated name and opens a new one in its place
+fn rotate_spool(path: &Path) -> io::Result<File> {
+    fs::rename(path, rotated_spool(path))?;
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn count_lines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&b| b == b'\n').count()
+}
+
+// Lines in the spool files of a source, counted in chunks rather than read at once
+fn spooled_lines(path: &Path) -> usize {
+    let mut lines = 0;
+    let mut buf = vec![0u8; SPOOL_CHUNK];
+    for path in [rotated_spool(path), path.to_path_buf()] {
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        let mut reader = BufReader::new(file);
+        while let Ok(read) = reader.read(&mut buf) && read > 0 {
+            lines += count_lines(&buf[..read]);
+        }
+    }
+    lines
+}
+
+// The last `lines` lines of a file, read backwards in chunks until enough have been found
+fn read_tail(path: &Path, lines: usize) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut start = file.metadata()?.len();
+    let mut tail = Vec::new();
+    let mut newlines = 0;
+    // The newline before the first wanted line is needed to know that line is complete
+    while start > 0 && newlines <= lines {
+        let size = start.min(SPOOL_CHUNK as u64);
+        start -= size;
+        let mut chunk = vec![0u8; size as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        newlines += count_lines(&chunk);
+        chunk.append(&mut tail);
+        tail = chunk;
+    }
+
+    // Drop the lines before the wanted ones
+    let skip = newlines.saturating_sub(lines);
+    if skip == 0 {
+        return Ok(tail);
+    }
+    let cut = tail.iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(skip - 1)
+        .map_or(0, |(idx, _)| idx + 1);
+    Ok(tail.split_off(cut))
+}
+
+// File name of a spool file: the source name with every byte but letters, digits, `.`, `-` and
+// `_` percent-encoded, so different names never share a file
+fn spool_file_name(name: &str) -> String {
+    let mut file = String::with_capacity(name.len() + 4);
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b".-_".contains(&byte) {
+            file.push(byte as char);
+        } else {
+            file.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    file.push_str(".log");
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn live_sources(spool_dir: &Path, history_lines: usize, max_spool_bytes: u64) -> LiveSources {
+        let (log_tx, _) = broadcast::channel(16);
+        let config = LiveConfig {
+            spool_dir: Some(spool_dir.to_path_buf()),
+            history_lines,
+            max_spool_bytes,
+            ..LiveConfig::default()
+        };
+        LiveSources::new(log_tx, Arc::default(), Arc::default(), &config)
+    }
+
+    fn numbered(records: &[LogRecord]) -> Vec<String> {
+        records.iter().map(LogRecord::numbered).collect()
+    }
+
+    #[test]
+    fn tails_are_read_from_the_end() {
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-tail.log", std::process::id()));
+        let text: String = (1..=20_000).map(|n| format!("line {}\n", n)).collect();
+        fs::write(&path, &text).unwrap();
+
+        assert_eq!(read_tail(&path, 2).unwrap(), b"line 19999\nline 20000\n");
+        assert_eq!(count_lines(&read_tail(&path, 15_000).unwrap()), 15_000);
+        assert_eq!(read_tail(&path, 30_000).unwrap(), text.as_bytes());
+        assert!(read_tail(&path, 0).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn full_spool_files_are_rotated() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-spool-rotation", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let name = "command://app/stdout";
+        {
+            // Each record takes 9 bytes: three fit into a spool file
+            let live = live_sources(&dir, 4, 27);
+            for n in 1..=8 {
+                live.push(name, &format!("record {}", n));
+            }
+            let spool = live.spool_path(name).unwrap();
+            assert_eq!(fs::read_to_string(&spool).unwrap(), "record 7\nrecord 8\n");
+            assert_eq!(fs::read_to_string(rotated_spool(&spool)).unwrap(), "record 4\nrecord 5\nrecord 6\n");
+            assert_eq!(numbered(&live.history(name)), ["5: record 5", "6: record 6", "7: record 7", "8: record 8"]);
+        }
+
+        // After a restart the numbering goes on from the lines still spooled
+        let live = live_sources(&dir, 10, 27);
+        assert_eq!(numbered(&live.history(name)), ["1: record 4", "2: record 5", "3: record 6", "4: record 7", "5: record 8"]);
+        live.push(name, "record 9");
+        assert_eq!(live.history(name).last().map(LogRecord::numbered).as_deref(), Some("6: record 9"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicted_syslog_sources_lose_their_spool_files() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-spool-eviction", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (log_tx, _) = broadcast::channel(16);
+        let config = LiveConfig { spool_dir: Some(dir.clone()), max_spool_bytes: 10, max_syslog_sources: 1, ..LiveConfig::default() };
+        let live = LiveSources::new(log_tx, Arc::default(), Arc::default(), &config);
+        live.push("syslog://web/nginx", "first record");
+        live.push("syslog://web/nginx", "second record");
+        let spool = live.spool_path("syslog://web/nginx").unwrap();
+        assert!(spool.exists() && rotated_spool(&spool).exists());
+
+        live.push("syslog://db/postgres", "other sender");
+        assert!(!spool.exists() && !rotated_spool(&spool).exists());
+        assert_eq!(live.names(), ["syslog://db/postgres"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
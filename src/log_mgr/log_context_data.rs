@@ -1,64 +1,505 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Instant;
 use tokio::sync::broadcast;
-use crate::log_mgr::LogContextData;
+use tracing::{debug, info, warn};
+use crate::log_mgr::alert_history::{AlertHistory, AlertState};
+use crate::log_mgr::anomaly::PatternAlerts;
+use crate::log_mgr::log_monitoring::WatchCommand;
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::metrics::LogMetrics;
+use crate::log_mgr::notification::{Heartbeats, Notification, NotificationRule, Notifier, Trigger, WindowAlerts};
+use crate::log_mgr::notification_sink::NotificationSinks;
 use crate::log_mgr::rust_server::WsEventTx;
+use crate::log_mgr::silence::Silences;
+use crate::log_mgr::timeline::MERGED_SCHEME;
+use crate::log_mgr::templates::{LiveTemplates, TemplateFilter, TemplateMiner};
+use crate::log_mgr::timestamp;
+use crate::log_mgr::volume::{LiveVolumes, VolumeQuery};
 
+/// Filters and notifications per source; the engine applied to every tailed line.
+///
+/// Each record passes the client filters, the notification rules ([`crate::log_mgr::notification`]
+/// and [`crate::log_mgr::anomaly`]), the metrics and the live volumes and templates. Notifications
+/// go to the dashboards, the sinks and the alert history, unless a silence holds them back.
+/// Wrap it in `Arc<Mutex<_>>` to share it with a [`LogWatcher`](crate::LogWatcher).
+#[derive(Debug, Default)]
+pub struct LogContextData {
+    filters: HashMap<PathBuf, RecordFilter>,
+    template_filters: HashMap<PathBuf, TemplateFilter>,
+    notifies: HashMap<PathBuf, NotificationRule>,
+    rules: Vec<NotificationRule>,
+    notifier: Notifier,
+    windows: WindowAlerts,
+    heartbeats: Heartbeats,
+    patterns: PatternAlerts,
+    sinks: NotificationSinks,
+    alerts: AlertHistory,
+    silences: Silences,
+    metrics: LogMetrics,
+    volumes: LiveVolumes,
+    templates: LiveTemplates,
+    scans: Scans,
+    // Wakes the watcher manager so it sends counted notifications when their cooldown ends
+    waker: Option<Sender<WatchCommand>>,
+}
 
 impl LogContextData {
-    // Set a filter pattern for the first path in the list
-    pub fn set_filter(&mut self, paths: Vec<PathBuf>, pattern: String) {
-        let path = paths[0].clone();
-        println!("set filter for path {} with pattern {}", path.display(), pattern);
-        self.filters.insert(path, pattern);
+    /// Creates an empty context without filters or notifications
+    pub fn new() -> LogContextData {
+        LogContextData::default()
     }
 
-    // Remove filter for a specific path
-    pub fn remove_filter(&mut self, path: PathBuf) {
-        self.filters.remove(&path);
+    /// Creates a context applying notification rules to all sources they match
+    pub fn with_rules(rules: Vec<NotificationRule>) -> LogContextData {
+        LogContextData { rules, ..LogContextData::default() }
     }
 
-    // Set a notification pattern for the first path in the list
-    pub fn set_notification(&mut self, paths: Vec<PathBuf>, pattern: String) {
-        println!("set notification for path {} with pattern {}", paths[0].display(), pattern);
-        let path = paths[0].clone();
-        self.notifies.insert(path, pattern);
+    /// Sends notifications to these sinks too; reports rules naming sinks that don't exist
+    pub fn set_sinks(&mut self, sinks: NotificationSinks) {
+        for rule in &self.rules {
+            for name in rule.sinks.iter().filter(|name| !sinks.contains(name)) {
+                warn!(rule = %rule.id, sink = %name, "Notification rule names an unknown sink");
+            }
+        }
+        self.sinks = sinks;
+    }
+
+    /// Whether rules set from the dashboard may send to the named sink
+    pub fn open_to_dashboard(&self, sink: &str) -> bool {
+        self.sinks.open_to_dashboard(sink)
+    }
+
+    /// Keeps alerts in this history
+    pub fn set_alert_history(&mut self, alerts: AlertHistory) {
+        self.alerts = alerts;
+    }
+
+    /// The alerts raised so far
+    pub fn alert_history(&self) -> &AlertHistory {
+        &self.alerts
+    }
+
+    /// The alerts raised so far, to acknowledge, resolve or silence them
+    pub fn alert_history_mut(&mut self) -> &mut AlertHistory {
+        &mut self.alerts
+    }
+
+    /// Replaces the silences
+    pub fn set_silences(&mut self, silences: Silences) {
+        self.silences = silences;
+    }
+
+    /// The silences and maintenance windows
+    pub fn silences(&self) -> &Silences {
+        &self.silences
+    }
+
+    /// The silences and maintenance windows, to add or remove one
+    pub fn silences_mut(&mut self) -> &mut Silences {
+        &mut self.silences
+    }
+
+    /// Replaces the new pattern and anomaly state, e.g. to learn templates with the configured settings
+    pub fn set_pattern_alerts(&mut self, patterns: PatternAlerts) {
+        self.patterns = patterns;
+    }
+
+    /// Replaces the log-derived metrics
+    pub fn set_metrics(&mut self, metrics: LogMetrics) {
+        self.metrics = metrics;
+    }
+
+    /// The log-derived metrics
+    pub fn metrics(&self) -> &LogMetrics {
+        &self.metrics
+    }
+
+    /// Starts keeping the new records of `path` while a client reads its existing ones; pass
+    /// the returned scan on when done, so no record falls between the two
+    pub fn begin_scan(&mut self, path: PathBuf, client: u64) -> u64 {
+        self.scans.begin(path, client)
+    }
+
+    /// Forgets a scan whose reading failed
+    pub fn end_scan(&mut self, scan: u64) {
+        self.scans.finish(scan, &[]);
+    }
+
+    /// Keeps the volume a client asked for current with the new records of the scanned source,
+    /// counting those that arrived after the `read` ones
+    pub fn track_volume(&mut self, scan: u64, read: &[LogRecord], query: VolumeQuery, bucket_ms: i64) {
+        if let Some((path, client, missed)) = self.scans.finish(scan, read) {
+            self.volumes.track(path, query, bucket_ms, client, &missed, Instant::now());
+        }
+    }
+
+    /// Stops what a client asked for: its scans, and the volumes and template miners (with
+    /// their template filters) no other client needs
+    pub fn release_client(&mut self, client: u64) {
+        self.scans.release(client);
+        self.volumes.release(client);
+        for path in self.templates.release(client) {
+            self.template_filters.remove(&path);
+        }
+    }
+
+    /// Replaces the template miners, e.g. to apply the configured settings
+    pub fn set_templates(&mut self, templates: LiveTemplates) {
+        self.templates = templates;
+    }
+
+    /// The template miners of the sources whose templates were asked for
+    pub fn templates(&self) -> &LiveTemplates {
+        &self.templates
+    }
+
+    /// Keeps mining the new records of the scanned source with a miner that has seen the `read`
+    /// ones, adding those that arrived meanwhile
+    pub fn track_templates(&mut self, scan: u64, read: &[LogRecord], miner: TemplateMiner) {
+        if let Some((path, client, missed)) = self.scans.finish(scan, read) {
+            self.templates.track(path, miner, client, &missed, Instant::now());
+        }
+    }
+
+    /// Only lines containing `pattern` (in `field`, when given) are forwarded for `path`
+    pub fn set_filter(&mut self, path: PathBuf, pattern: String, field: Option<String>) {
+        info!(path = %path.display(), pattern = %pattern, field = ?field, "Set filter");
+        self.filters.insert(path, RecordFilter { pattern: pattern.to_lowercase(), field });
+    }
+
+    /// Removes the filters for `path`, by pattern and by template
+    pub fn remove_filter(&mut self, path: &Path) {
+        self.filters.remove(path);
+        self.template_filters.remove(path);
+    }
+
+    /// Only lines of the template `id` (or, with `exclude`, all others) are forwarded for `path`;
+    /// its templates must be mined
+    pub fn set_template_filter(&mut self, path: PathBuf, filter: TemplateFilter) -> Result<(), String> {
+        if !self.templates.get(&path).is_some_and(|miner| miner.contains(filter.id)) {
+            return Err(format!("no template {} for {}", filter.id, path.display()));
+        }
+        info!(path = %path.display(), template = filter.id, exclude = filter.exclude, "Set template filter");
+        self.template_filters.insert(path, filter);
+        Ok(())
+    }
+
+    /// Returns the filter pattern for `path`, if any
+    pub fn filter(&self, path: &Path) -> Option<&str> {
+        self.filters.get(path).map(|filter| filter.pattern.as_str())
+    }
+
+    /// Sends a notification whenever a line of `path` contains `pattern`
+    pub fn set_notification(&mut self, path: PathBuf, pattern: String) {
+        let id = format!("notify:{}", path.display());
+        if let Ok(rule) = NotificationRule::new(id, &pattern, false) {
+            self.set_notification_rule(path, rule);
+        }
+    }
+
+    /// Applies a notification rule to `path`, replacing the one set before
+    pub fn set_notification_rule(&mut self, path: PathBuf, rule: NotificationRule) {
+        info!(path = %path.display(), rule = %rule.id, pattern = %rule.pattern(), "Set notification");
+        let deadline = self.deadline();
+        self.remove_notification(&path);
+        self.heartbeats.arm(&rule, &path, Instant::now());
+        self.notifies.insert(path, rule);
+        self.wake_if_moved(deadline);
+    }
+
+    /// Removes the notification for `path`
+    pub fn remove_notification(&mut self, path: &Path) {
+        if let Some(rule) = self.notifies.remove(path) {
+            self.notifier.remove(&rule.id, path);
+            self.windows.remove(&rule.id, path);
+            self.heartbeats.remove(&rule.id, path);
+            self.patterns.remove(&rule.id, path);
+        }
+    }
+
+    /// Called when a file starts being watched; absence rules for it start waiting for records
+    pub fn source_started(&mut self, path: &Path) {
+        let now = Instant::now();
+        let rules = self.notifies.get(path).into_iter()
+            .chain(self.rules.iter().filter(|rule| rule.applies_to(path)));
+        for rule in rules {
+            self.heartbeats.arm(rule, path, now);
+        }
+    }
+
+    /// Called when a file is no longer watched
+    pub fn source_stopped(&mut self, path: &Path) {
+        self.notifier.forget_source(path);
+        self.windows.forget_source(path);
+        self.heartbeats.forget_source(path);
+        self.patterns.forget_source(path);
+        self.volumes.forget_source(path);
+        self.templates.forget_source(path);
+        self.template_filters.remove(path);
+    }
+
+    /// Returns the notification pattern for `path`, if any
+    pub fn notification(&self, path: &Path) -> Option<&str> {
+        self.notifies.get(path).map(NotificationRule::pattern)
+    }
+
+    /// The configured rules applied to all sources they match
+    pub fn rules(&self) -> &[NotificationRule] {
+        &self.rules
     }
 
-    // Remove notification for a specific path
-    pub fn remove_notification(&mut self, path: PathBuf) {
-        self.notifies.remove(&path);
+    /// Lets the watcher manager be woken when a cooldown starts counting matches
+    pub(crate) fn set_waker(&mut self, waker: Sender<WatchCommand>) {
+        self.waker = Some(waker);
     }
 
-    // Called when a log file is modified; sends notifications and filtered log lines
-    pub fn on_event_modified(&self, path: &PathBuf, line: &str, log_tx: &broadcast::Sender<WsEventTx>) {
-        // Check if notification pattern matches the line
-        if let Some(pattern) = self.notifies.get(path) {
-            if line.to_lowercase().contains(&pattern.to_lowercase()) {
-                let to_send = format!("NOTIFICATION: {}", line);
-                let _ = log_tx.send(WsEventTx::SearchResult {
-                    path: path.to_string_lossy().to_string(),
-                    lines: vec![to_send],
-                });
+    /// When the next counted notifications, recoveries, absence alerts, anomaly windows or volume
+    /// and template updates are due
+    pub fn deadline(&self) -> Option<Instant> {
+        [
+            self.notifier.deadline(),
+            self.windows.deadline(),
+            self.heartbeats.deadline(),
+            self.patterns.deadline(),
+            self.volumes.deadline(),
+            self.templates.deadline(),
+        ]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Sends the counted notifications whose cooldown has ended, the recoveries of windows
+    /// that went quiet, the alerts of sources that stayed quiet too long and those of anomaly
+    /// windows that ended
+    pub fn flush_notifications(&mut self, now: Instant, log_tx: &broadcast::Sender<WsEventTx>) {
+        let mut notifications = self.notifier.flush_expired(now);
+        notifications.extend(self.windows.flush_expired(now));
+        notifications.extend(self.heartbeats.flush_expired(now));
+        notifications.extend(self.patterns.flush_expired(now));
+        for notification in notifications {
+            self.send_notification(notification, log_tx);
+        }
+    }
+
+    /// Sends the volume and template updates that are due
+    pub fn flush_updates(&mut self, now: Instant, log_tx: &broadcast::Sender<WsEventTx>) {
+        for volume in self.volumes.flush_expired(now) {
+            let _ = log_tx.send(WsEventTx::Volume(volume));
+        }
+        for templates in self.templates.flush_expired(now) {
+            let _ = log_tx.send(WsEventTx::Templates(templates));
+        }
+    }
+
+    // Records a notification in the alert history and, unless a silence covers it or its alert,
+    // sends it to the dashboards and (unless acknowledged) to the sinks of its rule
+    fn send_notification(&mut self, notification: Notification, log_tx: &broadcast::Sender<WsEventTx>) {
+        let now = timestamp::now_millis();
+        let silence = self.silences.matching(&notification, now).map(|silence| silence.id().to_string());
+        let alert = self.alerts.record(&notification, silence.as_deref());
+        if let Some(alert) = &alert {
+            let _ = log_tx.send(WsEventTx::Alert(alert.clone()));
+        }
+        if let Some(silence) = silence {
+            debug!(rule = %notification.rule_id, path = %notification.path, silence = %silence, "Notification suppressed");
+            return;
+        }
+        if alert.as_ref().is_some_and(|alert| alert.is_silenced(now)) {
+            return;
+        }
+
+        let acknowledged = alert.is_some_and(|alert| alert.state == AlertState::Acknowledged);
+        let rule = self.rules.iter()
+            .chain(self.notifies.values())
+            .find(|rule| rule.id == notification.rule_id);
+        if let Some(rule) = rule
+            && !acknowledged {
+            self.sinks.send(&rule.sinks, &notification);
+        }
+        let _ = log_tx.send(WsEventTx::Notification(notification));
+    }
+
+    // Wakes the watcher manager when the next deadline changed, so it doesn't sleep past it
+    fn wake_if_moved(&self, deadline: Option<Instant>) {
+        if self.deadline() != deadline
+            && let Some(waker) = &self.waker {
+            let _ = waker.send(WatchCommand::Wake);
+        }
+    }
+
+    /// Called for every new record of a source (sent as `"<line number>: <content>"`);
+    /// sends notifications and, unless filtered out, the line itself
+    pub fn on_event_modified(&mut self, path: &Path, record: &LogRecord, log_tx: &broadcast::Sender<WsEventTx>) {
+        let line = &record.numbered();
+        let content = record.text.as_str();
+
+        // Check the rules for this source and the configured ones against the content
+        let now = Instant::now();
+        let deadline = self.deadline();
+        let rules = self.notifies.get(path).into_iter()
+            .chain(self.rules.iter().filter(|rule| rule.applies_to(path)));
+        let mut notifications = Vec::new();
+        for rule in rules {
+            match rule.trigger {
+                Trigger::Match if rule.matches(content) => notifications.extend(self.notifier.on_match(rule, path, line, content, now)),
+                Trigger::Match => {}
+                Trigger::Absence { .. } => notifications.extend(self.heartbeats.on_record(rule, path, line, content, now)),
+                Trigger::Threshold { .. } | Trigger::Ratio { .. } => {
+                    notifications.extend(self.windows.on_record(rule, path, line, content, now));
+                }
+                Trigger::NewPattern { .. } | Trigger::Anomaly { .. } => {
+                    notifications.extend(self.patterns.on_record(rule, path, line, content, now));
+                }
             }
         }
+        for notification in notifications {
+            self.send_notification(notification, log_tx);
+        }
 
-        // Split line into line number and content
-        let (_line_number, content) = match line.split_once(": ") {
-            Some((num, rest)) => (num, rest),
-            None => ("", line),
-        };
+        // Merged timelines repeat the records of their files, which were counted already
+        if !path.to_string_lossy().starts_with(MERGED_SCHEME) {
+            self.metrics.observe(path, content);
+        }
+        self.volumes.on_record(path, content, now);
+        self.templates.on_record(path, content, now);
+        self.scans.on_record(path, record);
+        self.wake_if_moved(deadline);
 
         // If filter is set, only send lines that match the filter pattern
-        if let Some(filter_pattern) = self.filters.get(path) {
-            if !content.to_lowercase().contains(&filter_pattern.to_lowercase()) {
-                return;
-            }
+        if let Some(filter) = self.filters.get(path)
+            && !filter.passes(record) {
+            return;
+        }
+        if let Some(filter) = self.template_filters.get(path)
+            && let Some(miner) = self.templates.get(path)
+            && !filter.passes(miner, content) {
+            return;
         }
 
         // Send the log line to clients
         let _ = log_tx.send(WsEventTx::Log {
             path: path.to_string_lossy().to_string(),
-            line: line.to_string()
+            line: line.to_string(),
+            fields: record.fields.clone(),
         });
     }
-}
\ No newline at end of file
+}
+
+/// A filter set by a client: a lowercase substring of the record, or of one of its fields
+#[derive(Debug)]
+struct RecordFilter {
+    pattern: String,
+    field: Option<String>,
+}
+
+impl RecordFilter {
+    fn passes(&self, record: &LogRecord) -> bool {
+        record.matched_text(self.field.as_deref())
+            .is_some_and(|text| text.to_lowercase().contains(&self.pattern))
+    }
+}
+
+/// A client reading the existing records of a source, and the records that arrived meanwhile
+#[derive(Debug)]
+struct Scan {
+    path: PathBuf,
+    client: u64,
+    arrived: Vec<LogRecord>,
+}
+
+/// Scans in progress by id
+#[derive(Debug, Default)]
+struct Scans {
+    next: u64,
+    scans: HashMap<u64, Scan>,
+}
+
+impl Scans {
+    fn begin(&mut self, path: PathBuf, client: u64) -> u64 {
+        self.next += 1;
+        self.scans.insert(self.next, Scan { path, client, arrived: Vec::new() });
+        self.next
+    }
+
+    fn on_record(&mut self, path: &Path, record: &LogRecord) {
+        for scan in self.scans.values_mut().filter(|scan| scan.path == path) {
+            scan.arrived.push(record.clone());
+        }
+    }
+
+    // Ends a scan; returns its source, its client and the records that arrived after the
+    // `read` ones. Line numbers count per file, so merged timelines compare them per origin.
+    fn finish(&mut self, scan: u64, read: &[LogRecord]) -> Option<(PathBuf, u64, Vec<LogRecord>)> {
+        let scan = self.scans.remove(&scan)?;
+        let mut last: HashMap<Option<&str>, usize> = HashMap::new();
+        for record in read {
+            let line = last.entry(record.origin.as_deref()).or_default();
+            *line = (*line).max(record.line_number);
+        }
+        let missed = scan.arrived.into_iter()
+            .filter(|record| record.line_number > last.get(&record.origin.as_deref()).copied().unwrap_or_default())
+            .collect();
+        Some((scan.path, scan.client, missed))
+    }
+
+    fn release(&mut self, client: u64) {
+        self.scans.retain(|_, scan| scan.client != client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_mgr::config::ThresholdConfig;
+
+    #[test]
+    fn replacing_or_stopping_a_rule_resets_its_window() {
+        let (log_tx, mut log_rx) = broadcast::channel(16);
+        let path = PathBuf::from("/var/log/app.log");
+        let threshold = || {
+            let mut rule = NotificationRule::new("burst", "error", false).unwrap();
+            rule.set_trigger(Some(&ThresholdConfig { count: 1, window_ms: 60_000 }), None, None, None, None).unwrap();
+            rule
+        };
+        let mut alerts = |ctx: &mut LogContextData, line| {
+            ctx.on_event_modified(&path, &LogRecord::new(line, "error"), &log_tx);
+            std::iter::from_fn(|| log_rx.try_recv().ok())
+                .filter(|event| matches!(event, WsEventTx::Notification(_)))
+                .count()
+        };
+
+        let mut ctx = LogContextData::new();
+        ctx.set_notification_rule(path.clone(), threshold());
+        assert_eq!(alerts(&mut ctx, 1), 0);
+        ctx.set_notification_rule(path.clone(), threshold());
+        assert_eq!(alerts(&mut ctx, 2), 0);
+        assert_eq!(alerts(&mut ctx, 3), 1);
+
+        ctx.source_stopped(&path);
+        assert_eq!(alerts(&mut ctx, 4), 0);
+        ctx.remove_notification(&path);
+        assert_eq!(alerts(&mut ctx, 5), 0);
+        assert!(ctx.deadline().is_none());
+    }
+
+    #[test]
+    fn scans_return_the_records_after_the_read_ones() {
+        let mut scans = Scans::default();
+        let path = PathBuf::from("merged://both");
+        let scan = scans.begin(path.clone(), 7);
+        for (origin, line) in [("a", 10), ("a", 11), ("b", 3), ("c", 1)] {
+            scans.on_record(&path, &LogRecord::new(line, "text").with_origin(origin));
+        }
+        scans.on_record(Path::new("/var/log/other.log"), &LogRecord::new(99, "text"));
+
+        let read = [LogRecord::new(10, "text").with_origin("a"), LogRecord::new(5, "text").with_origin("b")];
+        let (scanned, client, missed) = scans.finish(scan, &read).unwrap();
+        assert_eq!((scanned, client), (path, 7));
+        let missed: Vec<(Option<&str>, usize)> = missed.iter().map(|r| (r.origin.as_deref(), r.line_number)).collect();
+        assert_eq!(missed, [(Some("a"), 11), (Some("c"), 1)]);
+        assert!(scans.finish(scan, &[]).is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use regex::Regex;
+use serde::Deserialize;
+use crate::log_mgr::config::SourceFormat;
+use crate::log_mgr::journal::{self, JournalEntry};
+use crate::log_mgr::log_record::LogRecord;
+
+/// CRI log line: `<RFC 3339 timestamp> <stream> <P|F> <message>`
+static CRI_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\d{4}-\d{2}-\d{2}T\S+) (stdout|stderr) ([PF])(?::\S*)? ?(.*)$").unwrap()
+});
+
+/// One line of the Docker json-file log driver
+#[derive(Deserialize)]
+struct DockerLine {
+    log: String,
+    stream: String,
+    time: String,
+}
+
+/// A container runtime entry with its wrapper removed
+struct ContainerEntry {
+    time: String,
+    stream: String,
+    message: String,
+    /// The runtime split a long line; the rest follows in the next entries of the stream
+    partial: bool,
+}
+
+/// Guesses the format of a source from one of its lines
+pub fn detect(line: &str) -> SourceFormat {
+    if journal::is_export(line.as_bytes()) {
+        SourceFormat::Journal
+    } else if journal::is_json_entry(line) {
+        SourceFormat::JournalJson
+    } else if line.starts_with("{\"log\":") && serde_json::from_str::<DockerLine>(line).is_ok() {
+        SourceFormat::Docker
+    } else if CRI_LINE.is_match(line) {
+        SourceFormat::Cri
+    } else {
+        SourceFormat::Plain
+    }
+}
+
+/// Unwraps container runtime lines into their messages, rendered as `<time> [<stream>] <message>`
+/// with `time` and `stream` fields, and joins partial entries. Journal entries become one record
+/// each, rendered as `<time> <priority> <unit>[<pid>]: <message>` with these parts as fields.
+/// Plain sources pass through unchanged.
+#[derive(Debug)]
+pub struct FormatParser {
+    format: Option<SourceFormat>,
+    // Partial entries per stream: line number of the first piece, its time and the text so far
+    pending: HashMap<String, (usize, String, String)>,
+    // Fields of the journal export entry being read and the line number it started at
+    journal_entry: Option<(usize, JournalEntry)>,
+}
+
+impl FormatParser {
+    /// Creates a parser; without a configured format it is detected from the first line
+    pub fn new(format: Option<SourceFormat>) -> FormatParser {
+        FormatParser { format, pending: HashMap::new(), journal_entry: None }
+    }
+
+    /// Parses one line; returns a record unless the line is part of an unfinished entry
+    pub fn push(&mut self, line: LogRecord) -> Option<LogRecord> {
+        let format = *self.format.get_or_insert_with(|| detect(&line.text));
+        let entry = match format {
+            SourceFormat::Plain => None,
+            SourceFormat::Docker => parse_docker(&line.text),
+            SourceFormat::Cri => parse_cri(&line.text),
+            SourceFormat::Journal => return self.push_journal_field(line),
+            SourceFormat::JournalJson => {
+                return Some(match journal::parse_json_entry(&line.text) {
+                    Some(entry) => entry.log_record(line.line_number),
+                    None => line,
+                });
+            }
+        };
+        // Lines that don't fit the format are kept as they are
+        let Some(entry) = entry else {
+            return Some(line);
+        };
+
+        let (line_number, time, mut message) = self.pending.remove(&entry.stream)
+            .unwrap_or((line.line_number, entry.time, String::new()));
+        message.push_str(&entry.message);
+
+        if entry.partial {
+            self.pending.insert(entry.stream, (line_number, time, message));
+            None
+        } else {
+            Some(render(line_number, &time, &entry.stream, &message))
+        }
+    }
+
+    /// Returns the unfinished entries, e.g. at the end of a file
+    pub fn flush(&mut self) -> Vec<LogRecord> {
+        let mut records: Vec<LogRecord> = self.pending.drain()
+            .map(|(stream, (line_number, time, message))| render(line_number, &time, &stream, &message))
+            .collect();
+        records.extend(self.finish_journal_entry());
+        records.sort_by_key(|record| record.line_number);
+        records
+    }
+
+    // Collects `NAME=value` lines of an export entry; the blank line after it completes the entry
+    fn push_journal_field(&mut self, line: LogRecord) -> Option<LogRecord> {
+        if line.text.is_empty() {
+            return self.finish_journal_entry();
+        }
+        let Some((name, value)) = journal::parse_export_field(&line.text) else {
+            return Some(line);
+        };
+        let (_, entry) = self.journal_entry.get_or_insert_with(|| (line.line_number, JournalEntry::default()));
+        entry.push(name, value.replace("\\n", "\n"));
+        None
+    }
+
+    fn finish_journal_entry(&mut self) -> Option<LogRecord> {
+        self.journal_entry.take()
+            .filter(|(_, entry)| !entry.is_empty())
+            .map(|(line_number, entry)| entry.log_record(line_number))
+    }
+}
+
+/// Parses complete lines of a source into records, in the order their first lines appear
+pub fn parse_records(format: Option<SourceFormat>, lines: Vec<LogRecord>) -> Vec<LogRecord> {
+    let mut parser = FormatParser::new(format);
+    let mut records: Vec<LogRecord> = lines.into_iter().filter_map(|line| parser.push(line)).collect();
+    records.extend(parser.flush());
+    records.sort_by_key(|record| record.line_number);
+    records
+}
+
+fn render(line_number: usize, time: &str, stream: &str, message: &str) -> LogRecord {
+    LogRecord::new(line_number, format!("{} [{}] {}", time, stream, message))
+        .with_field("time", time)
+        .with_field("stream", stream)
+}
+
+// `{"log":"message\n","stream":"stdout","time":"..."}`; a message without newline is partial
+fn parse_docker(line: &str) -> Option<ContainerEntry> {
+    let parsed: DockerLine = serde_json::from_str(line).ok()?;
+    let partial = !parsed.log.ends_with('\n');
+    let message = parsed.log.trim_end_matches(['\r', '\n']).to_string();
+    Some(ContainerEntry { time: parsed.time, stream: parsed.stream, message, partial })
+}
+
+// `2024-01-01T00:00:00.000000000Z stdout F message`
+fn parse_cri(line: &str) -> Option<ContainerEntry> {
+    let caps = CRI_LINE.captures(line)?;
+    Some(ContainerEntry {
+        time: caps[1].to_string(),
+        stream: caps[2].to_string(),
+        message: caps[4].to_string(),
+        partial: &caps[3] == "P",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_mgr::log_record::line_records;
+
+    fn texts(records: &[LogRecord]) -> Vec<(usize, &str)> {
+        records.iter().map(|record| (record.line_number, record.text.as_str())).collect()
+    }
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(detect(r#"{"log":"x\n","stream":"stdout","time":"2024-05-01T10:00:00Z"}"#), SourceFormat::Docker);
+        assert_eq!(detect("2024-05-01T10:00:00.1Z stderr F boom"), SourceFormat::Cri);
+        assert_eq!(detect("2024-05-01T10:00:00.1Z stderr F"), SourceFormat::Cri);
+        assert_eq!(detect("__CURSOR=s=1"), SourceFormat::Journal);
+        assert_eq!(detect(r#"{"__CURSOR":"s","MESSAGE":"x"}"#), SourceFormat::JournalJson);
+        assert_eq!(detect(r#"{"log":"x"}"#), SourceFormat::Plain);
+        assert_eq!(detect("2024-05-01T10:00:00Z INFO plain"), SourceFormat::Plain);
+    }
+
+    #[test]
+    fn docker_partials_are_joined_per_stream() {
+        let lines = line_records(concat!(
+            r#"{"log":"long ","stream":"stdout","time":"t1"}"#, "\n",
+            r#"{"log":"oops\n","stream":"stderr","time":"t2"}"#, "\n",
+            r#"{"log":"line\r\n","stream":"stdout","time":"t3"}"#, "\n",
+            "not json\n",
+        ));
+        let records = parse_records(None, lines);
+        assert_eq!(texts(&records), [(1, "t1 [stdout] long line"), (2, "t2 [stderr] oops"), (4, "not json")]);
+        assert_eq!(records[0].fields.get("stream").map(String::as_str), Some("stdout"));
+        assert_eq!(records[0].fields.get("time").map(String::as_str), Some("t1"));
+        assert!(records[2].fields.is_empty());
+    }
+
+    #[test]
+    fn cri_partials_and_unfinished_entries() {
+        let lines = line_records(concat!(
+            "2024-05-01T10:00:00Z stdout P hel\n",
+            "2024-05-01T10:00:01Z stdout P lo\n",
+            "2024-05-01T10:00:02Z stdout F  world\n",
+            "2024-05-01T10:00:03Z stderr P cut off\n",
+        ));
+        let records = parse_records(Some(SourceFormat::Cri), lines);
+        assert_eq!(texts(&records), [
+            (1, "2024-05-01T10:00:00Z [stdout] hello world"),
+            (4, "2024-05-01T10:00:03Z [stderr] cut off"),
+        ]);
+    }
+
+    #[test]
+    fn journal_export_entries() {
+        let lines = line_records(concat!(
+            "__CURSOR=a\n__REALTIME_TIMESTAMP=0\nPRIORITY=4\nSYSLOG_IDENTIFIER=app\nMESSAGE=one\\ntwo\n\n",
+            "__CURSOR=b\nMESSAGE=last\n",
+        ));
+        let records = parse_records(None, lines);
+        assert_eq!(texts(&records), [
+            (1, "1970-01-01T00:00:00.000000Z warning app: one\\ntwo"),
+            (7, "- - -: last"),
+        ]);
+        assert_eq!(records[0].fields.get("unit").map(String::as_str), Some("app"));
+    }
+
+    #[test]
+    fn configured_plain_format_keeps_lines() {
+        let lines = line_records(r#"{"log":"x\n","stream":"stdout","time":"t"}"#);
+        assert_eq!(texts(&parse_records(Some(SourceFormat::Plain), lines)), [(1, r#"{"log":"x\n","stream":"stdout","time":"t"}"#)]);
+    }
+}
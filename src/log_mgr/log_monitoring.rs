@@ -1,257 +1,568 @@
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
 use notify::event::{EventKind, CreateKind, ModifyKind};
 use std::{path::Path,fs};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::path::PathBuf;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use serde_json::Value;
 use tokio::sync::broadcast;
+use tracing::{debug, debug_span, info, info_span, warn};
 use crate::log_mgr::rust_server::WsEventTx;
-
-
-
-/// Commands for the watcher manager to add or remove files to watch
+use crate::log_mgr::diagnostics::StatusData;
+use crate::log_mgr::log_context_data::LogContextData;
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::line_buffer::LineBuffer;
+use crate::log_mgr::encoding::{self, TextEncoding};
+use crate::log_mgr::compression::{self, Compression};
+use crate::log_mgr::rotation;
+use crate::log_mgr::timeline::Timeline;
+use crate::log_mgr::journal;
+use crate::log_mgr::config::SourceFormat;
+use crate::log_mgr::log_format::FormatParser;
+use crate::log_mgr::multiline::RecordAssembler;
+use crate::log_mgr::source_rules::{SourceOptions, SourceRules};
+
+
+
+/// Messages for the watcher manager: add/remove files, merged timelines, file system events and shutdown.
+/// `Wake` only makes it look at its deadlines again, e.g. after a notification cooldown started.
+/// `Merge` gives each file with the number of lines it had, where tailing continues the numbering;
+/// merging under a name in use replaces that timeline.
 #[derive(Debug)]
 pub enum WatchCommand {
     Add(PathBuf),
     Remove(PathBuf),
+    Merge { name: String, files: Vec<(PathBuf, usize)>, window: Duration },
+    Unmerge(String),
+    FileEvent(Event),
+    Wake,
+    Shutdown,
 }
 
-/// State for tailing a file: path, current offset, and line number
+/// Size of the buffer used to read newly appended data
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// State for tailing a file: path, current offset, any unterminated line, any partial container
+/// entry and any record still being assembled
 struct TailState {
     path: PathBuf,
     offset: u64,
-    line_number: usize,
+    lines: LineBuffer,
+    parser: FormatParser,
+    assembler: Option<RecordAssembler>,
 }
 
-/// Context data for log management, including filters and notifications
-pub struct LogContextData {
-    pub(crate) filters: HashMap<PathBuf, String>,
-    pub(crate) notifies: HashMap<PathBuf, String>,
+impl TailState {
+    fn new(path: PathBuf, offset: u64, line_number: usize, rules: &SourceRules) -> TailState {
+        let options = rules.options_for(&path);
+        let encoding = TextEncoding::detect_file(options.encoding, &path);
+        let lines = LineBuffer::new(line_number, options.partial_line_timeout, encoding);
+        let parser = FormatParser::new(options.format);
+        let assembler = options.multiline.map(RecordAssembler::new);
+        TailState { path, offset, lines, parser, assembler }
+    }
+
+    /// Parses freshly read lines and groups them into records; the last record stays pending until it is complete
+    fn assemble(&mut self, lines: Vec<LogRecord>) -> Vec<LogRecord> {
+        let parsed = lines.into_iter().filter_map(|line| self.parser.push(line));
+        let Some(assembler) = self.assembler.as_mut() else {
+            return parsed.collect();
+        };
+        parsed.filter_map(|line| assembler.push(line.line_number, &line.text))
+            .collect()
+    }
+
+    /// When the unterminated line or the pending record must be emitted if nothing else arrives
+    fn deadline(&self) -> Option<Instant> {
+        let record_deadline = self.assembler.as_ref().and_then(RecordAssembler::deadline);
+        match (self.lines.deadline(), record_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Emits the unterminated line and the pending record whose deadlines have passed
+    fn flush_expired(&mut self, now: Instant) -> Vec<LogRecord> {
+        let mut records = Vec::new();
+        if self.lines.deadline().is_some_and(|deadline| deadline <= now)
+            && let Some(line) = self.lines.flush() {
+            records = self.assemble(vec![line]);
+        }
+
+        if let Some(assembler) = self.assembler.as_mut()
+            && assembler.deadline().is_some_and(|deadline| deadline <= now) {
+            records.extend(assembler.flush());
+        }
+        records
+    }
 }
 
-/// Loads the entire contents of a log file as a String
-pub(crate) fn load_log_contents(log_path: &Path) -> String{
-    let contents = fs::read_to_string(log_path)
-        .expect("error reading log file");
-    return contents;
+/// Loads the entire contents of a log file as a String: decompressed, decoded with the source's
+/// (or detected) encoding and, for rotated sources, preceded by its rotations oldest first.
+/// Rotations the source's sandbox does not allow are skipped.
+pub(crate) fn load_log_contents(log_path: &Path, options: &SourceOptions) -> std::io::Result<String> {
+    let files = if options.rotated {
+        rotation::rotated_set(log_path).into_iter()
+            .filter(|file| {
+                let allowed = file == log_path || options.sandbox.as_ref().is_none_or(|sandbox| sandbox.allows(file));
+                if !allowed {
+                    warn!(path = %file.display(), "Skipping rotation outside the allowed log locations");
+                }
+                allowed
+            })
+            .collect()
+    } else {
+        vec![log_path.to_path_buf()]
+    };
+
+    let mut text = String::new();
+    for file in &files {
+        let bytes = match compression::read_file(file) {
+            Ok(b) => b,
+            // A broken rotation should not hide the rest of the stream
+            Err(e) if file != log_path => {
+                warn!(path = %file.display(), "Skipping unreadable rotation: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        // Binary fields of journal exports span lines; rewrite them so the dump reads line by line
+        let bytes = match options.format {
+            Some(SourceFormat::Journal) => journal::export_to_text(&bytes),
+            None if journal::is_export(&bytes) => journal::export_to_text(&bytes),
+            _ => bytes,
+        };
+        text.push_str(&encoding::decode_file(options.encoding, &bytes));
+        if file != log_path && !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+    Ok(text)
 }
 
-/// Starts the watcher manager thread, which listens for add/remove commands and file events
+/// Whether a file is shown as one pretty-printed JSON document rather than read as a log.
+/// Journal exports made with `journalctl -o json` are `.json` files with one entry per line.
+pub(crate) fn is_json_document(path: &Path, options: &SourceOptions) -> bool {
+    if path.extension().and_then(|e| e.to_str()).is_none_or(|e| !e.eq_ignore_ascii_case("json")) {
+        return false;
+    }
+    match options.format {
+        Some(format) => format == SourceFormat::Plain,
+        None => {
+            let mut head = vec![0u8; READ_BUFFER_SIZE];
+            let read = File::open(path).and_then(|mut f| f.read(&mut head)).unwrap_or(0);
+            let head = String::from_utf8_lossy(&head[..read]);
+            !journal::is_json_entry(head.lines().next().unwrap_or_default())
+        }
+    }
+}
+
+/// Starts the watcher manager thread, which blocks on add/remove commands and file events until shutdown
 pub fn start_watcher_manager(
+    cmd_tx: Sender<WatchCommand>,
     cmd_rx: Receiver<WatchCommand>,
     log_tx: broadcast::Sender<WsEventTx>,
-    context: Arc<Mutex<LogContextData>>
+    context: Arc<Mutex<LogContextData>>,
+    status: Arc<Mutex<StatusData>>,
+    rules: Arc<SourceRules>,
 ) -> thread::JoinHandle<()> {
 
     thread::spawn(move || {
-        let (event_tx, event_rx) = std::sync::mpsc::channel();
         let mut watchers: HashMap<PathBuf, RecommendedWatcher> = HashMap::new();
         let mut states: HashMap<PathBuf, TailState> = HashMap::new();
+        // Files clients asked for; others are only watched for the merged timelines using them
+        let mut requested: HashSet<PathBuf> = HashSet::new();
+        let mut timelines: HashMap<String, Timeline> = HashMap::new();
 
+        // Commands and file events arrive on the same channel, so the thread sleeps until there is work,
+        // a pending multi-line record is due, a merged record leaves its reordering window, a
+        // notification cooldown ends or a volume update is due
         loop {
-            // Handle add/remove commands
-            while let Ok(cmd) = cmd_rx.try_recv() {
-                match cmd {
-                    WatchCommand::Add(path) => {
-                        if watchers.contains_key(&path) {
-                            continue;
-                        }
-                        let mut old_lines= 0;
-
-                        // Send existing log lines before watching
-                        if path.exists() {
-                            old_lines = send_old_log_lines(&path, &log_tx);
-                        }
-
-                        println!("Watching {:?}", path);
-
-                        // Only watch non-JSON files
-                        if path
-                            .extension()
-                            .and_then(|e| e.to_str())
-                            .map_or(true, |e| !e.eq_ignore_ascii_case("json"))
-                        {
-                            let tx = event_tx.clone();
-                            let mut watcher = match RecommendedWatcher::new(
-                                move |res| {
-                                    if let Ok(event) = res {
-                                        let _ = tx.send(event);
-                                    }
-                                },
-                                notify::Config::default(),
-                            ) {
-                                Ok(w) => w,
-                                Err(e) => {
-                                    eprintln!("Failed to create watcher for {}: {}", path.display(), e);
-                                    continue;
-                                }
-                            };
-
-                            if let Err(e) = watcher.watch(&path, RecursiveMode::Recursive) {
-                                eprintln!("Failed to watch {}: {}", path.display(), e);
-                                continue;
-                            }
-
-                            watchers.insert(path.clone(), watcher);
-
-                            // Initialize tail state for the file
-                            let offset = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
-                            states.insert(path.clone(), TailState {
-                                path: path.clone(),
-                                offset,
-                                line_number: old_lines,
-                            });
-                        }
+            let notification_deadline = context.lock().unwrap().deadline();
+            let deadline = states.values().filter_map(TailState::deadline)
+                .chain(timelines.values().filter_map(Timeline::deadline))
+                .chain(notification_deadline)
+                .min();
+            let cmd = match deadline {
+                Some(deadline) => match cmd_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(cmd) => cmd,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let records = flush_expired_records(&mut states);
+                        deliver(records, &mut timelines, &log_tx, &context);
+                        let mut ctx = context.lock().unwrap();
+                        ctx.flush_notifications(Instant::now(), &log_tx);
+                        ctx.flush_updates(Instant::now(), &log_tx);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match cmd_rx.recv() {
+                    Ok(cmd) => cmd,
+                    Err(_) => break,
+                },
+            };
+
+            match cmd {
+                WatchCommand::Add(path) => {
+                    if !requested.insert(path.clone()) {
+                        continue;
                     }
+                    let _span = info_span!("watch", path = %path.display()).entered();
+                    let mut old_lines= 0;
 
-                    WatchCommand::Remove(path) => {
-                        watchers.remove(&path);
-                        states.remove(&path);
-                        println!("Stopped watching {:?}", path);
+                    // Send existing log lines before watching
+                    if path.exists() {
+                        old_lines = send_old_log_lines(&path, &log_tx, &rules);
                     }
 
+                    info!("Watching");
+                    // A merged timeline may already be tailing the file
+                    if !watchers.contains_key(&path) {
+                        start_tailing(&path, old_lines, &cmd_tx, &mut watchers, &mut states, &status, &rules);
+                    }
+                    context.lock().unwrap().source_started(&path);
                 }
-            }
 
-            // Handle file system events
-            while let Ok(event) = event_rx.try_recv() {
-
-                match event.kind {
-                    EventKind::Modify(ModifyKind::Data(_)) => {
-                        for path in &event.paths {
-                            // Get or create tail state for the file
-                            let state = states.entry(path.clone()).or_insert_with(|| {
-                                let offset = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
-                                TailState { path: path.clone(), offset, line_number: 0 }
-                            });
-
-                            // Read new data and send to clients
-                            match tail_new_data(state) {
-                                Ok(new_data) => {
-                                    for line in new_data.lines() {
-                                        LogContextData::on_event_modified(&context.lock().unwrap(), &path, line, &log_tx);
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Failed to read {}: {}", path.display(), e);
-                                }
-                            }
-                        }
+                WatchCommand::Remove(path) => {
+                    requested.remove(&path);
+                    if !timelines.values().any(|timeline| timeline.contains(&path)) {
+                        stop_tailing(&path, &mut watchers, &mut states, &status);
                     }
+                    context.lock().unwrap().source_stopped(&path);
+                }
 
-                    EventKind::Create(CreateKind::File) => {
-                        for path in &event.paths {
-                            println!("File created {:?}", path);
+                WatchCommand::Merge { name, files, window } => {
+                    let _span = info_span!("merge", name = %name).entered();
+                    for (path, old_lines) in &files {
+                        if !watchers.contains_key(path) {
+                            start_tailing(path, *old_lines, &cmd_tx, &mut watchers, &mut states, &status, &rules);
                         }
                     }
-
-                    _ => {
-                        // ignore other events
+                    info!(files = files.len(), "Merging");
+                    let paths = files.into_iter().map(|(path, _)| path).collect();
+                    if let Some(old) = timelines.insert(name, Timeline::new(paths, window)) {
+                        release_files(&old, &requested, &timelines, &mut watchers, &mut states, &status);
                     }
                 }
 
-            }
+                WatchCommand::Unmerge(name) => {
+                    let Some(timeline) = timelines.remove(&name) else {
+                        continue;
+                    };
+                    info!(name = %name, "Stopped merging");
+                    release_files(&timeline, &requested, &timelines, &mut watchers, &mut states, &status);
+                    context.lock().unwrap().source_stopped(Path::new(&name));
+                }
+
+                WatchCommand::FileEvent(event) => {
+                    let records = handle_file_event(event, &watchers, &mut states, &status, &rules);
+                    deliver(records, &mut timelines, &log_tx, &context);
+                }
+
+                WatchCommand::Wake => {}
 
-            thread::sleep(Duration::from_millis(100));
+                WatchCommand::Shutdown => {
+                    break;
+                }
+            }
         }
+
+        // Dropping the watchers unregisters them from the OS before the thread exits
+        let count = watchers.len();
+        watchers.clear();
+        info!(released = count, "Watcher manager stopped");
     })
 }
 
-/// Reads new data appended to a file since the last read, updates state, and returns the new lines
-fn tail_new_data(state: &mut TailState) -> std::io::Result<String> {
+/// Starts watching a file for appended data, unless it is a JSON document or compressed
+fn start_tailing(
+    path: &Path,
+    old_lines: usize,
+    cmd_tx: &Sender<WatchCommand>,
+    watchers: &mut HashMap<PathBuf, RecommendedWatcher>,
+    states: &mut HashMap<PathBuf, TailState>,
+    status: &Arc<Mutex<StatusData>>,
+    rules: &SourceRules,
+) {
+    // Only watch non-JSON files; compressed files are not appended to, so they are not tailed either
+    if is_json_document(path, &rules.options_for(path)) || Compression::detect_file(path).is_some() {
+        return;
+    }
 
-    let mut file = match File::open(&state.path) {
-        Ok(f) => f,
+    let tx = cmd_tx.clone();
+    let mut watcher = match RecommendedWatcher::new(
+        move |res| {
+            if let Ok(event) = res {
+                let _ = tx.send(WatchCommand::FileEvent(event));
+            }
+        },
+        notify::Config::default(),
+    ) {
+        Ok(w) => w,
         Err(e) => {
-            eprintln!("Cannot open {}: {}", state.path.display(), e);
-            return Ok(String::new());
+            warn!(path = %path.display(), "Failed to create watcher: {}", e);
+            return;
         }
     };
 
-    let len = match file.metadata() {
-        Ok(m) => m.len(),
-        Err(e) => {
-            eprintln!("Cannot get metadata for {}: {}", state.path.display(), e);
-            return Ok(String::new());
+    if let Err(e) = watcher.watch(path, RecursiveMode::Recursive) {
+        warn!(path = %path.display(), "Failed to watch: {}", e);
+        return;
+    }
+
+    watchers.insert(path.to_path_buf(), watcher);
+
+    // Initialize tail state for the file
+    let offset = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    status.lock().unwrap().source_added(path, offset, old_lines);
+    states.insert(path.to_path_buf(), TailState::new(path.to_path_buf(), offset, old_lines, rules));
+}
+
+/// Stops watching a file and forgets its tail state
+fn stop_tailing(
+    path: &Path,
+    watchers: &mut HashMap<PathBuf, RecommendedWatcher>,
+    states: &mut HashMap<PathBuf, TailState>,
+    status: &Arc<Mutex<StatusData>>,
+) {
+    watchers.remove(path);
+    states.remove(path);
+    status.lock().unwrap().source_removed(path);
+    info!(path = %path.display(), "Stopped watching");
+}
+
+/// Stops tailing the files of a timeline that was unmerged or replaced, unless a client asked
+/// for them or another timeline still uses them
+fn release_files(
+    timeline: &Timeline,
+    requested: &HashSet<PathBuf>,
+    timelines: &HashMap<String, Timeline>,
+    watchers: &mut HashMap<PathBuf, RecommendedWatcher>,
+    states: &mut HashMap<PathBuf, TailState>,
+    status: &Arc<Mutex<StatusData>>,
+) {
+    for path in timeline.paths() {
+        if !requested.contains(path) && !timelines.values().any(|other| other.contains(path)) {
+            stop_tailing(path, watchers, states, status);
+        }
+    }
+}
+
+/// Sends new records of files through filters and notifications and into the merged timelines
+/// using them, then sends the merged records whose reordering window has passed
+fn deliver(
+    records: Vec<(PathBuf, Vec<LogRecord>)>,
+    timelines: &mut HashMap<String, Timeline>,
+    log_tx: &broadcast::Sender<WsEventTx>,
+    context: &Arc<Mutex<LogContextData>>,
+) {
+    let now = Instant::now();
+    let mut ctx = context.lock().unwrap();
+    for (path, records) in records {
+        for record in records {
+            ctx.on_event_modified(&path, &record, log_tx);
+            for timeline in timelines.values_mut().filter(|timeline| timeline.contains(&path)) {
+                timeline.push(&path, &record, now);
+            }
+        }
+    }
+
+    for (name, timeline) in timelines.iter_mut() {
+        for record in timeline.release(now) {
+            ctx.on_event_modified(Path::new(name), &record, log_tx);
+        }
+    }
+}
+
+/// Handles a single file system event from one of the watchers, returns the new records per file
+fn handle_file_event(
+    event: Event,
+    watchers: &HashMap<PathBuf, RecommendedWatcher>,
+    states: &mut HashMap<PathBuf, TailState>,
+    status: &Arc<Mutex<StatusData>>,
+    rules: &SourceRules,
+) -> Vec<(PathBuf, Vec<LogRecord>)> {
+    let mut records = Vec::new();
+    match event.kind {
+        EventKind::Modify(ModifyKind::Data(_)) => {
+            for path in &event.paths {
+                // Events can still be queued for a file whose watcher was just removed
+                if !watchers.keys().any(|watched| path.starts_with(watched)) {
+                    continue;
+                }
+                let _span = debug_span!("tail", path = %path.display()).entered();
+
+                // Get or create tail state for the file
+                let state = states.entry(path.clone()).or_insert_with(|| {
+                    let offset = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                    TailState::new(path.clone(), offset, 0, rules)
+                });
+
+                // Read new data to be sent to clients
+                match tail_new_data(state) {
+                    Ok(new_lines) => {
+                        debug!(offset = state.offset, line = state.lines.line_number(), "Read new data");
+                        status.lock().unwrap().source_read(path, state.offset, state.lines.line_number());
+                        records.push((path.clone(), state.assemble(new_lines)));
+                    }
+                    Err(e) => {
+                        warn!("Failed to read: {}", e);
+                        status.lock().unwrap().source_failed(path, e.to_string());
+                    }
+                }
+            }
         }
-    };
+
+        EventKind::Create(CreateKind::File) => {
+            for path in &event.paths {
+                debug!(path = %path.display(), "File created");
+            }
+        }
+
+        _ => {
+            // ignore other events
+        }
+    }
+    records
+}
+
+/// Returns unterminated lines and multi-line records whose flush timeout has passed
+fn flush_expired_records(states: &mut HashMap<PathBuf, TailState>) -> Vec<(PathBuf, Vec<LogRecord>)> {
+    let now = Instant::now();
+    states.values_mut()
+        .map(|state| (state.path.clone(), state.flush_expired(now)))
+        .filter(|(_, records)| !records.is_empty())
+        .collect()
+}
+
+/// Reads new data appended to a file since the last read, updates state, and returns the completed lines.
+/// A trailing line without its newline is held back in the tail state until the rest arrives.
+/// Errors are returned to the caller so they show up in the source status.
+fn tail_new_data(state: &mut TailState) -> std::io::Result<Vec<LogRecord>> {
+
+    let mut file = File::open(&state.path)?;
+    let len = file.metadata()?.len();
 
     // If file was truncated, reset offset and line number
     if len < state.offset {
+        info!(path = %state.path.display(), "File truncated, reading from the start");
         state.offset = 0;
-        state.line_number = 0;
+        state.lines.reset();
+        state.parser.flush();
+        if let Some(assembler) = state.assembler.as_mut() {
+            assembler.flush();
+        }
     }
 
     file.seek(SeekFrom::Start(state.offset))?;
 
-    let mut buf = String::new();
-    if let Err(e) = file.read_to_string(&mut buf) {
-        eprintln!("Failed to read {}: {}", state.path.display(), e);
-        return Ok(String::new());
-    }
-
-    state.offset = len;
-
-    // Add line numbers to each new line
-    let mut numbered_buf = String::new();
-    for line in buf.lines() {
-        state.line_number += 1;
-        numbered_buf.push_str(&format!("{}: {}\n", state.line_number, line));
+    // Read up to the length seen above in bounded chunks; data appended meanwhile comes with the next event
+    let mut lines = Vec::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    while state.offset < len {
+        let want = usize::try_from(len - state.offset).map_or(READ_BUFFER_SIZE, |n| n.min(READ_BUFFER_SIZE));
+        let read = file.read(&mut buf[..want])?;
+        if read == 0 {
+            break;
+        }
+        state.offset += read as u64;
+        lines.extend(state.lines.push(&buf[..read]));
     }
 
-    Ok(numbered_buf)
+    Ok(lines)
 }
 
-/// Sends all existing lines of a log file to the broadcast channel in batches, returns total lines sent
-pub fn send_old_log_lines(log_path: &Path, log_tx: &broadcast::Sender<WsEventTx>)-> usize {
+/// Sends all existing records of a log file to the broadcast channel in batches, returns total lines read
+pub fn send_old_log_lines(log_path: &Path, log_tx: &broadcast::Sender<WsEventTx>, rules: &SourceRules) -> usize {
 
-    let contents = match fs::read_to_string(log_path) {
+    let options = rules.options_for(log_path);
+    let contents = match load_log_contents(log_path, &options) {
         Ok(c) => c,
         Err(e) => {
-            eprintln!("Failed to read {}: {}", log_path.display(), e);
+            warn!(path = %log_path.display(), "Failed to read: {}", e);
             return 0;
         }
     };
 
     // Pretty-print JSON files, otherwise use raw contents
-    let text = if log_path.extension().and_then(|e| e.to_str()) == Some("json") {
+    let text = if is_json_document(log_path, &options) {
         let v: Value = serde_json::from_str(&contents).unwrap_or_default();
         serde_json::to_string_pretty(&v).unwrap_or_default()
     } else {
         contents
     };
 
-    let mut keep_line_nr = 0;
+    let keep_line_nr = text.lines().count();
     let mut batch = Vec::with_capacity(200);
 
-    // Send lines in batches of 200
-    for (i, line) in text.lines().enumerate() {
-        batch.push(format!("{}: {}", i + 1, line));
-        keep_line_nr = i + 1;
+    // Send records in batches of 200
+    let name = log_path.to_string_lossy();
+    for record in options.records(&text) {
+        batch.push(record);
 
         if batch.len() == 200 {
-            let _ = log_tx.send(WsEventTx::LogBatch {
-                path: log_path.to_string_lossy().to_string(),
-                lines: batch,
-            });
-            batch = Vec::with_capacity(200);
+            let _ = log_tx.send(WsEventTx::log_batch(&name, &batch));
+            batch.clear();
         }
     }
 
     // Send remaining lines
     if !batch.is_empty() {
-        let _ = log_tx.send(WsEventTx::LogBatch {
-            path: log_path.to_string_lossy().to_string(),
-            lines: batch,
-        });
+        let _ = log_tx.send(WsEventTx::log_batch(&name, &batch));
     }
-    return keep_line_nr;
+    keep_line_nr
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(records: &[LogRecord]) -> Vec<(usize, &str)> {
+        records.iter().map(|record| (record.line_number, record.text.as_str())).collect()
+    }
+
+    #[test]
+    fn rotated_sources_read_renamed_files_before_the_new_one() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-rename-create", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "old 1\nold 2").unwrap();
+
+        // logrotate's default: rename the file away and create a new one
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        fs::write(&path, "new 1\n").unwrap();
+
+        let options = SourceOptions { rotated: true, ..SourceOptions::default() };
+        assert_eq!(load_log_contents(&path, &options).unwrap(), "old 1\nold 2\nnew 1\n");
+        assert_eq!(load_log_contents(&path, &SourceOptions::default()).unwrap(), "new 1\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tailing_restarts_after_copytruncate() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-copytruncate", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "old 1\n").unwrap();
+
+        let mut state = TailState::new(path.clone(), 0, 0, &SourceRules::default());
+        assert_eq!(texts(&tail_new_data(&mut state).unwrap()), [(1, "old 1")]);
+        fs::write(&path, "old 1\nold 2\n").unwrap();
+        assert_eq!(texts(&tail_new_data(&mut state).unwrap()), [(2, "old 2")]);
+
+        // Copy the file away and truncate it in place; the next write is shorter than the offset
+        fs::copy(&path, dir.join("app.log.1")).unwrap();
+        fs::write(&path, "").unwrap();
+        fs::write(&path, "new\n").unwrap();
+        assert_eq!(texts(&tail_new_data(&mut state).unwrap()), [(1, "new")]);
+        assert_eq!(state.offset, 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
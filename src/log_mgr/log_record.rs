@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+/// A log record: a single line, or several lines when multi-line grouping is configured
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Line number (1-based) of the first line of the record in its source
+    pub line_number: usize,
+    /// Text of the record; continuation lines are joined with `\n`
+    pub text: String,
+    /// Source the record came from, for records of a merged timeline
+    pub origin: Option<String>,
+    /// Fields the source format carries besides the text, e.g. the stream of a container log
+    pub fields: BTreeMap<String, String>,
+}
+
+impl LogRecord {
+    pub fn new(line_number: usize, text: impl Into<String>) -> LogRecord {
+        LogRecord { line_number, text: text.into(), origin: None, fields: BTreeMap::new() }
+    }
+
+    /// Adds a field, e.g. `stream` or `unit`
+    pub fn with_field(mut self, name: &str, value: impl Into<String>) -> LogRecord {
+        self.fields.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// What a search or filter on `field` looks at: the field's value (none if the record
+    /// lacks it), or the whole text without a field
+    pub fn matched_text(&self, field: Option<&str>) -> Option<&str> {
+        match field {
+            Some(field) => self.fields.get(field).map(String::as_str),
+            None => Some(&self.text),
+        }
+    }
+
+    /// Tags the record with the source it came from
+    pub fn with_origin(mut self, origin: impl Into<String>) -> LogRecord {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Formats the record as `"<line number>: <text>"`, the form sent to clients,
+    /// preceded by `[<origin>] ` for records of a merged timeline
+    pub fn numbered(&self) -> String {
+        match &self.origin {
+            Some(origin) => format!("[{}] {}: {}", origin, self.line_number, self.text),
+            None => format!("{}: {}", self.line_number, self.text),
+        }
+    }
+}
+
+/// Splits text into one record per line, numbered from 1
+pub fn line_records(text: &str) -> Vec<LogRecord> {
+    text.lines()
+        .enumerate()
+        .map(|(idx, line)| LogRecord::new(idx + 1, line))
+        .collect()
+}
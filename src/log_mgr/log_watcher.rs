@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::broadcast;
+use crate::log_mgr::diagnostics::StatusData;
+use crate::log_mgr::log_context_data::LogContextData;
+use crate::log_mgr::log_monitoring::{start_watcher_manager, WatchCommand};
+use crate::log_mgr::rust_server::WsEventTx;
+use crate::log_mgr::source_rules::SourceRules;
+
+/// Capacity of the event channel; slow subscribers that fall further behind miss events
+pub const EVENT_CHANNEL_CAPACITY: usize = 8192;
+
+/// Handle to a running watcher manager: watch files and receive their lines as events.
+///
+/// Subscribe before calling [`watch`](LogWatcher::watch) to also receive the existing lines
+/// of a file (sent as `LogBatch`); new lines arrive as `Log` events, filtered and with
+/// notifications applied by the shared [`LogContextData`]. Dropping the handle stops the
+/// watcher thread.
+pub struct LogWatcher {
+    pub(crate) cmd_tx: Sender<WatchCommand>,
+    pub(crate) log_tx: broadcast::Sender<WsEventTx>,
+    pub(crate) context: Arc<Mutex<LogContextData>>,
+    pub(crate) status: Arc<Mutex<StatusData>>,
+    pub(crate) rules: Arc<SourceRules>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl LogWatcher {
+    /// Starts the watcher manager thread with an empty filter/notification context
+    pub fn start() -> LogWatcher {
+        LogWatcher::with_context(Arc::new(Mutex::new(LogContextData::new())))
+    }
+
+    /// Starts the watcher manager thread using an existing filter/notification context
+    pub fn with_context(context: Arc<Mutex<LogContextData>>) -> LogWatcher {
+        LogWatcher::with_source_rules(context, Arc::new(SourceRules::default()))
+    }
+
+    /// Starts the watcher manager thread with per-source rules (e.g. multi-line grouping)
+    pub fn with_source_rules(context: Arc<Mutex<LogContextData>>, rules: Arc<SourceRules>) -> LogWatcher {
+        let (cmd_tx, cmd_rx) = std::sync::mpsc::channel();
+        let (log_tx, _log_rx) = broadcast::channel::<WsEventTx>(EVENT_CHANNEL_CAPACITY);
+        let status = Arc::new(Mutex::new(StatusData::default()));
+        context.lock().unwrap().set_waker(cmd_tx.clone());
+
+        let thread = start_watcher_manager(
+            cmd_tx.clone(),
+            cmd_rx,
+            log_tx.clone(),
+            Arc::clone(&context),
+            Arc::clone(&status),
+            Arc::clone(&rules),
+        );
+
+        LogWatcher { cmd_tx, log_tx, context, status, rules, thread: Some(thread) }
+    }
+
+    /// Starts following a file (existing lines are sent first)
+    pub fn watch(&self, path: impl Into<PathBuf>) {
+        let _ = self.cmd_tx.send(WatchCommand::Add(path.into()));
+    }
+
+    /// Stops following a file
+    pub fn unwatch(&self, path: impl Into<PathBuf>) {
+        let _ = self.cmd_tx.send(WatchCommand::Remove(path.into()));
+    }
+
+    /// Returns a receiver for all events produced from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<WsEventTx> {
+        self.log_tx.subscribe()
+    }
+
+    /// The filter/notification engine applied to every new line
+    pub fn context(&self) -> &Arc<Mutex<LogContextData>> {
+        &self.context
+    }
+
+    /// Per-source rules used when reading files
+    pub fn source_rules(&self) -> &Arc<SourceRules> {
+        &self.rules
+    }
+
+    /// Stops the watcher thread and waits until all file watchers are released
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.cmd_tx.send(WatchCommand::Shutdown);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for LogWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    // Waits up to five seconds for an event the test is looking for
+    fn wait_for<T>(rx: &mut broadcast::Receiver<WsEventTx>, mut found: impl FnMut(WsEventTx) -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match rx.try_recv() {
+                Ok(event) => {
+                    if let Some(value) = found(event) {
+                        return Some(value);
+                    }
+                }
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn appended_lines_are_sent_and_shutdown_releases_the_watcher() {
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-watcher.log", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let watcher = LogWatcher::start();
+        let mut rx = watcher.subscribe();
+        watcher.watch(&path);
+
+        let history = wait_for(&mut rx, |event| match event {
+            WsEventTx::LogBatch { lines, .. } => Some(lines),
+            _ => None,
+        });
+        assert_eq!(history.unwrap(), ["1: first"]);
+
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"second\n").unwrap();
+        let line = wait_for(&mut rx, |event| match event {
+            WsEventTx::Log { line, .. } => Some(line),
+            _ => None,
+        });
+        assert_eq!(line.unwrap(), "2: second");
+
+        // The event-driven loop stops at once, and nothing is tailed afterwards
+        let started = Instant::now();
+        watcher.shutdown();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"third\n").unwrap();
+        thread::sleep(Duration::from_millis(300));
+        assert!(!matches!(rx.try_recv(), Ok(WsEventTx::Log { .. })));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use glob::Pattern;
+use regex::{Captures, Regex};
+use tracing::{debug, warn};
+use crate::log_mgr::config::{MetricConfig, MetricKind};
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The values of one label combination
+#[derive(Debug, Clone)]
+enum Series {
+    Counter(u64),
+    Histogram { buckets: Vec<u64>, sum: f64, count: u64 },
+}
+
+/// A configured metric and its series, keyed by label values in label name order
+#[derive(Debug)]
+struct Metric {
+    name: String,
+    help: String,
+    kind: MetricKind,
+    regex: Regex,
+    sources: Vec<Pattern>,
+    // Label names with the capture group providing each, sorted by label name
+    labels: Vec<(String, String)>,
+    source_label: Option<String>,
+    value: Option<String>,
+    buckets: Vec<f64>,
+    max_series: usize,
+    series: BTreeMap<Vec<String>, Series>,
+    // Whether reaching `max_series` has been reported
+    capped: bool,
+}
+
+impl Metric {
+    fn new(config: &MetricConfig) -> Result<Metric, String> {
+        let fail = |message: String| format!("metric {}: {}", config.name, message);
+        if !is_metric_name(&config.name) {
+            return Err(fail(String::from("invalid name")));
+        }
+        let regex = Regex::new(&config.pattern).map_err(|e| fail(format!("invalid regex: {}", e)))?;
+        let has_group = |group: &str| regex.capture_names().flatten().any(|name| name == group);
+        let sources = config.sources.iter()
+            .map(|glob| Pattern::new(glob).map_err(|e| fail(format!("invalid source glob {}: {}", glob, e))))
+            .collect::<Result<_, _>>()?;
+
+        let mut labels: Vec<(String, String)> = config.labels.iter()
+            .map(|(label, group)| (label.clone(), group.clone()))
+            .collect();
+        labels.sort();
+        for (label, group) in &labels {
+            if !is_label_name(label) || label == "le" {
+                return Err(fail(format!("invalid label name {}", label)));
+            }
+            if !has_group(group) {
+                return Err(fail(format!("pattern has no group named {}", group)));
+            }
+        }
+        if let Some(label) = &config.source_label
+            && (!is_label_name(label) || label == "le" || config.labels.contains_key(label)) {
+            return Err(fail(format!("invalid source label {}", label)));
+        }
+
+        let mut buckets: Vec<f64> = config.buckets.iter().copied().filter(|bound| bound.is_finite()).collect();
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        match (config.kind, &config.value) {
+            (MetricKind::Histogram, None) => return Err(fail(String::from("a histogram needs a value group"))),
+            (MetricKind::Histogram, Some(group)) if !has_group(group) => {
+                return Err(fail(format!("pattern has no group named {}", group)));
+            }
+            _ => {}
+        }
+
+        let mut metric = Metric {
+            name: config.name.clone(),
+            help: config.help.clone(),
+            kind: config.kind,
+            regex,
+            sources,
+            labels,
+            source_label: config.source_label.clone(),
+            value: config.value.clone(),
+            buckets,
+            max_series: config.max_series.max(1),
+            series: BTreeMap::new(),
+            capped: false,
+        };
+        // Without labels the metric has a single series, shown from the start
+        if metric.labels.is_empty() && metric.source_label.is_none() {
+            metric.series.insert(Vec::new(), metric.empty_series());
+        }
+        Ok(metric)
+    }
+
+    fn empty_series(&self) -> Series {
+        match self.kind {
+            MetricKind::Counter => Series::Counter(0),
+            MetricKind::Histogram => Series::Histogram { buckets: vec![0; self.buckets.len()], sum: 0.0, count: 0 },
+        }
+    }
+
+    fn observe(&mut self, path: &Path, content: &str) {
+        if !self.sources.is_empty() && !self.sources.iter().any(|glob| glob.matches_path(path)) {
+            return;
+        }
+        let Some(captures) = self.regex.captures(content) else {
+            return;
+        };
+        let value = match &self.value {
+            Some(group) => match group_text(&captures, group).trim().parse::<f64>() {
+                Ok(value) if value.is_finite() => value,
+                _ => {
+                    debug!(metric = %self.name, "Skipping record without a numeric {}", group);
+                    return;
+                }
+            },
+            None => 0.0,
+        };
+        let key = self.key(path, &captures);
+
+        if !self.series.contains_key(&key) {
+            if self.series.len() >= self.max_series {
+                if !self.capped {
+                    warn!(metric = %self.name, "Reached {} series, ignoring new label values", self.max_series);
+                    self.capped = true;
+                }
+                return;
+            }
+            let series = self.empty_series();
+            self.series.insert(key.clone(), series);
+        }
+        match self.series.get_mut(&key) {
+            Some(Series::Counter(count)) => *count += 1,
+            Some(Series::Histogram { buckets, sum, count }) => {
+                for (bucket, bound) in buckets.iter_mut().zip(&self.buckets) {
+                    if value <= *bound {
+                        *bucket += 1;
+                    }
+                }
+                *sum += value;
+                *count += 1;
+            }
+            None => {}
+        }
+    }
+
+    // Label values of a record, in the order of `label_names`
+    fn key(&self, path: &Path, captures: &Captures) -> Vec<String> {
+        let mut key: Vec<String> = self.labels.iter()
+            .map(|(_, group)| group_text(captures, group).to_string())
+            .collect();
+        if self.source_label.is_some() {
+            key.push(path.to_string_lossy().to_string());
+        }
+        key
+    }
+
+    fn label_names(&self) -> Vec<&str> {
+        self.labels.iter()
+            .map(|(label, _)| label.as_str())
+            .chain(self.source_label.as_deref())
+            .collect()
+    }
+
+    fn render(&self, out: &mut String) {
+        let names = self.label_names();
+        if !self.help.is_empty() {
+            let _ = writeln!(out, "# HELP {} {}", self.name, self.help.replace('\\', "\\\\").replace('\n', "\\n"));
+        }
+        let kind = match self.kind {
+            MetricKind::Counter => "counter",
+            MetricKind::Histogram => "histogram",
+        };
+        let _ = writeln!(out, "# TYPE {} {}", self.name, kind);
+
+        for (values, series) in &self.series {
+            let labels: Vec<(&str, String)> = names.iter().copied().zip(values.iter().cloned()).collect();
+            match series {
+                Series::Counter(count) => {
+                    let _ = writeln!(out, "{}{} {}", self.name, label_set(&labels), count);
+                }
+                Series::Histogram { buckets, sum, count } => {
+                    for (bound, bucket) in self.buckets.iter().zip(buckets) {
+                        let le = [labels.clone(), vec![("le", bound.to_string())]].concat();
+                        let _ = writeln!(out, "{}_bucket{} {}", self.name, label_set(&le), bucket);
+                    }
+                    let le = [labels.clone(), vec![("le", String::from("+Inf"))]].concat();
+                    let _ = writeln!(out, "{}_bucket{} {}", self.name, label_set(&le), count);
+                    let _ = writeln!(out, "{}_sum{} {}", self.name, label_set(&labels), sum);
+                    let _ = writeln!(out, "{}_count{} {}", self.name, label_set(&labels), count);
+                }
+            }
+        }
+    }
+}
+
+/// Counters and histograms computed from the records of the watched sources, for Prometheus.
+///
+/// Each metric counts the records matching its regex; named capture groups provide label
+/// values (e.g. the level of an Apache error log) and, for histograms, the observed number
+/// (e.g. a latency).
+#[derive(Debug, Default)]
+pub struct LogMetrics {
+    metrics: Vec<Metric>,
+}
+
+impl LogMetrics {
+    /// Compiles the configured metrics, skipping (and reporting) invalid ones
+    pub fn from_config(configs: &[MetricConfig]) -> LogMetrics {
+        let mut metrics: Vec<Metric> = Vec::new();
+        for config in configs {
+            if metrics.iter().any(|metric| metric.name == config.name) {
+                warn!("Ignoring metric {}: defined twice", config.name);
+                continue;
+            }
+            match Metric::new(config) {
+                Ok(metric) => metrics.push(metric),
+                Err(e) => warn!("Ignoring metric: {}", e),
+            }
+        }
+        LogMetrics { metrics }
+    }
+
+    /// Counts the content of a record of a source
+    pub fn observe(&mut self, path: &Path, content: &str) {
+        for metric in &mut self.metrics {
+            metric.observe(path, content);
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for metric in &self.metrics {
+            metric.render(&mut out);
+        }
+        out
+    }
+}
+
+// The text of a named group; empty when it did not take part in the match
+fn group_text<'a>(captures: &Captures<'a>, group: &str) -> &'a str {
+    captures.name(group).map(|m| m.as_str()).unwrap_or_default()
+}
+
+// `{name="value",...}` with escaped values, or nothing without labels
+fn label_set(labels: &[(&str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels.iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn metrics(configs: serde_json::Value) -> LogMetrics {
+        LogMetrics::from_config(&serde_json::from_value::<Vec<MetricConfig>>(configs).unwrap())
+    }
+
+    #[test]
+    fn counters_count_per_label_value() {
+        let mut metrics = metrics(json!([
+            { "name": "app_lines_total", "help": "Lines\nby level", "pattern": r"^(?P<level>[A-Z]+) ",
+              "labels": { "level": "level" }, "source_label": "source", "sources": ["/var/log/*.log"] },
+            { "name": "app_errors_total", "pattern": "ERROR" },
+        ]));
+        assert!(metrics.render().contains("app_errors_total 0\n"));
+
+        let app = Path::new("/var/log/app.log");
+        for line in ["ERROR failed", "INFO ok", "ERROR again", "no level"] {
+            metrics.observe(app, line);
+        }
+        metrics.observe(Path::new("/tmp/other.log"), "WARN \"quoted\\\" source");
+        metrics.observe(Path::new("/var/log/q\"a.log"), "WARN odd path");
+
+        let text = metrics.render();
+        assert!(text.contains("# HELP app_lines_total Lines\\nby level\n# TYPE app_lines_total counter\n"));
+        assert!(text.contains("app_lines_total{level=\"ERROR\",source=\"/var/log/app.log\"} 2\n"));
+        assert!(text.contains("app_lines_total{level=\"INFO\",source=\"/var/log/app.log\"} 1\n"));
+        assert!(text.contains("app_lines_total{level=\"WARN\",source=\"/var/log/q\\\"a.log\"} 1\n"));
+        assert!(!text.contains("/tmp/other.log"));
+        assert!(text.contains("app_errors_total 2\n"));
+    }
+
+    #[test]
+    fn histograms_observe_the_value_group() {
+        let mut metrics = metrics(json!([
+            { "name": "request_seconds", "type": "histogram", "pattern": r"took (?P<secs>\S+)s",
+              "value": "secs", "buckets": [1.0, 0.1, f64::MAX, 0.1] },
+        ]));
+        for line in ["took 0.05s", "took 0.5s", "took 3s", "took NaNs", "took fast"] {
+            metrics.observe(Path::new("app.log"), line);
+        }
+
+        let text = metrics.render();
+        assert!(text.contains("request_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("request_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(text.contains("request_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("request_seconds_sum 3.55\n"));
+        assert!(text.contains("request_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn series_are_capped() {
+        let mut metrics = metrics(json!([
+            { "name": "users_total", "pattern": r"user=(?P<user>\w+)", "labels": { "user": "user" }, "max_series": 2 },
+        ]));
+        for user in ["a", "b", "c", "a"] {
+            metrics.observe(Path::new("app.log"), &format!("user={}", user));
+        }
+
+        let text = metrics.render();
+        assert!(text.contains("users_total{user=\"a\"} 2\n"));
+        assert!(text.contains("users_total{user=\"b\"} 1\n"));
+        assert!(!text.contains("user=\"c\""));
+    }
+
+    #[test]
+    fn invalid_metrics_are_skipped() {
+        let metrics = metrics(json!([
+            { "name": "ok_total", "pattern": "x" },
+            { "name": "ok_total", "pattern": "y" },
+            { "name": "1bad", "pattern": "x" },
+            { "name": "bad_regex", "pattern": "(" },
+            { "name": "bad_label", "pattern": "(?P<g>x)", "labels": { "le": "g" } },
+            { "name": "missing_group", "pattern": "x", "labels": { "level": "level" } },
+            { "name": "clashing_source", "pattern": "(?P<g>x)", "labels": { "source": "g" }, "source_label": "source" },
+            { "name": "no_value", "type": "histogram", "pattern": "x" },
+        ]));
+        assert_eq!(metrics.metrics.len(), 1);
+        assert_eq!(metrics.metrics[0].regex.as_str(), "x");
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use regex::Regex;
+use crate::log_mgr::config::{MultilineConfig, MultilineMode};
+use crate::log_mgr::log_record::LogRecord;
+
+/// Timestamps recognised at the start of a record in `timestamp` mode:
+/// ISO 8601, syslog (`Nov 16 16:12:26`), Apache error log (`[Sun Dec 04 04:47:44 2005]`),
+/// Apache access log (`[10/Oct/2000:13:55:36`) and bare `HH:MM:SS`
+static TIMESTAMP_START: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^\[?(",
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}",
+        r"|[A-Z][a-z]{2} +\d{1,2} \d{2}:\d{2}:\d{2}",
+        r"|[A-Z][a-z]{2} [A-Z][a-z]{2} +\d{1,2} \d{2}:\d{2}:\d{2}",
+        r"|\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}",
+        r"|\d{2}:\d{2}:\d{2}",
+        r")",
+    ))
+        .unwrap()
+});
+
+/// How the first line of a record is recognised
+#[derive(Debug, Clone)]
+enum RecordStart {
+    /// Lines matching the regex start a record
+    Pattern(Regex),
+    /// Lines not starting with whitespace start a record
+    Indent,
+    /// Lines starting with a timestamp start a record
+    Timestamp,
+}
+
+/// Compiled multi-line grouping settings for a source
+#[derive(Debug, Clone)]
+pub struct MultilineRule {
+    start: RecordStart,
+    /// Records are cut after this many lines
+    pub max_lines: usize,
+    /// A pending record is emitted when no line arrived for this long while tailing
+    pub flush_timeout: Duration,
+}
+
+impl MultilineRule {
+    pub fn from_config(config: &MultilineConfig) -> Result<MultilineRule, String> {
+        let start = match &config.mode {
+            MultilineMode::StartPattern { pattern } => RecordStart::Pattern(
+                Regex::new(pattern).map_err(|e| format!("invalid multiline pattern {}: {}", pattern, e))?,
+            ),
+            MultilineMode::Indent => RecordStart::Indent,
+            MultilineMode::Timestamp => RecordStart::Timestamp,
+        };
+
+        Ok(MultilineRule {
+            start,
+            max_lines: config.max_lines.max(1),
+            flush_timeout: Duration::from_millis(config.flush_timeout_ms),
+        })
+    }
+
+    /// Whether `line` begins a new record rather than continuing the previous one
+    pub fn starts_record(&self, line: &str) -> bool {
+        match &self.start {
+            RecordStart::Pattern(re) => re.is_match(line),
+            RecordStart::Indent => !line.is_empty() && !line.starts_with([' ', '\t']),
+            RecordStart::Timestamp => TIMESTAMP_START.is_match(line),
+        }
+    }
+}
+
+/// Groups a stream of lines into records according to a rule
+#[derive(Debug)]
+pub struct RecordAssembler {
+    rule: MultilineRule,
+    pending: Option<LogRecord>,
+    pending_lines: usize,
+    last_line_at: Instant,
+}
+
+impl RecordAssembler {
+    pub fn new(rule: MultilineRule) -> RecordAssembler {
+        RecordAssembler { rule, pending: None, pending_lines: 0, last_line_at: Instant::now() }
+    }
+
+    /// Adds a line; returns the previous record if this line completed it
+    pub fn push(&mut self, line_number: usize, line: &str) -> Option<LogRecord> {
+        self.last_line_at = Instant::now();
+
+        if let Some(pending) = self.pending.as_mut()
+            && !self.rule.starts_record(line)
+            && self.pending_lines < self.rule.max_lines {
+            pending.text.push('\n');
+            pending.text.push_str(line);
+            self.pending_lines += 1;
+            return None;
+        }
+
+        self.pending_lines = 1;
+        self.pending.replace(LogRecord::new(line_number, line))
+    }
+
+    /// Returns the pending record, if any
+    pub fn flush(&mut self) -> Option<LogRecord> {
+        self.pending_lines = 0;
+        self.pending.take()
+    }
+
+    /// When the pending record should be flushed if no further line arrives
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|_| self.last_line_at + self.rule.flush_timeout)
+    }
+}
+
+/// Splits complete text into records, one per line when no rule is given
+pub fn group_records(rule: Option<&MultilineRule>, lines: Vec<LogRecord>) -> Vec<LogRecord> {
+    let Some(rule) = rule else {
+        return lines;
+    };
+
+    let mut assembler = RecordAssembler::new(rule.clone());
+    let mut records = Vec::new();
+    for line in lines {
+        if let Some(record) = assembler.push(line.line_number, &line.text) {
+            records.push(record);
+        }
+    }
+    records.extend(assembler.flush());
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(mode: MultilineMode, max_lines: usize) -> MultilineRule {
+        MultilineRule::from_config(&MultilineConfig { mode, max_lines, flush_timeout_ms: 500 }).unwrap()
+    }
+
+    fn lines(texts: &[&str]) -> Vec<LogRecord> {
+        texts.iter().enumerate().map(|(idx, text)| LogRecord::new(idx + 1, *text)).collect()
+    }
+
+    fn texts(records: &[LogRecord]) -> Vec<(usize, &str)> {
+        records.iter().map(|record| (record.line_number, record.text.as_str())).collect()
+    }
+
+    #[test]
+    fn indented_lines_continue_the_record() {
+        let records = group_records(Some(&rule(MultilineMode::Indent, 500)), lines(&[
+            "Exception in thread main",
+            "    at Foo.bar(Foo.java:10)",
+            "\tat Foo.main(Foo.java:3)",
+            "next record",
+        ]));
+        assert_eq!(texts(&records), [
+            (1, "Exception in thread main\n    at Foo.bar(Foo.java:10)\n\tat Foo.main(Foo.java:3)"),
+            (4, "next record"),
+        ]);
+    }
+
+    #[test]
+    fn start_pattern_and_timestamps_begin_records() {
+        let pattern = rule(MultilineMode::StartPattern { pattern: String::from(r"^\d{4}-") }, 500);
+        let input = ["2024-05-01 ERROR failed", "Traceback:", "  File x", "2024-05-01 INFO ok"];
+        assert_eq!(group_records(Some(&pattern), lines(&input)).len(), 2);
+
+        let timestamp = rule(MultilineMode::Timestamp, 500);
+        assert!(timestamp.starts_record("Nov 16 16:12:26 host app: started"));
+        assert!(timestamp.starts_record("[Sun Dec 04 04:47:44 2005] [notice] ok"));
+        assert!(timestamp.starts_record("10:00:00 ready"));
+        assert!(!timestamp.starts_record("caused by: timeout"));
+        assert!(MultilineRule::from_config(&MultilineConfig {
+            mode: MultilineMode::StartPattern { pattern: String::from("(") },
+            max_lines: 1,
+            flush_timeout_ms: 0,
+        }).is_err());
+    }
+
+    #[test]
+    fn records_are_cut_at_max_lines() {
+        let records = group_records(Some(&rule(MultilineMode::Indent, 2)), lines(&["start", " a", " b", " c"]));
+        assert_eq!(texts(&records), [(1, "start\n a"), (3, " b\n c")]);
+        assert_eq!(group_records(None, lines(&["a", " b"])).len(), 2);
+    }
+
+    #[test]
+    fn pending_record_is_flushed_after_the_timeout() {
+        let mut assembler = RecordAssembler::new(rule(MultilineMode::Indent, 500));
+        assert!(assembler.deadline().is_none());
+        let before = Instant::now();
+        assert!(assembler.push(1, "error").is_none());
+        assert!(assembler.push(2, "  detail").is_none());
+        let deadline = assembler.deadline().unwrap();
+        assert!(deadline >= before + Duration::from_millis(500));
+        assert!(deadline <= Instant::now() + Duration::from_millis(500));
+
+        let record = assembler.flush().unwrap();
+        assert_eq!((record.line_number, record.text.as_str()), (1, "error\n  detail"));
+        assert!(assembler.deadline().is_none());
+        // A continuation after the flush starts a record of its own
+        assert!(assembler.push(3, "  late").is_none());
+        assert_eq!(assembler.flush().unwrap().text, "  late");
+    }
+}
@@ -0,0 +1,843 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::log_mgr::config::{AbsenceConfig, AnomalyConfig, NewPatternConfig, NotificationRuleConfig, RatioConfig, ThresholdConfig};
+use crate::log_mgr::timeline;
+use crate::log_mgr::timestamp;
+
+/// Cooldown of rules set from the dashboard without one
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Longest cooldown, window or quiet period a rule accepts
+pub const MAX_RULE_DURATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Shortest window an anomaly rule counts in
+pub const MIN_ANOMALY_WINDOW: Duration = Duration::from_secs(1);
+
+/// Most groups tracked at once; expired ones are dropped when there are more
+const MAX_GROUPS: usize = 10_000;
+
+/// Digit runs, replaced when grouping messages so timestamps and ids don't split a group
+static DIGITS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+").unwrap());
+
+/// How urgent a notification is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// How a rule recognises records
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// Case-insensitive substring, stored lowercase
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// A case-insensitive substring, or a regex when `regex` is set
+    pub(crate) fn new(pattern: &str, regex: bool) -> Result<Matcher, regex::Error> {
+        Ok(if regex {
+            Matcher::Regex(Regex::new(pattern)?)
+        } else {
+            Matcher::Substring(pattern.to_lowercase())
+        })
+    }
+
+    pub(crate) fn matches(&self, content: &str) -> bool {
+        match self {
+            Matcher::Substring(needle) => content.to_lowercase().contains(needle),
+            Matcher::Regex(regex) => regex.is_match(content),
+        }
+    }
+}
+
+/// When a rule raises a notification
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// Every matching record (rate limited by the cooldown)
+    Match,
+    /// More than `count` matching records within `window`
+    Threshold { count: u64, window: Duration },
+    /// More than `percent` of the records within `window` match; only records matching `of`
+    /// are counted when it is set, and fewer than `min_records` never alert
+    Ratio { percent: f64, window: Duration, of: Option<Matcher>, min_records: u64 },
+    /// No matching record for `within`
+    Absence { within: Duration },
+    /// A matching record of a message template not seen during the first `learning`
+    NewPattern { learning: Duration },
+    /// The count of a template (or of all matching records) in a `window` is more than `factor`
+    /// times its baseline and at least `min_count`, or below the baseline divided by `factor`,
+    /// once `learning_windows` windows were counted
+    Anomaly { window: Duration, factor: f64, min_count: u64, learning_windows: u32 },
+}
+
+/// A notification rule: which records of which sources raise a notification, and how often
+#[derive(Debug, Clone)]
+pub struct NotificationRule {
+    pub id: String,
+    pub severity: Severity,
+    pub cooldown: Duration,
+    pub trigger: Trigger,
+    /// Names of the sinks notifications are also sent to
+    pub sinks: Vec<String>,
+    pattern: String,
+    matcher: Matcher,
+    // Globs of the sources the rule applies to; empty for all
+    sources: Vec<Pattern>,
+}
+
+impl NotificationRule {
+    /// A rule matching `pattern` as a case-insensitive substring, or as a regex
+    pub fn new(id: impl Into<String>, pattern: &str, regex: bool) -> Result<NotificationRule, String> {
+        let id = id.into();
+        let matcher = Matcher::new(pattern, regex).map_err(|e| format!("rule {}: invalid regex: {}", id, e))?;
+        Ok(NotificationRule {
+            id,
+            severity: Severity::default(),
+            cooldown: DEFAULT_COOLDOWN,
+            trigger: Trigger::Match,
+            sinks: Vec::new(),
+            pattern: pattern.to_string(),
+            matcher,
+            sources: Vec::new(),
+        })
+    }
+
+    /// Compiles a rule from the `notifications` config section
+    pub fn from_config(config: &NotificationRuleConfig) -> Result<NotificationRule, String> {
+        let mut rule = NotificationRule::new(&config.id, &config.pattern, config.regex)?;
+        rule.severity = config.severity;
+        rule.set_cooldown(config.cooldown_ms)?;
+        rule.sinks = config.sinks.clone();
+        rule.sources = config.sources.iter()
+            .map(|glob| Pattern::new(glob).map_err(|e| format!("rule {}: invalid source glob {}: {}", config.id, glob, e)))
+            .collect::<Result<_, _>>()?;
+
+        rule.set_trigger(
+            config.threshold.as_ref(),
+            config.ratio.as_ref(),
+            config.absence.as_ref(),
+            config.new_pattern.as_ref(),
+            config.anomaly.as_ref(),
+        )?;
+        Ok(rule)
+    }
+
+    /// Sets how long identical messages are only counted after a notification
+    pub fn set_cooldown(&mut self, cooldown_ms: u64) -> Result<(), String> {
+        self.cooldown = self.duration("cooldown_ms", cooldown_ms)?;
+        Ok(())
+    }
+
+    // A window of the rule, refused when zero or above MAX_RULE_DURATION
+    fn window(&self, name: &str, ms: u64) -> Result<Duration, String> {
+        if ms == 0 {
+            return Err(format!("rule {}: {} must be above 0", self.id, name));
+        }
+        self.duration(name, ms)
+    }
+
+    // A duration setting of the rule, refused above MAX_RULE_DURATION
+    fn duration(&self, name: &str, ms: u64) -> Result<Duration, String> {
+        let duration = Duration::from_millis(ms);
+        if duration > MAX_RULE_DURATION {
+            return Err(format!("rule {}: {} is above the maximum of {}", self.id, name, MAX_RULE_DURATION.as_millis()));
+        }
+        Ok(duration)
+    }
+
+    /// Turns the rule into a threshold, ratio, absence, new pattern or anomaly rule; a plain
+    /// match rule when all are `None`
+    pub fn set_trigger(
+        &mut self,
+        threshold: Option<&ThresholdConfig>,
+        ratio: Option<&RatioConfig>,
+        absence: Option<&AbsenceConfig>,
+        new_pattern: Option<&NewPatternConfig>,
+        anomaly: Option<&AnomalyConfig>,
+    ) -> Result<(), String> {
+        let set = [threshold.is_some(), ratio.is_some(), absence.is_some(), new_pattern.is_some(), anomaly.is_some()];
+        if set.into_iter().filter(|set| *set).count() > 1 {
+            return Err(format!("rule {}: set only one of threshold, ratio, absence, new_pattern and anomaly", self.id));
+        }
+        let regex = matches!(self.matcher, Matcher::Regex(_));
+        self.trigger = if let Some(threshold) = threshold {
+            Trigger::Threshold {
+                count: threshold.count,
+                window: self.window("threshold.window_ms", threshold.window_ms)?,
+            }
+        } else if let Some(ratio) = ratio {
+            if !(0.0..=100.0).contains(&ratio.percent) {
+                return Err(format!("rule {}: ratio.percent must be between 0 and 100", self.id));
+            }
+            Trigger::Ratio {
+                percent: ratio.percent,
+                window: self.window("ratio.window_ms", ratio.window_ms)?,
+                of: ratio.of.as_deref()
+                    .map(|of| Matcher::new(of, regex))
+                    .transpose()
+                    .map_err(|e| format!("rule {}: invalid regex in ratio.of: {}", self.id, e))?,
+                min_records: ratio.min_records,
+            }
+        } else if let Some(absence) = absence {
+            Trigger::Absence { within: self.window("absence.within_ms", absence.within_ms)? }
+        } else if let Some(new_pattern) = new_pattern {
+            Trigger::NewPattern { learning: self.duration("new_pattern.learning_ms", new_pattern.learning_ms)? }
+        } else if let Some(anomaly) = anomaly {
+            let window = self.duration("anomaly.window_ms", anomaly.window_ms)?;
+            if window < MIN_ANOMALY_WINDOW || anomaly.factor <= 1.0 {
+                return Err(format!(
+                    "rule {}: anomaly needs a window of at least {} ms and a factor above 1",
+                    self.id,
+                    MIN_ANOMALY_WINDOW.as_millis(),
+                ));
+            }
+            Trigger::Anomaly {
+                window,
+                factor: anomaly.factor,
+                min_count: anomaly.min_count,
+                learning_windows: anomaly.learning_windows,
+            }
+        } else {
+            Trigger::Match
+        };
+        Ok(())
+    }
+
+    /// The pattern as it was given
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the rule watches a source. Merged timelines repeat the records of their files, so
+    /// only rules that name a timeline exactly watch it.
+    pub fn applies_to(&self, path: &Path) -> bool {
+        if let Some(name) = path.to_str().filter(|name| timeline::is_merged_name(name)) {
+            return self.sources.iter().any(|glob| glob.as_str() == name);
+        }
+        self.sources.is_empty() || self.sources.iter().any(|glob| glob.matches_path(path))
+    }
+
+    /// Whether a record's content raises this rule
+    pub fn matches(&self, content: &str) -> bool {
+        self.matcher.matches(content)
+    }
+}
+
+/// Compiles the configured rules, skipping (and reporting) invalid ones
+pub fn rules_from_config(configs: &[NotificationRuleConfig]) -> Vec<NotificationRule> {
+    configs.iter()
+        .filter_map(|config| NotificationRule::from_config(config)
+            .inspect_err(|e| warn!("Ignoring notification rule: {}", e))
+            .ok())
+        .collect()
+}
+
+/// `now + duration`, capped at MAX_RULE_DURATION so a deadline never overflows
+pub(crate) fn deadline_after(now: Instant, duration: Duration) -> Instant {
+    now.checked_add(duration.min(MAX_RULE_DURATION)).unwrap_or(now)
+}
+
+/// What a notification reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    /// Matching records
+    Match,
+    /// A window rule crossed its limit
+    Alert,
+    /// A window rule returned below its limit
+    Recovery,
+}
+
+/// A notification to send: the latest matching record and how many matches it stands for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub rule_id: String,
+    pub severity: Severity,
+    pub kind: NotificationKind,
+    pub path: String,
+    pub line: String,
+    /// What crossed the limit, for alerts and recoveries, or the new message pattern
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub count: u64,
+    /// Milliseconds since the Unix epoch of the first and latest match
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+/// Matches of one rule on one source with the same message, while the rule cools down
+#[derive(Debug)]
+struct Group {
+    until: Instant,
+    cooldown: Duration,
+    severity: Severity,
+    line: String,
+    suppressed: u64,
+    first_suppressed: i64,
+    last_seen: i64,
+}
+
+/// Rate limiting and deduplication of notifications.
+///
+/// The first match of a rule on a source is sent at once and starts the rule's cooldown for
+/// that message. Identical messages during the cooldown (ignoring digits, so timestamps and ids
+/// don't count) are only counted; when the cooldown ends they are sent as one notification with
+/// the count, and a new cooldown starts.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    groups: HashMap<(String, String, String), Group>,
+}
+
+impl Notifier {
+    /// Records a match; returns the notification to send now, if any
+    pub fn on_match(&mut self, rule: &NotificationRule, path: &Path, line: &str, content: &str, now: Instant) -> Option<Notification> {
+        let path = path.to_string_lossy().to_string();
+        let key = (rule.id.clone(), path.clone(), DIGITS.replace_all(content, "#").into_owned());
+        let seen = timestamp::now_millis();
+
+        if let Some(group) = self.groups.get_mut(&key) && now < group.until {
+            if group.suppressed == 0 {
+                group.first_suppressed = seen;
+            }
+            group.suppressed += 1;
+            group.last_seen = seen;
+            group.line = line.to_string();
+            return None;
+        }
+
+        if self.groups.len() >= MAX_GROUPS {
+            self.groups.retain(|_, group| now < group.until);
+        }
+        self.groups.insert(key, Group {
+            until: deadline_after(now, rule.cooldown),
+            cooldown: rule.cooldown,
+            severity: rule.severity,
+            line: line.to_string(),
+            suppressed: 0,
+            first_suppressed: seen,
+            last_seen: seen,
+        });
+        Some(Notification {
+            rule_id: rule.id.clone(),
+            severity: rule.severity,
+            kind: NotificationKind::Match,
+            path,
+            line: line.to_string(),
+            message: None,
+            count: 1,
+            first_seen: seen,
+            last_seen: seen,
+        })
+    }
+
+    /// Forgets the cooldowns of a rule on a source, with the matches they counted
+    pub fn remove(&mut self, rule_id: &str, path: &Path) {
+        let path = path.to_string_lossy();
+        self.groups.retain(|(rule, source, _), _| rule != rule_id || *source != path);
+    }
+
+    /// Forgets the cooldowns of a source that is no longer watched
+    pub fn forget_source(&mut self, path: &Path) {
+        let path = path.to_string_lossy();
+        self.groups.retain(|(_, source, _), _| *source != path);
+    }
+
+    /// When the next cooldown with counted matches ends
+    pub fn deadline(&self) -> Option<Instant> {
+        self.groups.values().filter(|group| group.suppressed > 0).map(|group| group.until).min()
+    }
+
+    /// Sends the counted matches of cooldowns that ended and forgets groups that stayed quiet
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        self.groups.retain(|(rule_id, path, _), group| {
+            if now < group.until {
+                return true;
+            }
+            if group.suppressed == 0 {
+                return false;
+            }
+            notifications.push(Notification {
+                rule_id: rule_id.clone(),
+                severity: group.severity,
+                kind: NotificationKind::Match,
+                path: path.clone(),
+                line: group.line.clone(),
+                message: None,
+                count: group.suppressed,
+                first_seen: group.first_suppressed,
+                last_seen: group.last_seen,
+            });
+            group.suppressed = 0;
+            group.until = deadline_after(now, group.cooldown);
+            true
+        });
+        notifications
+    }
+}
+
+/// Number of buckets a window is counted in; counts move on one bucket at a time
+const WINDOW_BUCKETS: u32 = 60;
+
+/// Matching and counted records in one slice of a window
+#[derive(Debug)]
+struct Bucket {
+    start: Instant,
+    hits: u64,
+    total: u64,
+}
+
+/// Counts of matching and counted records over a sliding window, kept in buckets so a busy
+/// source needs constant memory
+#[derive(Debug)]
+struct SlidingWindow {
+    window: Duration,
+    bucket: Duration,
+    buckets: VecDeque<Bucket>,
+    hits: u64,
+    total: u64,
+}
+
+impl SlidingWindow {
+    fn new(window: Duration) -> SlidingWindow {
+        let bucket = (window / WINDOW_BUCKETS).max(Duration::from_millis(100));
+        SlidingWindow { window, bucket, buckets: VecDeque::new(), hits: 0, total: 0 }
+    }
+
+    fn record(&mut self, now: Instant, hit: bool, counted: bool) {
+        self.expire(now);
+        let current = self.buckets.back().is_some_and(|b| now < deadline_after(b.start, self.bucket));
+        if !current {
+            self.buckets.push_back(Bucket { start: now, hits: 0, total: 0 });
+        }
+        if let Some(bucket) = self.buckets.back_mut() {
+            bucket.hits += u64::from(hit);
+            bucket.total += u64::from(counted);
+        }
+        self.hits += u64::from(hit);
+        self.total += u64::from(counted);
+    }
+
+    // Drops the buckets that ended more than a window ago
+    fn expire(&mut self, now: Instant) {
+        while let Some(oldest) = self.buckets.front() && self.end(oldest) <= now {
+            self.hits -= oldest.hits;
+            self.total -= oldest.total;
+            self.buckets.pop_front();
+        }
+    }
+
+    /// When the oldest bucket leaves the window
+    fn next_expiry(&self) -> Option<Instant> {
+        self.buckets.front().map(|oldest| self.end(oldest))
+    }
+
+    // When a bucket leaves the window
+    fn end(&self, bucket: &Bucket) -> Instant {
+        deadline_after(bucket.start, self.bucket.saturating_add(self.window))
+    }
+}
+
+/// Window of one rule on one source and whether it is alerting
+#[derive(Debug)]
+struct WindowState {
+    rule_id: String,
+    severity: Severity,
+    trigger: Trigger,
+    counts: SlidingWindow,
+    firing: bool,
+    line: String,
+    first_seen: i64,
+    last_seen: i64,
+}
+
+impl WindowState {
+    // Whether the counts are above the rule's limit
+    fn above(&self) -> bool {
+        match &self.trigger {
+            Trigger::Match | Trigger::Absence { .. } | Trigger::NewPattern { .. } | Trigger::Anomaly { .. } => false,
+            Trigger::Threshold { count, .. } => self.counts.hits > *count,
+            Trigger::Ratio { percent, min_records, .. } => {
+                self.counts.total >= *min_records && self.counts.hits as f64 * 100.0 > percent * self.counts.total as f64
+            }
+        }
+    }
+
+    // Describes the counts against the limit, e.g. `21 matches in 60s (limit 20)`
+    fn describe(&self) -> String {
+        match &self.trigger {
+            Trigger::Match | Trigger::Absence { .. } | Trigger::NewPattern { .. } | Trigger::Anomaly { .. } => String::new(),
+            Trigger::Threshold { count, window } => {
+                format!("{} matches in {:?} (limit {})", self.counts.hits, window, count)
+            }
+            Trigger::Ratio { window, min_records, .. } if self.counts.total < *min_records => {
+                format!("{} records in {:?} (minimum {})", self.counts.total, window, min_records)
+            }
+            Trigger::Ratio { percent, window, .. } => {
+                let share = if self.counts.total == 0 { 0.0 } else { self.counts.hits as f64 * 100.0 / self.counts.total as f64 };
+                format!("{:.1}% of {} records in {:?} (limit {}%)", share, self.counts.total, window, percent)
+            }
+        }
+    }
+
+    // Sends an alert or a recovery when the counts crossed the limit
+    fn transition(&mut self, path: &str) -> Option<Notification> {
+        let above = self.above();
+        if above == self.firing {
+            return None;
+        }
+        self.firing = above;
+        Some(Notification {
+            rule_id: self.rule_id.clone(),
+            severity: self.severity,
+            kind: if above { NotificationKind::Alert } else { NotificationKind::Recovery },
+            path: path.to_string(),
+            line: self.line.clone(),
+            message: Some(self.describe()),
+            count: self.counts.hits,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        })
+    }
+}
+
+/// Threshold and ratio rules: sliding-window counts per rule and source, raising an alert when
+/// they cross the limit and a recovery when they return below it
+#[derive(Debug, Default)]
+pub struct WindowAlerts {
+    states: HashMap<(String, String), WindowState>,
+}
+
+impl WindowAlerts {
+    /// Counts a record of a source the rule applies to; returns an alert or recovery it caused
+    pub fn on_record(&mut self, rule: &NotificationRule, path: &Path, line: &str, content: &str, now: Instant) -> Option<Notification> {
+        let (window, of) = match &rule.trigger {
+            Trigger::Match | Trigger::Absence { .. } | Trigger::NewPattern { .. } | Trigger::Anomaly { .. } => return None,
+            Trigger::Threshold { window, .. } => (*window, None),
+            Trigger::Ratio { window, of, .. } => (*window, of.as_ref()),
+        };
+        let counted = of.is_none_or(|of| of.matches(content));
+        let hit = counted && rule.matches(content);
+        let path = path.to_string_lossy().to_string();
+        let key = (rule.id.clone(), path.clone());
+
+        // Threshold windows start with the first match, ratio windows with the first counted record
+        let starts = match rule.trigger {
+            Trigger::Ratio { .. } => counted,
+            _ => hit,
+        };
+        if !starts && !self.states.contains_key(&key) {
+            return None;
+        }
+        let state = self.states.entry(key).or_insert_with(|| WindowState {
+            rule_id: rule.id.clone(),
+            severity: rule.severity,
+            trigger: rule.trigger.clone(),
+            counts: SlidingWindow::new(window),
+            firing: false,
+            line: String::new(),
+            first_seen: 0,
+            last_seen: 0,
+        });
+
+        state.counts.record(now, hit, counted);
+        if hit {
+            let seen = timestamp::now_millis();
+            if state.counts.hits == 1 {
+                state.first_seen = seen;
+            }
+            state.last_seen = seen;
+            state.line = line.to_string();
+        }
+        state.transition(&path)
+    }
+
+    /// Forgets the window of a rule on a source
+    pub fn remove(&mut self, rule_id: &str, path: &Path) {
+        self.states.remove(&(rule_id.to_string(), path.to_string_lossy().to_string()));
+    }
+
+    /// Forgets the windows of a source that is no longer watched
+    pub fn forget_source(&mut self, path: &Path) {
+        let path = path.to_string_lossy();
+        self.states.retain(|(_, source), _| *source != path);
+    }
+
+    /// When the counts of an alerting window next go down
+    pub fn deadline(&self) -> Option<Instant> {
+        self.states.values()
+            .filter(|state| state.firing)
+            .filter_map(|state| state.counts.next_expiry())
+            .min()
+    }
+
+    /// Moves the windows on to `now`; returns the recoveries of windows that went quiet
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        self.states.retain(|(_, path), state| {
+            state.counts.expire(now);
+            notifications.extend(state.transition(path));
+            state.firing || state.counts.total > 0
+        });
+        notifications
+    }
+}
+
+/// Heartbeat of one absence rule on one source
+#[derive(Debug)]
+struct Heartbeat {
+    rule_id: String,
+    severity: Severity,
+    pattern: String,
+    within: Duration,
+    // When the last matching record (or the start of watching) was seen
+    last: Instant,
+    line: String,
+    last_seen: i64,
+    firing: bool,
+}
+
+impl Heartbeat {
+    fn notify(&self, kind: NotificationKind, message: String, path: &str) -> Notification {
+        Notification {
+            rule_id: self.rule_id.clone(),
+            severity: self.severity,
+            kind,
+            path: path.to_string(),
+            line: self.line.clone(),
+            message: Some(message),
+            count: u64::from(kind == NotificationKind::Recovery),
+            first_seen: self.last_seen,
+            last_seen: self.last_seen,
+        }
+    }
+}
+
+/// Absence rules: the time of the last matching record per rule and source, raising an alert
+/// when a source stays quiet for too long and a recovery with its next matching record
+#[derive(Debug, Default)]
+pub struct Heartbeats {
+    states: HashMap<(String, String), Heartbeat>,
+}
+
+impl Heartbeats {
+    /// Starts expecting records from a source, so a source that never logs raises an alert too
+    pub fn arm(&mut self, rule: &NotificationRule, path: &Path, now: Instant) {
+        let Trigger::Absence { within } = rule.trigger else {
+            return;
+        };
+        let key = (rule.id.clone(), path.to_string_lossy().to_string());
+        self.states.entry(key).or_insert_with(|| Heartbeat {
+            rule_id: rule.id.clone(),
+            severity: rule.severity,
+            pattern: rule.pattern.clone(),
+            within,
+            last: now,
+            line: String::new(),
+            last_seen: 0,
+            firing: false,
+        });
+    }
+
+    /// Notes a record of a source the rule applies to; returns the recovery of an alerting source
+    pub fn on_record(&mut self, rule: &NotificationRule, path: &Path, line: &str, content: &str, now: Instant) -> Option<Notification> {
+        if !rule.matches(content) {
+            return None;
+        }
+        self.arm(rule, path, now);
+        let path = path.to_string_lossy().to_string();
+        let heartbeat = self.states.get_mut(&(rule.id.clone(), path.clone()))?;
+        let silence = Duration::from_secs(now.saturating_duration_since(heartbeat.last).as_secs());
+        heartbeat.last = now;
+        heartbeat.line = line.to_string();
+        heartbeat.last_seen = timestamp::now_millis();
+        if !heartbeat.firing {
+            return None;
+        }
+        heartbeat.firing = false;
+        Some(heartbeat.notify(NotificationKind::Recovery, format!("records again after {:?} of silence", silence), &path))
+    }
+
+    /// Stops expecting records for a rule on a source
+    pub fn remove(&mut self, rule_id: &str, path: &Path) {
+        self.states.remove(&(rule_id.to_string(), path.to_string_lossy().to_string()));
+    }
+
+    /// Stops expecting records from a source that is no longer watched
+    pub fn forget_source(&mut self, path: &Path) {
+        let path = path.to_string_lossy();
+        self.states.retain(|(_, source), _| *source != path);
+    }
+
+    /// When the next quiet source is due to alert
+    pub fn deadline(&self) -> Option<Instant> {
+        self.states.values()
+            .filter(|heartbeat| !heartbeat.firing)
+            .map(|heartbeat| deadline_after(heartbeat.last, heartbeat.within))
+            .min()
+    }
+
+    /// Returns the alerts of sources that have been quiet for longer than their rule allows
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        for ((_, path), heartbeat) in self.states.iter_mut() {
+            if heartbeat.firing || deadline_after(heartbeat.last, heartbeat.within) > now {
+                continue;
+            }
+            heartbeat.firing = true;
+            let message = if heartbeat.pattern.is_empty() {
+                format!("no new record for {:?}", heartbeat.within)
+            } else {
+                format!("no record matching \"{}\" for {:?}", heartbeat.pattern, heartbeat.within)
+            };
+            notifications.push(heartbeat.notify(NotificationKind::Alert, message, path));
+        }
+        notifications
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown_above_maximum_is_refused() {
+        let mut rule = NotificationRule::new("r", "error", false).unwrap();
+        assert!(rule.set_cooldown(u64::MAX).is_err());
+        assert_eq!(rule.cooldown, DEFAULT_COOLDOWN);
+        rule.set_cooldown(0).unwrap();
+        assert_eq!(rule.cooldown, Duration::ZERO);
+    }
+
+    #[test]
+    fn merged_timelines_need_a_rule_naming_them() {
+        let merged = Path::new("merged://web");
+        let mut rule = NotificationRule::new("r", "error", false).unwrap();
+        assert!(rule.applies_to(Path::new("/var/log/app.log")));
+        assert!(!rule.applies_to(merged));
+
+        rule.sources = vec![Pattern::new("merged://*").unwrap(), Pattern::new("*").unwrap()];
+        assert!(!rule.applies_to(merged));
+        rule.sources.push(Pattern::new("merged://web").unwrap());
+        assert!(rule.applies_to(merged));
+        assert!(!rule.applies_to(Path::new("merged://api")));
+    }
+
+    #[test]
+    fn deadline_after_does_not_overflow() {
+        let now = Instant::now();
+        assert_eq!(deadline_after(now, Duration::MAX), now + MAX_RULE_DURATION);
+    }
+
+    #[test]
+    fn windows_must_be_positive_and_bounded() {
+        let mut rule = NotificationRule::new("r", "error", false).unwrap();
+        let threshold = |window_ms| ThresholdConfig { count: 1, window_ms };
+        assert!(rule.set_trigger(Some(&threshold(0)), None, None, None, None).is_err());
+        assert!(rule.set_trigger(Some(&threshold(u64::MAX)), None, None, None, None).is_err());
+        rule.set_trigger(Some(&threshold(60_000)), None, None, None, None).unwrap();
+        assert!(matches!(rule.trigger, Trigger::Threshold { count: 1, window } if window == Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn threshold_alerts_and_recovers() {
+        let mut rule = NotificationRule::new("r", "error", false).unwrap();
+        rule.set_trigger(Some(&ThresholdConfig { count: 1, window_ms: 1000 }), None, None, None, None).unwrap();
+        let mut alerts = WindowAlerts::default();
+        let now = Instant::now();
+        let path = Path::new("/var/log/app.log");
+        assert!(alerts.on_record(&rule, path, "error", "error", now).is_none());
+        let alert = alerts.on_record(&rule, path, "error", "error", now).unwrap();
+        assert_eq!(alert.kind, NotificationKind::Alert);
+        let deadline = alerts.deadline().unwrap();
+        let recovery = alerts.flush_expired(deadline);
+        assert_eq!(recovery.len(), 1);
+        assert_eq!(recovery[0].kind, NotificationKind::Recovery);
+    }
+
+    #[test]
+    fn ratio_percent_must_be_a_share() {
+        let mut rule = NotificationRule::new("r", "error", false).unwrap();
+        let ratio = |percent| RatioConfig { percent, window_ms: 1000, of: None, min_records: 1 };
+        for percent in [-1.0, 100.5, f64::NAN] {
+            assert!(rule.set_trigger(None, Some(&ratio(percent)), None, None, None).is_err());
+        }
+        rule.set_trigger(None, Some(&ratio(0.0)), None, None, None).unwrap();
+        rule.set_trigger(None, Some(&ratio(100.0)), None, None, None).unwrap();
+    }
+
+    #[test]
+    fn removed_windows_and_cooldowns_start_over() {
+        let mut rule = NotificationRule::new("r", "error", false).unwrap();
+        rule.set_trigger(Some(&ThresholdConfig { count: 1, window_ms: 1000 }), None, None, None, None).unwrap();
+        let (app, db) = (Path::new("/var/log/app.log"), Path::new("/var/log/db.log"));
+        let now = Instant::now();
+
+        let mut alerts = WindowAlerts::default();
+        for path in [app, db] {
+            alerts.on_record(&rule, path, "error", "error", now);
+        }
+        alerts.remove("r", app);
+        assert!(alerts.on_record(&rule, app, "error", "error", now).is_none());
+        assert!(alerts.on_record(&rule, db, "error", "error", now).is_some());
+        alerts.forget_source(db);
+        assert!(alerts.deadline().is_none());
+
+        let mut notifier = Notifier::default();
+        let match_rule = NotificationRule::new("m", "error", false).unwrap();
+        for path in [app, db] {
+            assert!(notifier.on_match(&match_rule, path, "error", "error", now).is_some());
+            assert!(notifier.on_match(&match_rule, path, "error", "error", now).is_none());
+        }
+        notifier.remove("other", app);
+        notifier.remove("m", app);
+        assert!(notifier.on_match(&match_rule, app, "error", "error", now).is_some());
+        notifier.forget_source(db);
+        assert!(notifier.on_match(&match_rule, db, "error", "error", now).is_some());
+    }
+
+    #[test]
+    fn quiet_source_alerts_once_and_recovers() {
+        let mut rule = NotificationRule::new("r", "", false).unwrap();
+        assert!(rule.set_trigger(None, None, Some(&AbsenceConfig { within_ms: u64::MAX }), None, None).is_err());
+        assert!(rule.set_trigger(None, None, Some(&AbsenceConfig { within_ms: 0 }), None, None).is_err());
+        rule.set_trigger(None, None, Some(&AbsenceConfig { within_ms: 1000 }), None, None).unwrap();
+        let mut heartbeats = Heartbeats::default();
+        let now = Instant::now();
+        let path = Path::new("/var/log/app.log");
+        heartbeats.arm(&rule, path, now);
+        let due = heartbeats.deadline().unwrap();
+        assert_eq!(due, now + Duration::from_secs(1));
+        assert_eq!(heartbeats.flush_expired(due).len(), 1);
+        assert!(heartbeats.flush_expired(due).is_empty());
+        let recovery = heartbeats.on_record(&rule, path, "back", "back", due).unwrap();
+        assert_eq!(recovery.kind, NotificationKind::Recovery);
+    }
+
+    #[test]
+    fn identical_messages_are_counted_during_the_cooldown() {
+        let rule = NotificationRule::new("r", "error", false).unwrap();
+        let mut notifier = Notifier::default();
+        let now = Instant::now();
+        let path = Path::new("/var/log/app.log");
+        assert!(notifier.on_match(&rule, path, "error 1", "error 1", now).is_some());
+        assert!(notifier.on_match(&rule, path, "error 2", "error 2", now).is_none());
+        assert!(notifier.flush_expired(now).is_empty());
+        let flushed = notifier.flush_expired(now + DEFAULT_COOLDOWN);
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].count, 1);
+        assert_eq!(flushed[0].line, "error 2");
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde_json::{Map, Value};
+use tracing::{debug, info_span, warn};
+use crate::log_mgr::command_source;
+use crate::log_mgr::config::{SinkConfig, SinkKind, SmtpConfig, SmtpSecurity};
+use crate::log_mgr::notification::Notification;
+
+/// How often a command sink checks whether its command has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Notifications waiting for delivery per sink; more are dropped until the sink catches up
+const QUEUE_LEN: usize = 1000;
+
+/// Longest wait between two delivery attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Queue of one sink's delivery thread
+#[derive(Debug)]
+struct Queue {
+    tx: SyncSender<Notification>,
+    dashboard: bool,
+    // Notifications dropped since the queue was last full
+    dropped: u64,
+}
+
+/// The configured sinks. Each one delivers on its own thread, so a slow or unreachable
+/// destination delays neither the others nor the watcher manager.
+#[derive(Debug, Default)]
+pub struct NotificationSinks {
+    queues: HashMap<String, Queue>,
+}
+
+impl NotificationSinks {
+    /// Starts one delivery thread per configured sink
+    pub fn start(configs: &[SinkConfig]) -> NotificationSinks {
+        let queues = configs.iter()
+            .cloned()
+            .map(|config| {
+                let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+                let (name, dashboard) = (config.name.clone(), config.dashboard);
+                thread::spawn(move || {
+                    let _span = info_span!("sink", name = %config.name).entered();
+                    deliver_all(&config, rx);
+                });
+                (name, Queue { tx, dashboard, dropped: 0 })
+            })
+            .collect();
+
+        NotificationSinks { queues }
+    }
+
+    /// Whether a sink with this name is configured
+    pub fn contains(&self, name: &str) -> bool {
+        self.queues.contains_key(name)
+    }
+
+    /// Whether rules set from the dashboard may send to this sink
+    pub fn open_to_dashboard(&self, name: &str) -> bool {
+        self.queues.get(name).is_some_and(|queue| queue.dashboard)
+    }
+
+    /// Queues a notification for the named sinks; drops it for sinks that are too far behind
+    pub fn send(&mut self, names: &[String], notification: &Notification) {
+        for name in names {
+            let Some(queue) = self.queues.get_mut(name) else {
+                continue;
+            };
+            match queue.tx.try_send(notification.clone()) {
+                Ok(()) if queue.dropped > 0 => {
+                    warn!(sink = %name, "Sink caught up after dropping {} notifications", queue.dropped);
+                    queue.dropped = 0;
+                }
+                Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+                Err(TrySendError::Full(_)) => {
+                    if queue.dropped == 0 {
+                        warn!(sink = %name, "Sink is {} notifications behind, dropping new ones", QUEUE_LEN);
+                    }
+                    queue.dropped += 1;
+                }
+            }
+        }
+    }
+}
+
+// Delivers the queued notifications in order until the sinks are dropped
+fn deliver_all(config: &SinkConfig, rx: Receiver<Notification>) {
+    for notification in rx {
+        let mut fields = match serde_json::to_value(&notification) {
+            Ok(Value::Object(fields)) => fields,
+            _ => continue,
+        };
+        // Plain matches have no message; templates still know the field
+        fields.entry("message").or_insert(Value::Null);
+
+        let mut delay = Duration::from_millis(config.retry_delay_ms).min(MAX_RETRY_DELAY);
+        for attempt in 0..=config.retries {
+            match deliver(&config.kind, &fields) {
+                Ok(()) => {
+                    debug!(rule = %notification.rule_id, "Notification delivered");
+                    break;
+                }
+                Err(e) if attempt < config.retries => {
+                    warn!(rule = %notification.rule_id, "Delivery failed, retrying in {:?}: {}", delay, e);
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+                }
+                Err(e) => warn!(rule = %notification.rule_id, "Delivery failed, giving up: {}", e),
+            }
+        }
+    }
+}
+
+// Delivers one notification, given as its JSON fields
+fn deliver(kind: &SinkKind, fields: &Map<String, Value>) -> Result<(), String> {
+    match kind {
+        SinkKind::Webhook { url, body, headers, timeout_ms } => {
+            let body = match body {
+                Some(template) => render_json(template, fields),
+                None => Value::Object(fields.clone()),
+            };
+            let agent: ureq::Agent = ureq::Agent::config_builder()
+                .timeout_global(Some(Duration::from_millis(*timeout_ms)))
+                .build()
+                .into();
+            let mut request = agent.post(url).content_type("application/json");
+            for (name, value) in headers {
+                request = request.header(name, render(value, fields));
+            }
+            request.send(body.to_string()).map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        SinkKind::Command { command, timeout_ms } => {
+            let mut command = command_source::build_command(command).map_err(|e| e.to_string())?;
+            for (name, value) in fields {
+                command.env(format!("LOGGYXP_{}", name.to_uppercase()), text(value));
+            }
+            command.env("LOGGYXP_NOTIFICATION", Value::Object(fields.clone()).to_string());
+            let mut child = command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("cannot start command: {}", e))?;
+
+            let deadline = Instant::now() + Duration::from_millis(*timeout_ms);
+            loop {
+                if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                    return if status.success() { Ok(()) } else { Err(format!("command exited ({})", status)) };
+                }
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(String::from("command timed out"));
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        SinkKind::File { path } => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+            writeln!(file, "{}", Value::Object(fields.clone())).map_err(|e| e.to_string())
+        }
+        SinkKind::Smtp(smtp) => send_mail(smtp, fields),
+    }
+}
+
+// Sends the notification as a plain text email
+fn send_mail(config: &SmtpConfig, fields: &Map<String, Value>) -> Result<(), String> {
+    let mut message = Message::builder()
+        .from(config.from.parse().map_err(|e| format!("invalid from address: {}", e))?)
+        .subject(render(&config.subject, fields))
+        .header(ContentType::TEXT_PLAIN);
+    for to in &config.to {
+        message = message.to(to.parse().map_err(|e| format!("invalid to address {}: {}", to, e))?);
+    }
+    let message = message.body(render(&config.body, fields)).map_err(|e| e.to_string())?;
+
+    let (builder, port) = match config.security {
+        SmtpSecurity::None => (SmtpTransport::builder_dangerous(&config.host), 25),
+        SmtpSecurity::Starttls => (SmtpTransport::starttls_relay(&config.host).map_err(|e| e.to_string())?, 587),
+        SmtpSecurity::Tls => (SmtpTransport::relay(&config.host).map_err(|e| e.to_string())?, 465),
+    };
+    let mut builder = builder
+        .port(config.port.unwrap_or(port))
+        .timeout(Some(Duration::from_millis(config.timeout_ms)));
+    if let Some(username) = &config.username {
+        builder = builder.credentials(Credentials::new(username.clone(), config.password.clone().unwrap_or_default()));
+    }
+    builder.build().send(&message).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Replaces `{{field}}` in a template with the fields of a notification; unknown fields are kept
+fn render(template: &str, fields: &Map<String, Value>) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{")
+        && let Some(len) = rest[start..].find("}}") {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..start + len + 2];
+        match fields.get(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => rendered.push_str(&text(value)),
+            None => rendered.push_str(placeholder),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+// Renders the strings of a JSON template; a string that is only a placeholder becomes the
+// field itself, so numbers stay numbers
+fn render_json(template: &Value, fields: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(s) => {
+            let field = s.strip_prefix("{{")
+                .and_then(|s| s.strip_suffix("}}"))
+                .and_then(|name| fields.get(name.trim()));
+            match field {
+                Some(value) => value.clone(),
+                None => Value::String(render(s, fields)),
+            }
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_json(item, fields)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), render_json(v, fields))).collect()),
+        other => other.clone(),
+    }
+}
+
+// A field as plain text: strings without quotes, missing values empty
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use serde_json::json;
+    use crate::log_mgr::config::CommandLine;
+
+    fn fields() -> Map<String, Value> {
+        match json!({ "rule_id": "errors", "severity": "critical", "count": 3, "message": null }) {
+            Value::Object(fields) => fields,
+            _ => unreachable!(),
+        }
+    }
+
+    // A file in the temp directory that is removed first
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn render_replaces_known_fields() {
+        let rendered = render("[{{severity}}] {{ rule_id }} x{{count}} {{message}}{{unknown}}", &fields());
+        assert_eq!(rendered, "[critical] errors x3 {{unknown}}");
+        assert_eq!(render("open {{severity", &fields()), "open {{severity");
+    }
+
+    #[test]
+    fn render_json_keeps_field_types() {
+        let template = json!({ "text": "{{rule_id}} ({{count}})", "count": "{{count}}", "tags": ["{{severity}}", 1] });
+        assert_eq!(render_json(&template, &fields()), json!({ "text": "errors (3)", "count": 3, "tags": ["critical", 1] }));
+    }
+
+    #[test]
+    fn webhook_posts_rendered_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n").unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+
+        let kind = SinkKind::Webhook {
+            url,
+            body: Some(json!({ "text": "{{rule_id}}", "count": "{{count}}" })),
+            headers: HashMap::from([(String::from("X-Rule"), String::from("{{rule_id}}"))]),
+            timeout_ms: 5000,
+        };
+        deliver(&kind, &fields()).unwrap();
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /hook "));
+        assert!(head.to_lowercase().contains("x-rule: errors"));
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "text": "errors", "count": 3 }));
+    }
+
+    #[test]
+    fn smtp_sends_rendered_mail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let reply = |reader: &mut BufReader<std::net::TcpStream>, line: &str| {
+                reader.get_mut().write_all(format!("{}\r\n", line).as_bytes()).unwrap();
+            };
+            reply(&mut reader, "220 localhost ready");
+            let mut data = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        reply(&mut reader, "250 queued");
+                    } else {
+                        data.push_str(&line);
+                    }
+                    continue;
+                }
+                match line.get(..4).unwrap_or_default().to_uppercase().as_str() {
+                    "EHLO" => reply(&mut reader, "250 localhost"),
+                    "DATA" => {
+                        in_data = true;
+                        reply(&mut reader, "354 go ahead");
+                    }
+                    "QUIT" => {
+                        reply(&mut reader, "221 bye");
+                        break;
+                    }
+                    _ => reply(&mut reader, "250 ok"),
+                }
+            }
+            data
+        });
+
+        let config = SmtpConfig {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: String::from("loggyxp@example.com"),
+            to: vec![String::from("oncall@example.com")],
+            subject: String::from("[{{severity}}] {{rule_id}}"),
+            body: String::from("{{count}} matches"),
+            timeout_ms: 5000,
+        };
+        deliver(&SinkKind::Smtp(config), &fields()).unwrap();
+        let data = server.join().unwrap();
+        assert!(data.contains("Subject: [critical] errors"));
+        assert!(data.contains("To: oncall@example.com"));
+        assert!(data.contains("3 matches"));
+    }
+
+    #[test]
+    fn file_sink_appends_json_lines() {
+        let path = temp_file("sink.jsonl");
+        let kind = SinkKind::File { path: path.clone() };
+        deliver(&kind, &fields()).unwrap();
+        deliver(&kind, &fields()).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(serde_json::from_str::<Value>(lines[0]).unwrap(), Value::Object(fields()));
+    }
+
+    #[test]
+    fn command_sink_gets_fields_in_environment() {
+        let path = temp_file("sink.out");
+        let script = format!("printf '%s %s' \"$LOGGYXP_RULE_ID\" \"$LOGGYXP_COUNT\" > '{}'", path.display());
+        let kind = SinkKind::Command { command: CommandLine::Shell(script), timeout_ms: 5000 };
+        deliver(&kind, &fields()).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(written, "errors 3");
+
+        let failing = SinkKind::Command { command: CommandLine::Shell(String::from("exit 3")), timeout_ms: 5000 };
+        assert!(deliver(&failing, &fields()).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use glob::Pattern;
+use tracing::warn;
+use crate::log_mgr::config::SandboxConfig;
+use crate::log_mgr::container_discovery::{self, ContainerLogs};
+
+/// Restricts which files clients may open to a set of allowed roots and globs, and the
+/// log files of containers when container discovery is configured
+#[derive(Debug)]
+pub struct PathSandbox {
+    roots: Vec<PathBuf>,
+    globs: Vec<Pattern>,
+    containers: Option<ContainerLogs>,
+}
+
+impl PathSandbox {
+    /// Builds the sandbox from the configured roots and glob patterns
+    pub fn new(config: &SandboxConfig) -> PathSandbox {
+        PathSandbox::with_containers(config, None)
+    }
+
+    /// Builds the sandbox and also allows container logs, opened by their labels
+    pub fn with_containers(config: &SandboxConfig, containers: Option<ContainerLogs>) -> PathSandbox {
+        let roots = config.allowed_roots.clone();
+        let mut globs = Vec::new();
+        for glob in &config.allowed_globs {
+            match Pattern::new(glob) {
+                Ok(p) => globs.push(p),
+                Err(e) => warn!(glob = %glob, "Ignoring invalid allowed glob: {}", e),
+            }
+        }
+
+        PathSandbox { roots, globs, containers }
+    }
+
+    /// Labels of the container logs currently present
+    pub fn container_labels(&self) -> Vec<String> {
+        self.containers.iter()
+            .flat_map(ContainerLogs::discover)
+            .map(|log| log.label)
+            .collect()
+    }
+
+    /// Resolves a client-supplied path to its canonical form if it is an allowed regular file.
+    /// Canonicalising first means `..` components and symlinks cannot escape a root.
+    /// Container labels resolve to the container's current log file.
+    pub fn resolve(&self, requested: &str) -> Result<PathBuf, String> {
+        if requested.trim().is_empty() {
+            return Err(String::from("empty path"));
+        }
+
+        let target = if container_discovery::is_container_label(requested) {
+            self.containers.as_ref()
+                .and_then(|containers| containers.resolve(requested))
+                .ok_or_else(|| format!("cannot open {}: no such container log", requested))?
+        } else {
+            PathBuf::from(requested)
+        };
+
+        let canonical = fs::canonicalize(&target)
+            .map_err(|e| format!("cannot open {}: {}", requested, e))?;
+
+        if !self.is_allowed(&canonical) {
+            return Err(format!("access denied: {} is outside the allowed log locations", requested));
+        }
+
+        // Directories cannot be tailed, and pipes and devices would block the reader; FIFOs are
+        // configured as sources instead
+        let file_type = fs::metadata(&canonical)
+            .map_err(|e| format!("cannot open {}: {}", requested, e))?
+            .file_type();
+        if file_type.is_file() {
+            Ok(canonical)
+        } else {
+            Err(format!("cannot open {}: not a regular file (configure FIFOs under \"fifos\")", requested))
+        }
+    }
+
+    /// Whether a file the server found itself (e.g. a rotation of an allowed log) may be read:
+    /// its canonical path must be allowed and it must be a regular file
+    pub fn allows(&self, path: &Path) -> bool {
+        fs::canonicalize(path)
+            .is_ok_and(|canonical| self.is_allowed(&canonical) && canonical.is_file())
+    }
+
+    /// Checks an already canonical path against the roots, globs and container log files.
+    /// Roots are canonicalised on every check so directories created after startup still work.
+    fn is_allowed(&self, canonical: &Path) -> bool {
+        self.roots.iter()
+            .filter_map(|root| fs::canonicalize(root).ok())
+            .any(|root| canonical.starts_with(root))
+            || self.globs.iter().any(|glob| glob.matches_path(canonical))
+            || self.containers.as_ref().is_some_and(|containers| containers.is_log_file(canonical))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // A fresh directory with `root/app.log`, `root/logs/` and `outside/secret.log`
+    fn layout(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-sandbox-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root/logs")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(dir.join("root/app.log"), "line\n").unwrap();
+        fs::write(dir.join("outside/secret.log"), "secret\n").unwrap();
+        dir
+    }
+
+    fn sandbox(dir: &Path, globs: &[&str]) -> PathSandbox {
+        PathSandbox::new(&SandboxConfig {
+            allowed_roots: vec![dir.join("root")],
+            allowed_globs: globs.iter().map(|glob| glob.to_string()).collect(),
+        })
+    }
+
+    fn resolve(sandbox: &PathSandbox, path: PathBuf) -> Result<PathBuf, String> {
+        sandbox.resolve(&path.to_string_lossy())
+    }
+
+    #[test]
+    fn files_below_a_root_are_allowed() {
+        let dir = layout("root");
+        let sandbox = sandbox(&dir, &[]);
+        let expected = fs::canonicalize(dir.join("root/app.log")).unwrap();
+        assert_eq!(resolve(&sandbox, dir.join("root/app.log")).unwrap(), expected);
+        assert_eq!(resolve(&sandbox, dir.join("root/logs/../app.log")).unwrap(), expected);
+        assert!(resolve(&sandbox, dir.join("outside/secret.log")).unwrap_err().starts_with("access denied"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dot_dot_cannot_leave_a_root() {
+        let dir = layout("dotdot");
+        let sandbox = sandbox(&dir, &[]);
+        let escaped = resolve(&sandbox, dir.join("root/logs/../../outside/secret.log"));
+        assert!(escaped.unwrap_err().starts_with("access denied"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symlinks_cannot_leave_a_root() {
+        let dir = layout("symlink");
+        symlink(dir.join("outside/secret.log"), dir.join("root/link.log")).unwrap();
+        symlink(dir.join("outside"), dir.join("root/logs/out")).unwrap();
+        let sandbox = sandbox(&dir, &[]);
+        assert!(resolve(&sandbox, dir.join("root/link.log")).unwrap_err().starts_with("access denied"));
+        assert!(resolve(&sandbox, dir.join("root/logs/out/secret.log")).unwrap_err().starts_with("access denied"));
+        assert!(!sandbox.allows(&dir.join("root/link.log")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn globs_allow_matching_files() {
+        let dir = layout("glob");
+        let outside = fs::canonicalize(dir.join("outside")).unwrap();
+        let sandbox = sandbox(&dir, &[&format!("{}/*.log", outside.display())]);
+        assert_eq!(resolve(&sandbox, dir.join("outside/secret.log")).unwrap(), outside.join("secret.log"));
+        fs::write(dir.join("outside/secret.txt"), "x").unwrap();
+        assert!(resolve(&sandbox, dir.join("outside/secret.txt")).unwrap_err().starts_with("access denied"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_existing_regular_files_resolve() {
+        let dir = layout("kinds");
+        let sandbox = sandbox(&dir, &[]);
+        assert!(resolve(&sandbox, dir.join("root/logs")).unwrap_err().contains("not a regular file"));
+        assert!(resolve(&sandbox, dir.join("root/missing.log")).unwrap_err().starts_with("cannot open"));
+        assert_eq!(sandbox.resolve("  ").unwrap_err(), "empty path");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
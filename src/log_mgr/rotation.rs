@@ -0,0 +1,101 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions a rotated file may carry after its rotation suffix
+const COMPRESSED_EXTENSIONS: [&str; 4] = ["gz", "zst", "bz2", "xz"];
+
+/// Returns the rotated set of a log file, oldest first and ending with the file itself.
+///
+/// Rotations are siblings named like `app.log.1`, `app.log.2.gz` (higher numbers are older)
+/// or `app.log-20240101.zst` (dated, ordered by date).
+pub fn rotated_set(path: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(base)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return vec![path.to_path_buf()];
+    };
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+    let mut rotations: Vec<(RotationKey, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let key = rotation_key(base, name.to_str()?)?;
+            Some((key, entry.path()))
+        })
+        .collect();
+    rotations.sort();
+
+    let mut set: Vec<PathBuf> = rotations.into_iter().map(|(_, p)| p).collect();
+    set.push(path.to_path_buf());
+    set
+}
+
+/// Sort key of a rotation: dated rotations by date, then numbered ones from the highest number
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum RotationKey {
+    Dated(String),
+    Numbered(Reverse<u64>),
+}
+
+// Recognises `name` as a rotation of `base`
+fn rotation_key(base: &str, name: &str) -> Option<RotationKey> {
+    let rest = name.strip_prefix(base)?;
+    let rest = COMPRESSED_EXTENSIONS.iter()
+        .find_map(|ext| rest.strip_suffix(ext).and_then(|r| r.strip_suffix('.')))
+        .unwrap_or(rest);
+
+    if let Some(number) = rest.strip_prefix('.')
+        && !number.is_empty()
+        && number.bytes().all(|b| b.is_ascii_digit()) {
+        return number.parse().ok().map(|n| RotationKey::Numbered(Reverse(n)));
+    }
+    if let Some(date) = rest.strip_prefix('-')
+        && date.len() >= 8
+        && date.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(RotationKey::Dated(date.to_string()));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(set: &[PathBuf]) -> Vec<&str> {
+        set.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn numbered_rotations_come_before_the_file_oldest_first() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-numbered", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["app.log", "app.log.1", "app.log.2.gz", "app.log.10.zst", "app.log.bak", "app.logger", "other.log.1"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let set = rotated_set(&dir.join("app.log"));
+        assert_eq!(names(&set), ["app.log.10.zst", "app.log.2.gz", "app.log.1", "app.log"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dated_rotations_are_ordered_by_date() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-dated", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["app.log", "app.log-20240102.gz", "app.log-20231231", "app.log-2024"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let set = rotated_set(&dir.join("app.log"));
+        assert_eq!(names(&set), ["app.log-20231231", "app.log-20240102.gz", "app.log"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_file_without_rotations_is_its_own_set() {
+        let path = std::env::temp_dir().join(format!("loggyxp-{}-missing", std::process::id())).join("app.log");
+        assert_eq!(rotated_set(&path), vec![path.clone()]);
+    }
+}
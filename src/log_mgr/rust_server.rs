@@ -1,14 +1,35 @@
 use axum::{response::Html, routing::get, Router, extract::State};
 use axum::response::IntoResponse;
 use std::{net::SocketAddr, sync::Arc, path::PathBuf, sync::Mutex};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use tokio::net::TcpListener;
 use serde::{Deserialize, Serialize};
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use std::sync::mpsc::Sender;
-use crate::log_mgr::log_monitoring::{LogContextData, WatchCommand};
+use crate::log_mgr::log_monitoring::WatchCommand;
+use crate::log_mgr::log_context_data::LogContextData;
 use futures_util::{StreamExt, SinkExt};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc, watch};
+use std::time::Duration;
 use crate::log_mgr;
+use crate::log_mgr::path_sandbox::PathSandbox;
+use crate::log_mgr::config::{AbsenceConfig, AnomalyConfig, NewPatternConfig, RatioConfig, ServerConfig, SilenceConfig, ThresholdConfig, TimelineConfig, TlsConfig};
+use crate::log_mgr::diagnostics::{self, RecentErrors, StatusData};
+use crate::log_mgr::metrics;
+use crate::log_mgr::log_watcher::LogWatcher;
+use crate::log_mgr::source_rules::SourceRules;
+use crate::log_mgr::live_source::{self, LiveSources};
+use crate::log_mgr::notification::{Notification, NotificationRule, Severity};
+use crate::log_mgr::alert_history::Alert;
+use crate::log_mgr::silence::{Silence, SilenceInfo};
+use crate::log_mgr::timeline::{self, MERGED_SCHEME};
+use crate::log_mgr::timestamp;
+use crate::log_mgr::volume::{Volume, VolumeQuery};
+use crate::log_mgr::templates::{self, SourceTemplates, TemplateFilter};
+use crate::log_mgr::log_record::LogRecord;
+use axum::Json;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use axum_server::tls_rustls::RustlsConfig;
 
 // Shared application state for handlers
 #[derive(Clone)]
@@ -16,6 +37,15 @@ struct AppState {
     cmd_tx: Sender<WatchCommand>, // Channel to send watch commands
     log_tx: broadcast::Sender<WsEventTx>, // Channel to broadcast log events
     context: Arc<Mutex<LogContextData>>, // Shared log context
+    sandbox: Arc<PathSandbox>, // Allowlist for client-supplied paths
+    shutdown: watch::Receiver<bool>, // Flips to true once the server is shutting down
+    status: Arc<Mutex<StatusData>>, // Runtime state for the status endpoint
+    recent_errors: RecentErrors, // Latest warnings and errors for the status endpoint
+    started_at: u64, // Unix time the server started
+    rules: Arc<SourceRules>, // Per-source options such as multi-line grouping
+    live: Arc<LiveSources>, // Sources that are not files, e.g. syslog senders
+    merged: Arc<Mutex<HashMap<String, MergedTimeline>>>, // Merged timelines by name
+    reorder_window: Duration, // How long merged records are held back to put late writers in order
 }
 
 // Messages received from the client via WebSocket
@@ -25,6 +55,9 @@ enum ClientMessage {
     #[serde(rename = "watch_paths")]
     WatchPaths { paths: Vec<String> },
 
+    #[serde(rename = "watch_merged")]
+    WatchMerged { name: String, paths: Vec<String> },
+
     #[serde(rename = "start_tailing")]
     StartTailing {paths: Vec<String> },
 
@@ -36,18 +69,67 @@ enum ClientMessage {
         paths: Vec<String>,
         pattern: String,
         regex: bool,
+        #[serde(default)]
+        field: Option<String>,
+    },
+
+    #[serde(rename = "volume")]
+    Volume {
+        paths: Vec<String>,
+        #[serde(default)]
+        pattern: Option<String>,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        bucket_ms: Option<i64>,
+    },
+
+    #[serde(rename = "templates")]
+    Templates {
+        paths: Vec<String>,
+    },
+
+    #[serde(rename = "filter_template")]
+    FilterTemplate {
+        paths: Vec<String>,
+        id: u64,
+        #[serde(default)]
+        exclude: bool,
     },
 
     #[serde(rename = "Filter_by")]
     FilterBy {
         paths: Vec<String>,
         pattern: String,
+        #[serde(default)]
+        field: Option<String>,
     },
 
     #[serde(rename = "Notify_when")]
     NotifyWhen {
         paths: Vec<String>,
+        #[serde(default)]
         pattern: String,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        severity: Severity,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        cooldown_ms: Option<u64>,
+        #[serde(default)]
+        threshold: Option<ThresholdConfig>,
+        #[serde(default)]
+        ratio: Option<RatioConfig>,
+        #[serde(default)]
+        absence: Option<AbsenceConfig>,
+        #[serde(default)]
+        new_pattern: Option<NewPatternConfig>,
+        #[serde(default)]
+        anomaly: Option<AnomalyConfig>,
+        #[serde(default)]
+        sinks: Vec<String>,
     },
 
     #[serde(rename = "remove_filter")]
@@ -59,6 +141,56 @@ enum ClientMessage {
     RemoveNotification {
         paths: Vec<String>,
     },
+
+    #[serde(rename = "list_sources")]
+    ListSources,
+
+    #[serde(rename = "list_alerts")]
+    ListAlerts {
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+
+    #[serde(rename = "ack_alert")]
+    AckAlert {
+        id: u64,
+        by: String,
+    },
+
+    #[serde(rename = "resolve_alert")]
+    ResolveAlert {
+        id: u64,
+        by: String,
+    },
+
+    #[serde(rename = "silence_alert")]
+    SilenceAlert {
+        id: u64,
+        duration_ms: u64,
+        by: String,
+    },
+
+    #[serde(rename = "list_silences")]
+    ListSilences,
+
+    #[serde(rename = "add_silence")]
+    AddSilence {
+        #[serde(flatten)]
+        silence: SilenceConfig,
+        by: String,
+    },
+
+    #[serde(rename = "remove_silence")]
+    RemoveSilence {
+        id: String,
+    },
+}
+
+// The files of a merged timeline and the clients using it; it is unmerged when the last one stops
+#[derive(Debug)]
+struct MergedTimeline {
+    files: Vec<PathBuf>,
+    clients: HashSet<u64>,
 }
 
 // Events sent to the client via WebSocket
@@ -69,6 +201,8 @@ pub enum WsEventTx {
     Log {
         path: String,
         line: String,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        fields: BTreeMap<String, String>,
     },
 
     #[serde(rename = "search_result")]
@@ -81,9 +215,71 @@ pub enum WsEventTx {
     LogBatch {
         path: String,
         lines: Vec<String>,
+        // Fields of each line, when the source format has any
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<BTreeMap<String, String>>,
+    },
+
+    #[serde(rename = "volume")]
+    Volume(Volume),
+
+    #[serde(rename = "templates")]
+    Templates(SourceTemplates),
+
+    #[serde(rename = "path_resolved")]
+    PathResolved {
+        requested: String,
+        path: String,
+    },
+
+    #[serde(rename = "sources")]
+    Sources {
+        sources: Vec<String>,
+    },
+
+    #[serde(rename = "notification")]
+    Notification(Notification),
+
+    #[serde(rename = "alerts")]
+    Alerts {
+        alerts: Vec<Alert>,
+    },
+
+    #[serde(rename = "alert")]
+    Alert(Alert),
+
+    #[serde(rename = "silences")]
+    Silences {
+        silences: Vec<SilenceInfo>,
+    },
+
+    #[serde(rename = "error")]
+    Error {
+        path: String,
+        message: String,
+    },
+
+    #[serde(rename = "shutdown")]
+    Shutdown {
+        message: String,
+    },
+}
+
+impl WsEventTx {
+    /// A batch of existing records of a source, with their fields when any record has some
+    pub fn log_batch(path: &str, records: &[LogRecord]) -> WsEventTx {
+        let fields = if records.iter().any(|record| !record.fields.is_empty()) {
+            records.iter().map(|record| record.fields.clone()).collect()
+        } else {
+            Vec::new()
+        };
+        WsEventTx::LogBatch { path: path.to_string(), lines: records.iter().map(LogRecord::numbered).collect(), fields }
     }
 }
 
+// How long open connections get to finish after a shutdown signal
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
 // Loads HTML file for dashboard
 fn load_html(path: &str) -> Html<String> {
     let html = std::fs::read_to_string(path)
@@ -91,23 +287,115 @@ fn load_html(path: &str) -> Html<String> {
     Html(html)
 }
 
-// Starts the HTTP and WebSocket server
-pub fn run_server(cmd_tx: Sender<WatchCommand>, log_tx: broadcast::Sender<WsEventTx>, context: Arc<Mutex<LogContextData>>) {
-    let addr: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+// Builds the TLS settings from certificate files or a generated self-signed certificate
+async fn load_tls_config(tls: &TlsConfig) -> Result<RustlsConfig, String> {
+    // rustls needs a process-wide crypto provider; ignore the error if one is already installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    match (&tls.cert_path, &tls.key_path) {
+        (Some(cert), Some(key)) => RustlsConfig::from_pem_file(cert, key)
+            .await
+            .map_err(|e| format!("cannot load certificate {} / key {}: {}", cert.display(), key.display(), e)),
+        (None, None) if tls.self_signed => {
+            let names = vec![String::from("localhost"), String::from("127.0.0.1")];
+            let certified = rcgen::generate_simple_self_signed(names)
+                .map_err(|e| format!("cannot generate self-signed certificate: {}", e))?;
+            RustlsConfig::from_pem(
+                certified.cert.pem().into_bytes(),
+                certified.key_pair.serialize_pem().into_bytes(),
+            )
+                .await
+                .map_err(|e| format!("cannot use self-signed certificate: {}", e))
+        }
+        _ => Err(String::from("tls needs both cert_path and key_path, or self_signed: true")),
+    }
+}
+
+// Resolves on Ctrl+C or SIGTERM
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => { sig.recv().await; }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// Resolves once the shutdown flag has been raised
+async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+// Starts the HTTP and WebSocket server; returns after a shutdown signal once connections are closed
+pub fn run_server(
+    watcher: &LogWatcher,
+    live: Arc<LiveSources>,
+    sandbox: Arc<PathSandbox>,
+    server_config: ServerConfig,
+    timeline_config: TimelineConfig,
+    recent_errors: RecentErrors,
+) {
+    let addr: SocketAddr = match server_config.address.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Invalid server address {}: {}", server_config.address, e);
+            return;
+        }
+    };
     let html_path = "static/dashboard.html";
 
     let Html(html) = load_html(html_path);
     let html = Arc::new(html);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let log_tx = watcher.log_tx.clone();
     let state = AppState {
-        cmd_tx,
+        cmd_tx: watcher.cmd_tx.clone(),
         log_tx: log_tx.clone(),
-        context: context.clone(),
+        context: Arc::clone(&watcher.context),
+        sandbox,
+        shutdown: shutdown_rx.clone(),
+        status: Arc::clone(&watcher.status),
+        recent_errors,
+        started_at: diagnostics::unix_now(),
+        rules: Arc::clone(&watcher.rules),
+        live,
+        merged: Arc::new(Mutex::new(HashMap::new())),
+        reorder_window: Duration::from_millis(timeline_config.reorder_window_ms),
     };
 
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async move {
         let html_for_handler = html.clone();
 
+        // On a signal, tell clients we are going away and close their sockets
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!("Shutdown signal received, closing connections");
+            let _ = log_tx.send(WsEventTx::Shutdown {
+                message: String::from("server is shutting down"),
+            });
+            let _ = shutdown_tx.send(true);
+        });
+
         // Set up routes for HTTP and WebSocket
         let app = Router::new().route(
             "/",
@@ -117,6 +405,8 @@ pub fn run_server(cmd_tx: Sender<WatchCommand>, log_tx: broadcast::Sender<WsEven
             }),
 
         )
+            .route("/status", get(status_handler))
+            .route("/metrics", get(metrics_handler))
             .route(
                 "/ws",
                 get(move |ws: WebSocketUpgrade, State(state): State<AppState>| {
@@ -126,124 +416,676 @@ pub fn run_server(cmd_tx: Sender<WatchCommand>, log_tx: broadcast::Sender<WsEven
             )
             .with_state(state);
 
-        // Bind TCP listener and start server
-        let listener = TcpListener::bind(addr).await.unwrap();
-        println!("HTTP server listening on http://{}/", addr);
+        if let Some(tls) = &server_config.tls {
+            // Serve HTTPS and WSS
+            let tls_config = match load_tls_config(tls).await {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("Failed to set up TLS: {}", e);
+                    return;
+                }
+            };
+            info!("HTTPS server listening on https://{}/", addr);
+
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                let shutdown_rx = shutdown_rx.clone();
+                async move {
+                    shutdown_requested(shutdown_rx).await;
+                    handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+                }
+            });
+
+            if let Err(e) = axum_server::bind_rustls(addr, tls_config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await {
+                error!("HTTPS server failed: {}", e);
+            }
+        } else {
+            // Bind TCP listener and start server
+            let listener = match TcpListener::bind(addr).await {
+                Ok(l) => l,
+                Err(e) => {
+                    error!("Failed to bind {}: {}", addr, e);
+                    return;
+                }
+            };
+            info!("HTTP server listening on http://{}/", addr);
+
+            let server = axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown_requested(shutdown_rx.clone()));
 
-        axum::serve(listener, app).await.unwrap();
+            // Stop accepting on shutdown, but don't wait forever for slow clients
+            let grace = async {
+                shutdown_requested(shutdown_rx.clone()).await;
+                tokio::time::sleep(SHUTDOWN_GRACE).await;
+            };
+            tokio::select! {
+                result = server => {
+                    if let Err(e) = result {
+                        error!("HTTP server failed: {}", e);
+                    }
+                }
+                _ = grace => warn!("Connections did not close in time, stopping anyway"),
+            }
+        }
+        info!("HTTP server stopped");
     });
 }
 
+// Reports watched sources, connected clients, channel depth and recent errors
+async fn status_handler(State(state): State<AppState>) -> Json<diagnostics::StatusReport> {
+    let status = state.status.lock().unwrap();
+    Json(diagnostics::status_report(&status, &state.recent_errors, state.started_at, state.log_tx.len()))
+}
+
+// Serves the log-derived metrics to Prometheus
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let text = state.context.lock().unwrap().metrics().render();
+    ([(axum::http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)], text)
+}
+
+// The existing records of a file, a merged timeline or (from its history) a live source
+fn source_records(state: &AppState, path: &std::path::Path) -> Vec<LogRecord> {
+    if let Some(name) = path.to_str().filter(|p| live_source::is_live_name(p)) {
+        state.live.history(name)
+    }
+    else if let Some(name) = merged_name(path) {
+        let files = merged_files(state, name).unwrap_or_default();
+        log_mgr::get_merged_records(&files, &state.rules)
+    }
+    else {
+        log_mgr::get_records(path, &state.rules)
+    }
+}
+
 // Handles WebSocket upgrade and delegates to socket handler
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     log_rx: broadcast::Receiver<WsEventTx>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state, log_rx))
+    ws.on_upgrade(move |socket| {
+        let client = state.status.lock().unwrap().client_connected();
+        let status = state.status.clone();
+        async move {
+            info!("Client connected");
+            handle_socket(socket, state, log_rx, client).await;
+            status.lock().unwrap().client_disconnected();
+            info!("Client disconnected");
+        }
+            .instrument(info_span!("socket", client))
+    })
+}
+
+// Resolves client-supplied paths through the sandbox, reporting denied ones to the client and
+// remembering the others in `known`. Live source and merged timeline names are not files and
+// are passed through unchanged.
+fn resolve_paths(
+    sandbox: &PathSandbox,
+    paths: Vec<String>,
+    known: &mut HashMap<String, PathBuf>,
+    client_tx: &mpsc::UnboundedSender<WsEventTx>,
+) -> Vec<PathBuf> {
+    let mut resolved = Vec::with_capacity(paths.len());
+    for requested in paths {
+        if live_source::is_live_name(&requested) || timeline::is_merged_name(&requested) {
+            resolved.push(PathBuf::from(requested));
+            continue;
+        }
+        match sandbox.resolve(&requested) {
+            Ok(path) => {
+                // Let the client map its own spelling of the path to the one used in events
+                let path_str = path.to_string_lossy().to_string();
+                if path_str != requested {
+                    let _ = client_tx.send(WsEventTx::PathResolved {
+                        requested: requested.clone(),
+                        path: path_str,
+                    });
+                }
+                known.insert(requested, path.clone());
+                resolved.push(path);
+            }
+            Err(message) => {
+                warn!(path = %requested, "Rejected path: {}", message);
+                let _ = client_tx.send(WsEventTx::Error { path: requested, message });
+            }
+        }
+    }
+    resolved
+}
+
+// Paths to release: those this client's earlier requests resolved to, by the client's spelling
+// or the canonical one, so a file deleted or rotated away since can still be released without
+// going through the file system again. Other paths are resolved as usual.
+fn registered_paths(
+    sandbox: &PathSandbox,
+    paths: Vec<String>,
+    known: &mut HashMap<String, PathBuf>,
+    client_tx: &mpsc::UnboundedSender<WsEventTx>,
+) -> Vec<PathBuf> {
+    let mut registered = Vec::with_capacity(paths.len());
+    let mut unknown = Vec::new();
+    for requested in paths {
+        let path = known.get(&requested)
+            .or_else(|| known.values().find(|path| path.to_str() == Some(requested.as_str())))
+            .cloned();
+        match path {
+            Some(path) => registered.push(path),
+            None => unknown.push(requested),
+        }
+    }
+    registered.extend(resolve_paths(sandbox, unknown, known, client_tx));
+    registered
+}
+
+// Whether a resolved path names a live source
+fn is_live_path(path: &std::path::Path) -> bool {
+    path.to_str().is_some_and(live_source::is_live_name)
+}
+
+// The files of a merged timeline, if there is one by that name
+fn merged_files(state: &AppState, name: &str) -> Option<Vec<PathBuf>> {
+    state.merged.lock().unwrap().get(name).map(|timeline| timeline.files.clone())
+}
+
+// Searches the existing records of a file, a merged timeline or (in its history) a live source
+fn search_source(state: &AppState, pattern: &str, field: Option<&str>, regex: bool, path: &std::path::Path) {
+    if let Some(name) = path.to_str().filter(|p| live_source::is_live_name(p)) {
+        log_mgr::search_live_source(&state.log_tx, pattern, field, regex, name, &state.live);
+    }
+    else if let Some(name) = merged_name(path) {
+        let files = merged_files(state, name).unwrap_or_default();
+        log_mgr::search_merged(&state.log_tx, pattern, field, regex, name, &files, &state.rules);
+    }
+    else if regex {
+        log_mgr::get_search_input_with_regex(&state.log_tx, pattern, field, path, &state.rules);
+    }
+    else {
+        log_mgr::call_search_string(&state.log_tx, pattern, field, path, &state.rules);
+    }
+}
+
+// Lets go of a client's use of a merged timeline; returns whether nobody uses it any more,
+// in which case it is forgotten and should be unmerged
+fn release_merged(merged: &mut HashMap<String, MergedTimeline>, name: &str, client: u64) -> bool {
+    let Some(timeline) = merged.get_mut(name) else {
+        return false;
+    };
+    timeline.clients.remove(&client);
+    if !timeline.clients.is_empty() {
+        return false;
+    }
+    merged.remove(name);
+    true
+}
+
+// The merged timeline a resolved path names, if it is one
+fn merged_name(path: &std::path::Path) -> Option<&str> {
+    path.to_str().filter(|p| timeline::is_merged_name(p))
+}
+
+// Tells all dashboards about a changed alert, or the requesting one why it could not be changed
+fn send_alert_update(
+    result: Result<Alert, String>,
+    log_tx: &broadcast::Sender<WsEventTx>,
+    client_tx: &mpsc::UnboundedSender<WsEventTx>,
+) {
+    match result {
+        Ok(alert) => {
+            info!(alert = alert.id, state = ?alert.state, "Alert updated");
+            let _ = log_tx.send(WsEventTx::Alert(alert));
+        }
+        Err(message) => {
+            let _ = client_tx.send(WsEventTx::Error { path: String::new(), message });
+        }
+    }
 }
 
 // Handles communication with a single WebSocket client
 async fn handle_socket(
     socket: WebSocket,
     state: AppState,
-    mut log_rx: broadcast::Receiver<WsEventTx>
+    mut log_rx: broadcast::Receiver<WsEventTx>,
+    client: u64,
 ) {
 
     let (mut ws_tx, mut ws_rx) = socket.split();
 
+    // Events meant only for this client (e.g. denied paths)
+    let (client_tx, mut client_rx) = mpsc::unbounded_channel::<WsEventTx>();
+
+    // Canonical paths of the files this client asked for, by the spelling it used
+    let mut known: HashMap<String, PathBuf> = HashMap::new();
+
+    // Show what already fired before this dashboard connected, and what is silenced
+    {
+        let ctx = state.context.lock().unwrap();
+        let _ = client_tx.send(WsEventTx::Alerts { alerts: ctx.alert_history().replay() });
+        let _ = client_tx.send(WsEventTx::Silences { silences: ctx.silences().list(timestamp::now_millis()) });
+    }
+
     // Spawn a task to send log events to the client
     tokio::spawn(async move {
-        while let Ok(event) = log_rx.recv().await {
+        loop {
+            let event = tokio::select! {
+                Ok(event) = log_rx.recv() => event,
+                Some(event) = client_rx.recv() => event,
+                else => break,
+            };
             let json = serde_json::to_string(&event).unwrap();
-            let _ = ws_tx
-                .send(Message::Text(json.into()))
-                .await;
+            if ws_tx.send(Message::Text(json.into())).await.is_err() {
+                break;
+            }
+
+            // After the shutdown notice, close the socket from our side
+            if let WsEventTx::Shutdown { .. } = event {
+                let _ = ws_tx.send(Message::Close(None)).await;
+                break;
+            }
         }
     });
 
     // Main loop to receive and handle client messages
-    while let Some(Ok(msg)) = ws_rx.next().await {
+    let mut shutdown = state.shutdown.clone();
+    loop {
+        let msg = tokio::select! {
+            msg = ws_rx.next() => msg,
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        let Some(Ok(msg)) = msg else { break };
+
         if let Message::Text(text) = msg {
             match serde_json::from_str::<ClientMessage>(&text) {
                 Ok(ClientMessage::WatchPaths { paths }) => {
-                    // Add paths to watcher
-                    let paths_buf = paths.into_iter()
-                        .map(PathBuf::from)
+                    // Add paths to watcher; live sources replay their spooled history instead
+                    for path in resolve_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        if let Some(name) = merged_name(&path) {
+                            let files = state.merged.lock().unwrap().get_mut(name).map(|timeline| {
+                                timeline.clients.insert(client);
+                                timeline.files.clone()
+                            });
+                            match files {
+                                Some(files) => {
+                                    let (task_state, task_name, span) = (state.clone(), name.to_string(), tracing::Span::current());
+                                    let sent = tokio::task::spawn_blocking(move || {
+                                        let _span = span.entered();
+                                        log_mgr::send_merged_history(&task_state.log_tx, &task_name, &files, &task_state.rules);
+                                    }).await;
+                                    if let Err(e) = sent {
+                                        warn!(name = %name, "Sending merged history failed: {}", e);
+                                    }
+                                }
+                                None => {
+                                    let _ = client_tx.send(WsEventTx::Error {
+                                        path: name.to_string(),
+                                        message: format!("no merged timeline named {}", name),
+                                    });
+                                }
+                            }
+                            continue;
+                        }
+                        match path.to_str().filter(|p| live_source::is_live_name(p)) {
+                            Some(name) => { state.live.send_history(name); }
+                            None => state.cmd_tx.send(WatchCommand::Add(path)).expect("failed to create watcher"),
+                        }
+                    }
+                }
+                Ok(ClientMessage::WatchMerged { name, paths }) => {
+                    // Merge files into one timeline; live sources and other timelines cannot be merged
+                    let name = format!("{}{}", MERGED_SCHEME, name.trim_start_matches(MERGED_SCHEME));
+                    let files: Vec<PathBuf> = resolve_paths(&state.sandbox, paths, &mut known, &client_tx).into_iter()
+                        .filter(|path| {
+                            let file = !is_live_path(path) && merged_name(path).is_none();
+                            if !file {
+                                let _ = client_tx.send(WsEventTx::Error {
+                                    path: name.clone(),
+                                    message: format!("cannot merge {}: only files can be merged", path.display()),
+                                });
+                            }
+                            file
+                        })
                         .collect();
-                    state.cmd_tx.send(WatchCommand::Add(paths_buf)).expect("failed to create watcher");
+                    if files.is_empty() {
+                        continue;
+                    }
+                    debug!(name = %name, paths = ?files, "Merge request");
+                    // Joining a timeline other clients use is fine; changing its files under them is not
+                    let merge = {
+                        let mut merged = state.merged.lock().unwrap();
+                        match merged.get_mut(&name) {
+                            Some(timeline) if timeline.files == files => {
+                                timeline.clients.insert(client);
+                                Ok(false)
+                            }
+                            Some(timeline) if timeline.clients.iter().any(|other| *other != client) => {
+                                Err(format!("merged timeline {} is in use with other files", name))
+                            }
+                            _ => {
+                                merged.insert(name.clone(), MergedTimeline { files: files.clone(), clients: HashSet::from([client]) });
+                                Ok(true)
+                            }
+                        }
+                    };
+                    match merge {
+                        Ok(merge) => {
+                            let (task_state, task_name, task_files, span) = (state.clone(), name.clone(), files.clone(), tracing::Span::current());
+                            let sent = tokio::task::spawn_blocking(move || {
+                                let _span = span.entered();
+                                log_mgr::send_merged_history(&task_state.log_tx, &task_name, &task_files, &task_state.rules)
+                            }).await;
+                            let lines = match sent {
+                                Ok(lines) => lines,
+                                Err(e) => {
+                                    warn!(name = %name, "Sending merged history failed: {}", e);
+                                    // A timeline merged before is still being tailed and must be unmerged
+                                    if release_merged(&mut state.merged.lock().unwrap(), &name, client) && !merge {
+                                        state.cmd_tx.send(WatchCommand::Unmerge(name)).expect("failed to unmerge");
+                                    }
+                                    continue;
+                                }
+                            };
+                            if merge {
+                                let files = files.into_iter().zip(lines).collect();
+                                state.cmd_tx.send(WatchCommand::Merge { name, files, window: state.reorder_window })
+                                    .expect("failed to merge");
+                            }
+                        }
+                        Err(message) => {
+                            let _ = client_tx.send(WsEventTx::Error { path: name, message });
+                        }
+                    }
                 }
                 Ok(ClientMessage::StartTailing { paths}) => {
-                    println!("Start tailing log(s)");
-                    let paths_buf = paths.into_iter()
-                        .map(PathBuf::from)
-                        .collect();
-                    state.cmd_tx.send(WatchCommand::Add(paths_buf)).expect("failed to create watcher");
+                    debug!(?paths, "Start tailing");
+                    for path in resolve_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        if !is_live_path(&path) && merged_name(&path).is_none() {
+                            state.cmd_tx.send(WatchCommand::Add(path)).expect("failed to create watcher");
+                        }
+                    }
                 }
                 Ok(ClientMessage::StopTailing { paths }) => {
-                    println!("Stop tailing {}", paths.join(", "));
-                    let paths_buf = paths.into_iter()
-                        .map(PathBuf::from)
-                        .collect();
-                    state.cmd_tx.send(WatchCommand::Remove(paths_buf)).expect("failed to remove watcher");
+                    debug!(?paths, "Stop tailing");
+                    for path in registered_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        if let Some(name) = merged_name(&path) {
+                            if release_merged(&mut state.merged.lock().unwrap(), name, client) {
+                                state.cmd_tx.send(WatchCommand::Unmerge(name.to_string())).expect("failed to unmerge");
+                            }
+                        }
+                        else if !is_live_path(&path) {
+                            state.cmd_tx.send(WatchCommand::Remove(path)).expect("failed to remove watcher");
+                        }
+                    }
                 }
-                Ok(ClientMessage::Search { paths, pattern, regex }) => {
-                    // Perform search (regex or string)
-                    let paths_buf: Vec<_> = paths
-                        .into_iter()
-                        .map(PathBuf::from)
-                        .collect();
-                    
-                    if regex {
-                        log_mgr::get_search_input_with_regex(&state.log_tx, &pattern, paths_buf);
+                Ok(ClientMessage::Search { paths, pattern, regex, field }) => {
+                    // Perform search (regex or string); live sources are searched in their history
+                    for path in resolve_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        let (task_state, task_pattern, task_field, span) = (state.clone(), pattern.clone(), field.clone(), tracing::Span::current());
+                        let searched = tokio::task::spawn_blocking(move || {
+                            let _span = span.entered();
+                            search_source(&task_state, &task_pattern, task_field.as_deref(), regex, &path);
+                        }).await;
+                        if let Err(e) = searched {
+                            warn!("Search failed: {}", e);
+                        }
+                    }
+                }
+                Ok(ClientMessage::Volume { paths, pattern, regex, bucket_ms }) => {
+                    // Count the existing records per time bucket, then keep counting new ones
+                    let query = match VolumeQuery::new(pattern, regex) {
+                        Ok(query) => query,
+                        Err(message) => {
+                            let _ = client_tx.send(WsEventTx::Error { path: String::new(), message });
+                            continue;
+                        }
+                    };
+                    for path in resolve_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        let scan = state.context.lock().unwrap().begin_scan(path.clone(), client);
+                        let (task_state, task_query, span) = (state.clone(), query.clone(), tracing::Span::current());
+                        let counted = tokio::task::spawn_blocking(move || {
+                            let _span = span.entered();
+                            let records = source_records(&task_state, &path);
+                            let name = path.to_string_lossy().to_string();
+                            let bucket_ms = log_mgr::send_volume(&task_state.log_tx, &name, &records, &task_query, bucket_ms);
+                            (records, bucket_ms)
+                        }).await;
+                        let mut ctx = state.context.lock().unwrap();
+                        match counted {
+                            Ok((records, bucket_ms)) => ctx.track_volume(scan, &records, query.clone(), bucket_ms),
+                            Err(e) => {
+                                warn!("Volume scan failed: {}", e);
+                                ctx.end_scan(scan);
+                            }
+                        }
                     }
-                    else {
-                        log_mgr::call_search_string(&state.log_tx, &pattern, paths_buf);
+                }
+                Ok(ClientMessage::Templates { paths }) => {
+                    // Mine the existing records on a blocking thread, then keep mining new ones
+                    for path in resolve_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        let (scan, mut miner) = {
+                            let mut ctx = state.context.lock().unwrap();
+                            (ctx.begin_scan(path.clone(), client), ctx.templates().miner())
+                        };
+                        let (task_state, span) = (state.clone(), tracing::Span::current());
+                        let mined = tokio::task::spawn_blocking(move || {
+                            let name = path.to_string_lossy().to_string();
+                            let _span = info_span!(parent: &span, "templates", path = %name).entered();
+                            let records = source_records(&task_state, &path);
+                            miner.mine(&records);
+                            let mined = templates::source_templates(&name, &miner);
+                            info!(records = records.len(), templates = mined.templates.len(), "Templates mined");
+                            let _ = task_state.log_tx.send(WsEventTx::Templates(mined));
+                            (records, miner)
+                        }).await;
+                        let mut ctx = state.context.lock().unwrap();
+                        match mined {
+                            Ok((records, miner)) => ctx.track_templates(scan, &records, miner),
+                            Err(e) => {
+                                warn!("Template mining failed: {}", e);
+                                ctx.end_scan(scan);
+                            }
+                        }
                     }
                 }
-                Ok(ClientMessage::FilterBy { paths, pattern}) => {
+                Ok(ClientMessage::FilterTemplate { paths, id, exclude }) => {
+                    // Filter the tail by template and show the existing records that pass as search results
+                    for path in resolve_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        let name = path.to_string_lossy().to_string();
+                        let filter = TemplateFilter { id, exclude };
+                        let miner = {
+                            let mut ctx = state.context.lock().unwrap();
+                            ctx.set_template_filter(path.clone(), filter).map(|_| ctx.templates().get(&path).cloned())
+                        };
+                        let miner = match miner {
+                            Ok(Some(miner)) => miner,
+                            Ok(None) => continue,
+                            Err(message) => {
+                                let _ = client_tx.send(WsEventTx::Error { path: name, message });
+                                continue;
+                            }
+                        };
+                        let (task_state, span) = (state.clone(), tracing::Span::current());
+                        let filtered = tokio::task::spawn_blocking(move || {
+                            let _span = span.entered();
+                            let lines = source_records(&task_state, &path).iter()
+                                .filter(|record| filter.passes(&miner, &record.text))
+                                .map(LogRecord::numbered)
+                                .collect();
+                            let _ = task_state.log_tx.send(WsEventTx::SearchResult { path: name, lines });
+                        }).await;
+                        if let Err(e) = filtered {
+                            warn!("Template filter failed: {}", e);
+                        }
+                    }
+                }
+                Ok(ClientMessage::FilterBy { paths, pattern, field }) => {
                     // Set filter for paths
-                    let paths_buf: Vec<_> = paths
-                        .into_iter()
-                        .map(PathBuf::from)
-                        .collect();
-
+                    let paths_buf = resolve_paths(&state.sandbox, paths, &mut known, &client_tx);
                     let mut ctx = state.context.lock().unwrap();
-                    println!("Notify request: paths={:?}, pattern={}", paths_buf, pattern);
-                    ctx.set_filter(paths_buf, pattern);
+                    debug!(paths = ?paths_buf, pattern = %pattern, field = ?field, "Filter request");
+                    for path in paths_buf {
+                        ctx.set_filter(path, pattern.clone(), field.clone());
+                    }
 
                 }
-                Ok(ClientMessage::NotifyWhen { paths, pattern}) => {
-                    // Set notification for paths
-                    let paths_buf: Vec<_> = paths
-                        .into_iter()
-                        .map(PathBuf::from)
-                        .collect();
-
-                    let mut ctx = state.context.lock().unwrap();
-                    println!("Notify request: paths={:?}, pattern={}", paths_buf, pattern);
-                    ctx.set_notification(paths_buf, pattern);
-
+                Ok(ClientMessage::NotifyWhen { paths, pattern, id, severity, regex, cooldown_ms, threshold, ratio, absence, new_pattern, anomaly, sinks }) => {
+                    // Set a notification rule for paths
+                    let paths_buf = resolve_paths(&state.sandbox, paths, &mut known, &client_tx);
+                    debug!(paths = ?paths_buf, pattern = %pattern, "Notify request");
+                    let closed = {
+                        let ctx = state.context.lock().unwrap();
+                        sinks.iter().find(|sink| !ctx.open_to_dashboard(sink)).cloned()
+                    };
+                    if let Some(sink) = closed {
+                        let message = format!("sink {} is not open to dashboard rules", sink);
+                        let _ = client_tx.send(WsEventTx::Error { path: String::new(), message });
+                        continue;
+                    }
+                    for path in paths_buf {
+                        let rule_id = id.clone().unwrap_or_else(|| format!("notify:{}", path.display()));
+                        let rule = NotificationRule::new(rule_id, &pattern, regex)
+                            .and_then(|mut rule| rule.set_trigger(threshold.as_ref(), ratio.as_ref(), absence.as_ref(), new_pattern.as_ref(), anomaly.as_ref()).map(|_| rule));
+                        let mut rule = match rule {
+                            Ok(rule) => rule,
+                            Err(message) => {
+                                let _ = client_tx.send(WsEventTx::Error { path: path.to_string_lossy().to_string(), message });
+                                continue;
+                            }
+                        };
+                        rule.severity = severity;
+                        rule.sinks = sinks.clone();
+                        if let Some(ms) = cooldown_ms
+                            && let Err(message) = rule.set_cooldown(ms) {
+                            let _ = client_tx.send(WsEventTx::Error { path: path.to_string_lossy().to_string(), message });
+                            continue;
+                        }
+                        state.context.lock().unwrap().set_notification_rule(path, rule);
+                    }
                 }
                 Ok(ClientMessage::RemoveFilter { paths }) => {
                     // Remove filter for path
-                    let path: PathBuf = paths[0].clone().into();
                     let mut ctx = state.context.lock().unwrap();
-                    ctx.remove_filter(path)
+                    for path in registered_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        ctx.remove_filter(&path);
+                    }
                 }
                 Ok(ClientMessage::RemoveNotification { paths }) => {
                     // Remove notification for path
-                    let path: PathBuf = paths[0].clone().into();
                     let mut ctx = state.context.lock().unwrap();
-                    ctx.remove_notification(path)
+                    for path in registered_paths(&state.sandbox, paths, &mut known, &client_tx) {
+                        ctx.remove_notification(&path);
+                    }
+                }
+                Ok(ClientMessage::ListSources) => {
+                    // Offer the live sources seen so far, the merged timelines and the discovered container logs
+                    let mut sources = state.live.names();
+                    let mut merged: Vec<String> = state.merged.lock().unwrap().keys().cloned().collect();
+                    merged.sort();
+                    sources.extend(merged);
+                    sources.extend(state.sandbox.container_labels());
+                    let _ = client_tx.send(WsEventTx::Sources { sources });
+                }
+                Ok(ClientMessage::ListAlerts { limit }) => {
+                    let ctx = state.context.lock().unwrap();
+                    let alerts = match limit {
+                        Some(limit) => ctx.alert_history().latest(limit),
+                        None => ctx.alert_history().replay(),
+                    };
+                    let _ = client_tx.send(WsEventTx::Alerts { alerts });
+                }
+                Ok(ClientMessage::AckAlert { id, by }) => {
+                    let result = state.context.lock().unwrap().alert_history_mut().acknowledge(id, &by);
+                    send_alert_update(result, &state.log_tx, &client_tx);
+                }
+                Ok(ClientMessage::ResolveAlert { id, by }) => {
+                    let result = state.context.lock().unwrap().alert_history_mut().resolve(id, &by);
+                    send_alert_update(result, &state.log_tx, &client_tx);
+                }
+                Ok(ClientMessage::SilenceAlert { id, duration_ms, by }) => {
+                    let result = state.context.lock().unwrap().alert_history_mut().silence(id, Duration::from_millis(duration_ms), &by);
+                    send_alert_update(result, &state.log_tx, &client_tx);
+                }
+                Ok(ClientMessage::ListSilences) => {
+                    let silences = state.context.lock().unwrap().silences().list(timestamp::now_millis());
+                    let _ = client_tx.send(WsEventTx::Silences { silences });
+                }
+                Ok(ClientMessage::AddSilence { silence, by }) => {
+                    // Every dashboard sees the new list
+                    let now = timestamp::now_millis();
+                    let mut ctx = state.context.lock().unwrap();
+                    match Silence::new(silence, Some(by)).and_then(|silence| ctx.silences_mut().add(silence, now)) {
+                        Ok(()) => {
+                            let _ = state.log_tx.send(WsEventTx::Silences { silences: ctx.silences().list(now) });
+                        }
+                        Err(message) => {
+                            let _ = client_tx.send(WsEventTx::Error { path: String::new(), message });
+                        }
+                    }
+                }
+                Ok(ClientMessage::RemoveSilence { id }) => {
+                    let mut ctx = state.context.lock().unwrap();
+                    if ctx.silences_mut().remove(&id) {
+                        info!(silence = %id, "Removed silence");
+                        let _ = state.log_tx.send(WsEventTx::Silences { silences: ctx.silences().list(timestamp::now_millis()) });
+                    } else {
+                        let _ = client_tx.send(WsEventTx::Error { path: String::new(), message: format!("no silence {}", id) });
+                    }
                 }
                 Err(e) => {
                     // Handle invalid client message
-                    eprintln!("Invalid WS message: {}", e);
+                    warn!("Invalid WS message: {}", e);
                 }
             }
         }
     }
+
+    // Stop what only this client asked for, then unmerge the timelines only it used
+    state.context.lock().unwrap().release_client(client);
+    let released: Vec<String> = {
+        let mut merged = state.merged.lock().unwrap();
+        let names: Vec<String> = merged.keys().cloned().collect();
+        names.into_iter().filter(|name| release_merged(&mut merged, name, client)).collect()
+    };
+    for name in released {
+        let _ = state.cmd_tx.send(WatchCommand::Unmerge(name));
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn self_signed_certificate_is_generated() {
+        let tls = TlsConfig { self_signed: true, ..TlsConfig::default() };
+        assert!(load_tls_config(&tls).await.is_ok());
+    }
 
+    #[tokio::test]
+    async fn certificate_files_are_loaded() {
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let dir = std::env::temp_dir();
+        let cert = dir.join(format!("loggyxp-{}-cert.pem", std::process::id()));
+        let key = dir.join(format!("loggyxp-{}-key.pem", std::process::id()));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+        let tls = TlsConfig { cert_path: Some(cert.clone()), key_path: Some(key.clone()), self_signed: false };
+        let loaded = load_tls_config(&tls).await;
+        let swapped = TlsConfig { cert_path: Some(key.clone()), key_path: Some(cert.clone()), self_signed: false };
+        let swapped = load_tls_config(&swapped).await;
+        let _ = std::fs::remove_file(&cert);
+        let _ = std::fs::remove_file(&key);
+        assert!(loaded.is_ok());
+        assert!(swapped.unwrap_err().starts_with("cannot load certificate"));
+    }
+
+    #[tokio::test]
+    async fn incomplete_settings_are_refused() {
+        let cert_only = TlsConfig { cert_path: Some(PathBuf::from("cert.pem")), ..TlsConfig::default() };
+        assert!(load_tls_config(&cert_only).await.is_err());
+        assert!(load_tls_config(&TlsConfig::default()).await.is_err());
+    }
+}
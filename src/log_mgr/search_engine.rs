@@ -1,10 +1,18 @@
 extern crate regex;
 use regex::Regex;
+use tracing::info;
+use crate::log_mgr::log_record::{line_records, LogRecord};
 
 
 // Searches for a substring (case-insensitive) in each line of the content.
 // Returns matching lines with their line numbers.
-pub fn search_string(content: &String, word: &String) -> Vec<String> {
+pub fn search_string(content: &str, word: &str) -> Vec<String> {
+    search_records(&line_records(content), word, None)
+}
+
+// Searches for a substring (case-insensitive) in each record, or in one of its fields.
+// Returns matching records prefixed with the line number of their first line.
+pub fn search_records(records: &[LogRecord], word: &str, field: Option<&str>) -> Vec<String> {
     let mut lines = Vec::new();
 
     // If the search word is empty, return empty result.
@@ -14,10 +22,10 @@ pub fn search_string(content: &String, word: &String) -> Vec<String> {
 
     let needle = word.to_lowercase();
 
-    // Iterate over each line and check if it contains the search word.
-    for (idx, line) in content.lines().enumerate() {
-        if line.to_lowercase().contains(&needle) {
-            lines.push(format!("{}: {}", idx + 1, line));
+    // Iterate over each record and check if it contains the search word.
+    for record in records {
+        if record.matched_text(field).is_some_and(|text| text.to_lowercase().contains(&needle)) {
+            lines.push(record.numbered());
         }
     }
 
@@ -26,29 +34,33 @@ pub fn search_string(content: &String, word: &String) -> Vec<String> {
 
 // Searches for lines matching a regex pattern in the content.
 // Returns matching lines with their line numbers.
-pub fn search_input_pattern(content: &String, pattern: &String) -> Vec<String>
+pub fn search_input_pattern(content: &str, pattern: &str) -> Vec<String>
+{
+    search_records_pattern(&line_records(content), pattern, None)
+}
+
+// Searches for records (or one of their fields) matching a regex pattern.
+// Returns matching records prefixed with the line number of their first line.
+pub fn search_records_pattern(records: &[LogRecord], pattern: &str, field: Option<&str>) -> Vec<String>
 {
     let mut matches: Vec<String> = Vec::new();
 
     // Try to compile the regex pattern.
     match Regex::new(pattern) {
         Ok(regex) => {
-            // Filter lines that match the regex and collect them.
-            matches = content
-                .lines()
-                .enumerate()
-                .filter_map(|(idx, line)| {
-                    regex.is_match(line)
-                        .then(|| format!("{}: {}", idx + 1, line))
-                })
+            // Filter records that match the regex and collect them.
+            matches = records
+                .iter()
+                .filter(|record| record.matched_text(field).is_some_and(|text| regex.is_match(text)))
+                .map(LogRecord::numbered)
                 .collect();
-            return matches;
+            matches
         }
-        Err(_) => {
+        Err(e) => {
             // If the pattern is invalid, return an error message.
-            println!("Invalid pattern");
+            info!(pattern = %pattern, "Invalid search regex: {}", e);
             matches.push(String::from("loggyxp: invalid regex pattern"));
-            return matches;
+            matches
         }
     }
 }
@@ -0,0 +1,363 @@
+use glob::Pattern;
+use serde::Serialize;
+use tracing::{info, warn};
+use crate::log_mgr::config::{ScheduleConfig, SilenceConfig};
+use crate::log_mgr::notification::{Matcher, Notification};
+use crate::log_mgr::timestamp;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+const DAY_NAMES: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+/// Most silences clients may add; more are refused until some end or are removed
+const MAX_CLIENT_SILENCES: usize = 1000;
+
+/// A daily window in minutes after midnight, at an offset from UTC
+#[derive(Debug, Clone)]
+struct Schedule {
+    start: i64,
+    end: i64,
+    // Days the window starts on, Monday = 0; all when empty
+    days: Vec<i64>,
+    offset: i64,
+}
+
+impl Schedule {
+    fn new(config: &ScheduleConfig) -> Result<Schedule, String> {
+        let days = config.days.iter()
+            .map(|day| {
+                // Full names or their first three letters, in any case
+                let day = day.to_lowercase();
+                DAY_NAMES.iter()
+                    .position(|name| day == *name || day == name[..3])
+                    .map(|idx| idx as i64)
+                    .ok_or_else(|| format!("unknown day {}", day))
+            })
+            .collect::<Result<_, _>>()?;
+        let offset = match &config.utc_offset {
+            Some(offset) => parse_offset(offset).ok_or_else(|| format!("invalid utc_offset {}", offset))?,
+            None => 0,
+        };
+        Ok(Schedule {
+            start: parse_clock(&config.start).ok_or_else(|| format!("invalid start {}", config.start))?,
+            end: parse_clock(&config.end).ok_or_else(|| format!("invalid end {}", config.end))?,
+            days,
+            offset,
+        })
+    }
+
+    fn contains(&self, millis: i64) -> bool {
+        let minutes = millis.div_euclid(60_000) + self.offset;
+        let (day, minute) = (minutes.div_euclid(MINUTES_PER_DAY), minutes.rem_euclid(MINUTES_PER_DAY));
+        // 1970-01-01 was a Thursday
+        let starts_on = |day: i64| self.days.is_empty() || self.days.contains(&(day + 3).rem_euclid(7));
+
+        if self.start <= self.end {
+            starts_on(day) && self.start <= minute && minute < self.end
+        } else {
+            // Spans midnight: the late part started today, the early part yesterday
+            (minute >= self.start && starts_on(day)) || (minute < self.end && starts_on(day - 1))
+        }
+    }
+}
+
+/// A silence in force: what it matches and when
+#[derive(Debug, Clone)]
+pub struct Silence {
+    config: SilenceConfig,
+    created_by: Option<String>,
+    sources: Vec<Pattern>,
+    matcher: Option<Matcher>,
+    starts_at: Option<i64>,
+    ends_at: Option<i64>,
+    schedule: Option<Schedule>,
+}
+
+impl Silence {
+    /// Compiles a silence from the config or a client
+    pub fn new(config: SilenceConfig, created_by: Option<String>) -> Result<Silence, String> {
+        let fail = |message: String| format!("silence {}: {}", config.id, message);
+        let sources = config.sources.iter()
+            .map(|glob| Pattern::new(glob).map_err(|e| fail(format!("invalid source glob {}: {}", glob, e))))
+            .collect::<Result<_, _>>()?;
+        let matcher = config.pattern.as_deref()
+            .map(|pattern| Matcher::new(pattern, config.regex))
+            .transpose()
+            .map_err(|e| fail(format!("invalid regex: {}", e)))?;
+        let time = |text: &Option<String>| match text {
+            Some(text) => timestamp::parse_millis(text).map(Some).ok_or_else(|| fail(format!("invalid time {}", text))),
+            None => Ok(None),
+        };
+        let (starts_at, ends_at) = (time(&config.starts_at)?, time(&config.ends_at)?);
+        let schedule = config.schedule.as_ref().map(Schedule::new).transpose().map_err(fail)?;
+
+        Ok(Silence { sources, matcher, starts_at, ends_at, schedule, created_by, config })
+    }
+
+    /// Identifies the silence in alerts and client messages
+    pub fn id(&self) -> &str {
+        &self.config.id
+    }
+
+    /// Whether the silence is in force at `now` (Unix milliseconds)
+    pub fn is_active(&self, now: i64) -> bool {
+        self.starts_at.is_none_or(|start| start <= now)
+            && self.ends_at.is_none_or(|end| now < end)
+            && self.schedule.as_ref().is_none_or(|schedule| schedule.contains(now))
+    }
+
+    /// Whether the silence has ended for good
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.ends_at.is_some_and(|end| end <= now)
+    }
+
+    /// Whether the silence covers a notification
+    pub fn matches(&self, notification: &Notification) -> bool {
+        (self.config.rules.is_empty() || self.config.rules.contains(&notification.rule_id))
+            && (self.sources.is_empty() || self.sources.iter().any(|glob| glob.matches(&notification.path)))
+            && self.matcher.as_ref().is_none_or(|matcher| matcher.matches(&notification.line))
+    }
+}
+
+/// A silence as shown to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct SilenceInfo {
+    #[serde(flatten)]
+    pub config: SilenceConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    pub active: bool,
+}
+
+/// The configured silences and those added by clients
+#[derive(Debug, Default)]
+pub struct Silences {
+    silences: Vec<Silence>,
+}
+
+impl Silences {
+    /// Compiles the configured silences, skipping (and reporting) invalid ones
+    pub fn from_config(configs: &[SilenceConfig]) -> Silences {
+        let silences = configs.iter()
+            .cloned()
+            .filter_map(|config| match Silence::new(config, None) {
+                Ok(silence) => Some(silence),
+                Err(e) => {
+                    warn!("Ignoring silence: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Silences { silences }
+    }
+
+    /// Adds a silence, replacing one with the same id. Silences that ended are dropped first;
+    /// clients may add at most `MAX_CLIENT_SILENCES`.
+    pub fn add(&mut self, silence: Silence, now: i64) -> Result<(), String> {
+        self.prune(now);
+        let replaced = self.silences.iter().any(|other| other.id() == silence.id());
+        let added = self.silences.iter().filter(|other| other.created_by.is_some()).count();
+        if silence.created_by.is_some() && !replaced && added >= MAX_CLIENT_SILENCES {
+            return Err(format!("cannot add silence {}: at most {} silences can be added", silence.id(), MAX_CLIENT_SILENCES));
+        }
+        info!(silence = %silence.id(), by = silence.created_by.as_deref().unwrap_or("config"), "Added silence");
+        self.remove(silence.id());
+        self.silences.push(silence);
+        Ok(())
+    }
+
+    /// Removes a silence; returns whether it existed
+    pub fn remove(&mut self, id: &str) -> bool {
+        let before = self.silences.len();
+        self.silences.retain(|silence| silence.id() != id);
+        self.silences.len() != before
+    }
+
+    /// The active silence covering a notification, if any; silences that ended are dropped
+    pub fn matching(&mut self, notification: &Notification, now: i64) -> Option<&Silence> {
+        self.prune(now);
+        self.silences.iter().find(|silence| silence.is_active(now) && silence.matches(notification))
+    }
+
+    // Drops the silences that ended for good
+    fn prune(&mut self, now: i64) {
+        self.silences.retain(|silence| !silence.is_expired(now));
+    }
+
+    /// The silences that have not ended, for clients
+    pub fn list(&self, now: i64) -> Vec<SilenceInfo> {
+        self.silences.iter()
+            .filter(|silence| !silence.is_expired(now))
+            .map(|silence| SilenceInfo {
+                config: silence.config.clone(),
+                created_by: silence.created_by.clone(),
+                active: silence.is_active(now),
+            })
+            .collect()
+    }
+}
+
+// `HH:MM` as minutes after midnight
+fn parse_clock(text: &str) -> Option<i64> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    ((0..24).contains(&hours) && (0..60).contains(&minutes)).then_some(hours * 60 + minutes)
+}
+
+// `+02:00` or `-05:30` as minutes
+fn parse_offset(text: &str) -> Option<i64> {
+    let (sign, rest) = match text.trim().split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    parse_clock(rest).map(|minutes| sign * minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_mgr::notification::NotificationKind;
+
+    fn schedule(start: &str, end: &str, days: &[&str], utc_offset: Option<&str>) -> Schedule {
+        Schedule::new(&ScheduleConfig {
+            start: start.to_string(),
+            end: end.to_string(),
+            days: days.iter().map(|day| day.to_string()).collect(),
+            utc_offset: utc_offset.map(String::from),
+        }).unwrap()
+    }
+
+    // 2024-05-03 was a Friday
+    fn at(time: &str) -> i64 {
+        timestamp::parse_millis(time).unwrap()
+    }
+
+    #[test]
+    fn daily_window() {
+        let office = schedule("09:00", "17:00", &[], None);
+        assert!(office.contains(at("2024-05-03T09:00:00Z")));
+        assert!(office.contains(at("2024-05-03T16:59:59Z")));
+        assert!(!office.contains(at("2024-05-03T17:00:00Z")));
+        assert!(!office.contains(at("2024-05-03T08:59:59Z")));
+    }
+
+    #[test]
+    fn window_across_midnight() {
+        let nightly = schedule("22:00", "06:00", &[], None);
+        assert!(nightly.contains(at("2024-05-03T22:00:00Z")));
+        assert!(nightly.contains(at("2024-05-03T23:59:59Z")));
+        assert!(nightly.contains(at("2024-05-04T00:00:00Z")));
+        assert!(nightly.contains(at("2024-05-04T05:59:59Z")));
+        assert!(!nightly.contains(at("2024-05-04T06:00:00Z")));
+        assert!(!nightly.contains(at("2024-05-03T21:59:59Z")));
+    }
+
+    #[test]
+    fn weekdays_are_the_days_a_window_starts_on() {
+        let friday_night = schedule("22:00", "06:00", &["fri"], None);
+        assert!(friday_night.contains(at("2024-05-03T23:00:00Z")));
+        // Saturday morning is still Friday's window, Friday morning belongs to Thursday's
+        assert!(friday_night.contains(at("2024-05-04T03:00:00Z")));
+        assert!(!friday_night.contains(at("2024-05-03T03:00:00Z")));
+        assert!(!friday_night.contains(at("2024-05-04T23:00:00Z")));
+
+        let some_days = schedule("09:00", "17:00", &["Monday", "SAT"], None);
+        assert!(some_days.contains(at("2024-05-06T10:00:00Z")));
+        assert!(some_days.contains(at("2024-05-04T16:59:00Z")));
+        assert!(!some_days.contains(at("2024-05-07T10:00:00Z")));
+        assert!(!some_days.contains(at("2024-05-05T10:00:00Z")));
+    }
+
+    #[test]
+    fn utc_offset_moves_the_window_and_its_day() {
+        let berlin = schedule("09:00", "17:00", &["mon"], Some("+02:00"));
+        assert!(berlin.contains(at("2024-05-06T07:00:00Z")));
+        assert!(!berlin.contains(at("2024-05-06T06:59:00Z")));
+        // Monday 22:00 at -05:00 is Tuesday 03:00 UTC
+        let new_york = schedule("22:00", "23:00", &["mon"], Some("-05:00"));
+        assert!(new_york.contains(at("2024-05-07T03:30:00Z")));
+        assert!(!new_york.contains(at("2024-05-06T22:30:00Z")));
+    }
+
+    #[test]
+    fn before_the_epoch() {
+        // 1969-12-31 was a Wednesday
+        let wednesday = schedule("23:00", "23:59", &["wed"], None);
+        assert!(wednesday.contains(-60_000 * 30));
+        assert!(!schedule("23:00", "23:59", &["thu"], None).contains(-60_000 * 30));
+    }
+
+    #[test]
+    fn empty_window_never_matches() {
+        let empty = schedule("12:00", "12:00", &[], None);
+        assert!(!empty.contains(at("2024-05-03T12:00:00Z")));
+    }
+
+    fn silence(id: &str, ends_at: Option<&str>, created_by: Option<&str>) -> Silence {
+        let config: SilenceConfig = serde_json::from_value(serde_json::json!({ "id": id, "ends_at": ends_at })).unwrap();
+        Silence::new(config, created_by.map(String::from)).unwrap()
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            rule_id: String::from("errors"),
+            severity: Default::default(),
+            kind: NotificationKind::Match,
+            path: String::from("/var/log/app.log"),
+            line: String::from("1: error"),
+            message: None,
+            count: 1,
+            first_seen: 0,
+            last_seen: 0,
+        }
+    }
+
+    #[test]
+    fn ended_silences_are_dropped() {
+        let mut silences = Silences::default();
+        let now = at("2024-05-03T12:00:00Z");
+        silences.add(silence("deploy", Some("2024-05-03T13:00:00Z"), Some("alice")), now).unwrap();
+        silences.add(silence("forever", None, None), now).unwrap();
+        assert_eq!(silences.matching(&notification(), now).map(Silence::id), Some("deploy"));
+
+        let later = at("2024-05-03T13:00:00Z");
+        assert_eq!(silences.matching(&notification(), later).map(Silence::id), Some("forever"));
+        assert_eq!(silences.silences.len(), 1);
+    }
+
+    #[test]
+    fn clients_add_a_limited_number_of_silences() {
+        let mut silences = Silences::default();
+        let now = at("2024-05-03T12:00:00Z");
+        for idx in 0..MAX_CLIENT_SILENCES {
+            silences.add(silence(&format!("s{}", idx), None, Some("alice")), now).unwrap();
+        }
+        assert!(silences.add(silence("one-more", None, Some("alice")), now).is_err());
+        // Replacing one, or a configured one, is still possible
+        silences.add(silence("s0", None, Some("bob")), now).unwrap();
+        silences.add(silence("config", None, None), now).unwrap();
+        assert!(silences.remove("s1"));
+        silences.add(silence("one-more", None, Some("alice")), now).unwrap();
+        assert_eq!(silences.list(now).len(), MAX_CLIENT_SILENCES + 1);
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        let config = |start: &str, days: &[&str], offset: Option<&str>| ScheduleConfig {
+            start: start.to_string(),
+            end: String::from("06:00"),
+            days: days.iter().map(|day| day.to_string()).collect(),
+            utc_offset: offset.map(String::from),
+        };
+        assert!(Schedule::new(&config("24:00", &[], None)).is_err());
+        assert!(Schedule::new(&config("9", &[], None)).is_err());
+        assert!(Schedule::new(&config("22:00", &["funday"], None)).is_err());
+        // Only full day names and their three-letter abbreviations
+        assert!(Schedule::new(&config("22:00", &["Monday", "TUE", "wed"], None)).is_ok());
+        for day in ["monkey", "sunny", "mo", "thurs", ""] {
+            assert!(Schedule::new(&config("22:00", &[day], None)).is_err(), "{}", day);
+        }
+        assert!(Schedule::new(&config("22:00", &[], Some("02:00"))).is_err());
+        assert!(Schedule::new(&config("22:00", &[], Some("+"))).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use glob::Pattern;
+use tracing::warn;
+use crate::log_mgr::config::{SourceConfig, SourceEncoding, SourceFormat};
+use crate::log_mgr::log_format;
+use crate::log_mgr::log_record::{line_records, LogRecord};
+use crate::log_mgr::multiline::{self, MultilineRule};
+use crate::log_mgr::path_sandbox::PathSandbox;
+
+/// How long an unterminated line is held back when the source does not configure it
+const DEFAULT_PARTIAL_LINE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Per-source processing options resolved from the `sources` config section
+#[derive(Debug, Clone)]
+pub struct SourceOptions {
+    pub multiline: Option<MultilineRule>,
+    pub partial_line_timeout: Duration,
+    pub encoding: Option<SourceEncoding>,
+    pub rotated: bool,
+    pub format: Option<SourceFormat>,
+    /// Allowlist the rotations of a rotated source must pass; without one all of them are read
+    pub sandbox: Option<Arc<PathSandbox>>,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        SourceOptions {
+            multiline: None,
+            partial_line_timeout: DEFAULT_PARTIAL_LINE_TIMEOUT,
+            encoding: None,
+            rotated: false,
+            format: None,
+            sandbox: None,
+        }
+    }
+}
+
+impl SourceOptions {
+    /// Splits decoded text into records: lines, unwrapped from their container format, then
+    /// grouped into multi-line records
+    pub fn records(&self, text: &str) -> Vec<LogRecord> {
+        let lines = log_format::parse_records(self.format, line_records(text));
+        multiline::group_records(self.multiline.as_ref(), lines)
+    }
+}
+
+/// Compiled `sources` rules; the first rule whose glob matches a path wins
+#[derive(Debug, Default)]
+pub struct SourceRules {
+    rules: Vec<(Pattern, SourceOptions)>,
+    sandbox: Option<Arc<PathSandbox>>,
+}
+
+impl SourceRules {
+    /// Compiles the configured rules, skipping (and reporting) invalid ones
+    pub fn new(configs: &[SourceConfig]) -> SourceRules {
+        let mut rules = Vec::new();
+        for config in configs {
+            let pattern = match Pattern::new(&config.path) {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!(path = %config.path, "Ignoring source rule with invalid glob: {}", e);
+                    continue;
+                }
+            };
+
+            let multiline = match config.multiline.as_ref().map(MultilineRule::from_config).transpose() {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!(path = %config.path, "Ignoring source rule: {}", e);
+                    continue;
+                }
+            };
+
+            let partial_line_timeout = config.partial_line_timeout_ms
+                .map_or(DEFAULT_PARTIAL_LINE_TIMEOUT, Duration::from_millis);
+
+            rules.push((pattern, SourceOptions {
+                multiline,
+                partial_line_timeout,
+                encoding: config.encoding,
+                rotated: config.rotated,
+                format: config.format,
+                sandbox: None,
+            }));
+        }
+        SourceRules { rules, sandbox: None }
+    }
+
+    /// Only reads the rotations of rotated sources that `sandbox` allows, so a rotation cannot
+    /// open a file clients may not (e.g. through a symlink, or outside an allowed glob)
+    pub fn with_sandbox(mut self, sandbox: Arc<PathSandbox>) -> SourceRules {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Options for a source, or the defaults when no rule matches
+    pub fn options_for(&self, path: &Path) -> SourceOptions {
+        let mut options = self.rules.iter()
+            .find(|(pattern, _)| pattern.matches_path(path))
+            .map(|(_, options)| options.clone())
+            .unwrap_or_default();
+        options.sandbox = self.sandbox.clone();
+        options
+    }
+}
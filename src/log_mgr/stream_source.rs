@@ -0,0 +1,166 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, info_span, warn};
+use crate::log_mgr::config::FifoConfig;
+use crate::log_mgr::encoding::TextEncoding;
+use crate::log_mgr::live_source::LiveSources;
+
+/// Name of the live source fed by standard input
+pub const STDIN_SOURCE: &str = "stdin://stdin";
+
+/// Delay before reopening a FIFO that could not be opened; it doubles while the FIFO stays unusable
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between attempts to reopen a FIFO
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(60);
+
+/// Longest line forwarded as one record; longer lines are cut into several
+const MAX_LINE_SIZE: usize = 64 * 1024;
+
+// Streams cannot be seeked or interrupted, so their threads are not joined: they end with the process
+
+/// Reads standard input line by line into the `stdin://stdin` source until it is closed
+pub fn start_stdin(live: Arc<LiveSources>) {
+    thread::spawn(move || {
+        let _span = info_span!("stdin").entered();
+        info!("Reading standard input");
+        match forward_lines(io::stdin().lock(), STDIN_SOURCE, &live) {
+            Ok(()) => info!("Standard input closed"),
+            Err(e) => {
+                warn!("Failed to read standard input: {}", e);
+                live.source_failed(STDIN_SOURCE, &e.to_string());
+            }
+        }
+    });
+}
+
+/// Reads a named pipe into the `fifo://<name>` source, reopening it whenever its writer closes it.
+/// A FIFO that cannot be read, or a path that is not a FIFO, is reported once and retried with a
+/// growing delay.
+pub fn start_fifo(config: &FifoConfig, live: Arc<LiveSources>) {
+    let config = config.clone();
+    thread::spawn(move || {
+        let _span = info_span!("fifo", name = %config.name, path = %config.path.display()).entered();
+        let name = format!("fifo://{}", config.name);
+        let mut delay = None;
+        loop {
+            let result = open_fifo(&config.path)
+                .and_then(|file| forward_lines(BufReader::new(file), &name, &live));
+            match (result, delay) {
+                (Ok(()), _) => delay = None,
+                (Err(e), None) => {
+                    warn!("Failed to read FIFO, retrying: {}", e);
+                    delay = Some(REOPEN_DELAY);
+                }
+                (Err(e), Some(previous)) => {
+                    debug!("Failed to read FIFO again: {}", e);
+                    delay = Some((previous * 2).min(MAX_REOPEN_DELAY));
+                }
+            }
+            if let Some(delay) = delay {
+                thread::sleep(delay);
+            }
+        }
+    });
+}
+
+// Opens a named pipe, which blocks until a writer connects. Anything else is refused: a regular
+// file would be read to its end over and over. The file is checked again once open, in case the
+// path was replaced meanwhile.
+fn open_fifo(path: &Path) -> io::Result<File> {
+    let not_fifo = || io::Error::new(io::ErrorKind::InvalidInput, "not a FIFO");
+    if !fs::metadata(path)?.file_type().is_fifo() {
+        return Err(not_fifo());
+    }
+    let file = File::open(path)?;
+    if !file.metadata()?.file_type().is_fifo() {
+        return Err(not_fifo());
+    }
+    Ok(file)
+}
+
+/// Forwards every line of a stream, including a last one without newline, until end of input.
+/// Lines longer than `MAX_LINE_SIZE` are forwarded in pieces.
+pub(crate) fn forward_lines(mut reader: impl BufRead, name: &str, live: &LiveSources) -> io::Result<()> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.by_ref().take(MAX_LINE_SIZE as u64).read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        live.push(name, &TextEncoding::Utf8.decode(&line));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use std::process::Command;
+    use std::sync::Mutex;
+    use tokio::sync::broadcast;
+    use crate::log_mgr::config::LiveConfig;
+    use crate::log_mgr::diagnostics::StatusData;
+    use crate::log_mgr::log_context_data::LogContextData;
+
+    fn live_sources() -> LiveSources {
+        let (log_tx, _) = broadcast::channel(16);
+        let context = Arc::new(Mutex::new(LogContextData::new()));
+        let status = Arc::new(Mutex::new(StatusData::default()));
+        LiveSources::new(log_tx, context, status, &LiveConfig::default())
+    }
+
+    fn texts(live: &LiveSources, name: &str) -> Vec<String> {
+        live.history(name).into_iter().map(|record| record.text).collect()
+    }
+
+    #[test]
+    fn lines_are_forwarded_until_end_of_input() {
+        let live = live_sources();
+        forward_lines(Cursor::new("first\r\nsecond\nlast"), STDIN_SOURCE, &live).unwrap();
+        assert_eq!(texts(&live, STDIN_SOURCE), ["first", "second", "last"]);
+    }
+
+    #[test]
+    fn overlong_lines_are_cut() {
+        let live = live_sources();
+        let mut input = vec![b'a'; MAX_LINE_SIZE + 10];
+        input.extend(b"\nnext\n");
+        forward_lines(Cursor::new(input), STDIN_SOURCE, &live).unwrap();
+
+        let lengths: Vec<usize> = texts(&live, STDIN_SOURCE).iter().map(String::len).collect();
+        assert_eq!(lengths, [MAX_LINE_SIZE, 10, 4]);
+    }
+
+    #[test]
+    fn only_fifos_are_opened() {
+        let dir = std::env::temp_dir().join(format!("loggyxp-{}-fifo", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("regular.log");
+        fs::write(&file, "not a pipe\n").unwrap();
+        assert_eq!(open_fifo(&file).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(open_fifo(&dir).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(open_fifo(&dir.join("missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let fifo = dir.join("app.fifo");
+        assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+        let writer = {
+            let fifo = fifo.clone();
+            thread::spawn(move || {
+                let mut pipe = fs::OpenOptions::new().write(true).open(fifo).unwrap();
+                pipe.write_all(b"one\ntwo\n").unwrap();
+            })
+        };
+        let live = live_sources();
+        forward_lines(BufReader::new(open_fifo(&fifo).unwrap()), "fifo://app", &live).unwrap();
+        writer.join().unwrap();
+        assert_eq!(texts(&live, "fifo://app"), ["one", "two"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,432 @@
+use std::io::{self, Read};
+use std::net::{IpAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::Duration;
+use regex::Regex;
+use tracing::{debug, error, info, info_span, warn};
+use crate::log_mgr::config::SyslogConfig;
+use crate::log_mgr::encoding::TextEncoding;
+use crate::log_mgr::live_source::LiveSources;
+
+/// How often blocked listener threads check whether they should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Largest message accepted; longer TCP frames are cut
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Severity names in the order of their RFC 5424 codes
+pub(crate) const SEVERITIES: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+/// RFC 3164 timestamp, e.g. `Oct 11 22:14:15`
+static BSD_TIMESTAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2} ").unwrap()
+});
+
+/// A parsed syslog message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogMessage {
+    pub severity: &'static str,
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub structured_data: Option<String>,
+    pub message: String,
+}
+
+impl SyslogMessage {
+    /// Name of the live source this message belongs to, e.g. `syslog://web01/nginx`
+    pub fn source_name(&self, sender: IpAddr) -> String {
+        let host = self.hostname.clone().unwrap_or_else(|| sender.to_string());
+        let app = self.app_name.as_deref().unwrap_or("-");
+        format!("syslog://{}/{}", sanitize(&host), sanitize(app))
+    }
+
+    /// The record shown for this message: `timestamp severity app[pid]: [sd] message`
+    pub fn record(&self) -> String {
+        let mut record = format!(
+            "{} {} {}",
+            self.timestamp.as_deref().unwrap_or("-"),
+            self.severity,
+            self.app_name.as_deref().unwrap_or("-"),
+        );
+        if let Some(pid) = &self.proc_id {
+            record.push_str(&format!("[{}]", pid));
+        }
+        record.push(':');
+        if let Some(sd) = &self.structured_data {
+            record.push(' ');
+            record.push_str(sd);
+        }
+        if !self.message.is_empty() {
+            record.push(' ');
+            record.push_str(&self.message);
+        }
+        record
+    }
+}
+
+/// Parses an RFC 5424 or RFC 3164 message; anything unparsable becomes the message text
+pub fn parse_message(text: &str) -> SyslogMessage {
+    let text = text.trim_end_matches(['\r', '\n', '\0']);
+
+    // <PRI>, RFC 3164 defaults to user.notice without one
+    let (severity, rest) = match text.strip_prefix('<').and_then(|r| r.split_once('>')) {
+        Some((pri, rest)) if !pri.is_empty() && pri.len() <= 3 && pri.bytes().all(|b| b.is_ascii_digit()) => {
+            let pri: usize = pri.parse().unwrap_or(13);
+            (SEVERITIES[pri % 8], rest)
+        }
+        _ => (SEVERITIES[5], text),
+    };
+
+    match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(severity, rest),
+        None => parse_rfc3164(severity, rest),
+    }
+}
+
+// VERSION was consumed: TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]
+fn parse_rfc5424(severity: &'static str, rest: &str) -> SyslogMessage {
+    let (timestamp, rest) = next_field(rest);
+    let (hostname, rest) = next_field(rest);
+    let (app_name, rest) = next_field(rest);
+    let (proc_id, rest) = next_field(rest);
+    let (_msg_id, rest) = next_field(rest);
+    let (structured_data, rest) = structured_data(rest);
+
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    SyslogMessage {
+        severity,
+        timestamp: nil_value(timestamp),
+        hostname: nil_value(hostname),
+        app_name: nil_value(app_name),
+        proc_id: nil_value(proc_id),
+        structured_data: nil_value(structured_data),
+        message: message.trim_start_matches('\u{FEFF}').to_string(),
+    }
+}
+
+// [TIMESTAMP HOSTNAME] TAG[PID]: MSG, where senders often leave out the header
+fn parse_rfc3164(severity: &'static str, rest: &str) -> SyslogMessage {
+    let mut timestamp = None;
+    let mut hostname = None;
+    let mut rest = rest;
+
+    if let Some(found) = BSD_TIMESTAMP.find(rest) {
+        timestamp = Some(found.as_str().trim_end().to_string());
+        rest = &rest[found.end()..];
+
+        // A hostname follows the timestamp unless the next word already is the tag
+        let (word, after) = next_field(rest);
+        if !after.is_empty() && !word.ends_with(':') && !word.contains('[') {
+            hostname = Some(word.to_string());
+            rest = after;
+        }
+    }
+
+    // The tag is a short word, optionally followed by [pid], then a colon
+    let tag_end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c))).unwrap_or(rest.len());
+    let (tag, after_tag) = rest.split_at(tag_end);
+    let (proc_id, after_pid) = match after_tag.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        Some((pid, after)) => (Some(pid.to_string()), after),
+        None => (None, after_tag),
+    };
+
+    let (app_name, proc_id, message) = match after_pid.strip_prefix(':') {
+        Some(message) if !tag.is_empty() && tag.len() <= 48 => (Some(tag.to_string()), proc_id, message.trim_start()),
+        _ => (None, None, rest),
+    };
+
+    SyslogMessage {
+        severity,
+        timestamp,
+        hostname,
+        app_name,
+        proc_id,
+        structured_data: None,
+        message: message.to_string(),
+    }
+}
+
+// Splits off the next space-separated header field
+fn next_field(s: &str) -> (&str, &str) {
+    s.split_once(' ').unwrap_or((s, ""))
+}
+
+// RFC 5424 "-" means the field is absent
+fn nil_value(field: &str) -> Option<String> {
+    if field.is_empty() || field == "-" { None } else { Some(field.to_string()) }
+}
+
+// STRUCTURED-DATA is "-" or one or more [elements], in which `]` may be escaped
+fn structured_data(s: &str) -> (&str, &str) {
+    if !s.starts_with('[') {
+        return next_field(s);
+    }
+
+    let bytes = s.as_bytes();
+    let mut end = 0;
+    let mut in_element = false;
+    let mut escaped = false;
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'[' if !in_element => in_element = true,
+            b']' if in_element => {
+                in_element = false;
+                end = i + 1;
+            }
+            _ if !in_element => break,
+            _ => {}
+        }
+    }
+    s.split_at(end)
+}
+
+// Keeps host and app names usable in a source name
+fn sanitize(name: &str) -> String {
+    name.chars().map(|c| if c == '/' || c.is_whitespace() { '_' } else { c }).collect()
+}
+
+/// Running syslog listeners; stopped explicitly or when the process exits
+pub struct SyslogListener {
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl SyslogListener {
+    /// Binds the configured UDP and TCP addresses and starts receiving in background threads
+    pub fn start(config: &SyslogConfig, live: Arc<LiveSources>) -> SyslogListener {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::new();
+
+        if let Some(addr) = &config.udp {
+            match UdpSocket::bind(addr) {
+                Ok(socket) => {
+                    info!(%addr, "Syslog listening on UDP");
+                    let (stop, live) = (Arc::clone(&stop), Arc::clone(&live));
+                    threads.push(thread::spawn(move || receive_udp(socket, live, stop)));
+                }
+                Err(e) => error!(%addr, "Failed to bind syslog UDP socket: {}", e),
+            }
+        }
+
+        if let Some(addr) = &config.tcp {
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    info!(%addr, "Syslog listening on TCP");
+                    let (stop, live) = (Arc::clone(&stop), Arc::clone(&live));
+                    let max_connections = config.max_connections.max(1);
+                    threads.push(thread::spawn(move || accept_tcp(listener, live, stop, max_connections)));
+                }
+                Err(e) => error!(%addr, "Failed to bind syslog TCP socket: {}", e),
+            }
+        }
+
+        SyslogListener { stop, threads }
+    }
+
+    /// Stops receiving and waits for the listener threads
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Decodes and forwards one message
+fn deliver(live: &LiveSources, sender: IpAddr, bytes: &[u8]) {
+    let text = TextEncoding::Utf8.decode(bytes);
+    if text.trim().is_empty() {
+        return;
+    }
+    let message = parse_message(&text);
+    live.push(&message.source_name(sender), &message.record());
+}
+
+fn receive_udp(socket: UdpSocket, live: Arc<LiveSources>, stop: Arc<AtomicBool>) {
+    if let Err(e) = socket.set_read_timeout(Some(POLL_INTERVAL)) {
+        error!("Cannot set syslog UDP timeout: {}", e);
+        return;
+    }
+
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    while !stop.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buf) {
+            Ok((len, sender)) => deliver(&live, sender.ip(), &buf[..len]),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(e) => warn!("Syslog UDP receive failed: {}", e),
+        }
+    }
+}
+
+// Accepts TCP senders, each read on its own thread; beyond `max_connections` new ones are closed
+fn accept_tcp(listener: TcpListener, live: Arc<LiveSources>, stop: Arc<AtomicBool>, max_connections: usize) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        error!("Cannot configure syslog TCP listener: {}", e);
+        return;
+    }
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    let mut refused = false;
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) if connections.load(Ordering::Relaxed) >= max_connections => {
+                // Warn once per overload rather than for every refused connection
+                if !refused {
+                    warn!(%peer, limit = max_connections, "Too many syslog TCP senders, closing new connections");
+                    refused = true;
+                }
+                drop(stream);
+            }
+            Ok((stream, peer)) => {
+                refused = false;
+                connections.fetch_add(1, Ordering::Relaxed);
+                let (live, stop, connections) = (Arc::clone(&live), Arc::clone(&stop), Arc::clone(&connections));
+                thread::spawn(move || {
+                    let _span = info_span!("syslog_tcp", %peer).entered();
+                    debug!("Sender connected");
+                    if let Err(e) = receive_tcp(stream, peer.ip(), &live, &stop) {
+                        warn!("Syslog connection failed: {}", e);
+                    }
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                    debug!("Sender disconnected");
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => warn!("Syslog TCP accept failed: {}", e),
+        }
+    }
+}
+
+// Reads frames from one TCP sender until it disconnects or the listener stops
+fn receive_tcp(mut stream: TcpStream, sender: IpAddr, live: &LiveSources, stop: &AtomicBool) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buf = vec![0u8; 16 * 1024];
+    while !stop.load(Ordering::Relaxed) {
+        let read = match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        };
+        pending.extend_from_slice(&buf[..read]);
+
+        while let Some((frame, consumed)) = next_frame(&pending) {
+            deliver(live, sender, frame);
+            pending.drain(..consumed);
+        }
+    }
+
+    // A last message without its trailing newline
+    if !pending.is_empty() {
+        deliver(live, sender, &pending);
+    }
+    Ok(())
+}
+
+/// Finds the first complete frame: octet-counted (`<length> <message>`, RFC 6587) when the data
+/// starts with a digit, otherwise newline-delimited. Returns the frame and the bytes it used.
+fn next_frame(data: &[u8]) -> Option<(&[u8], usize)> {
+    let digits = data.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits > 0 && digits < 10 {
+        if data.len() == digits {
+            return None;
+        }
+        if data[digits] == b' '
+            && let Some(len) = std::str::from_utf8(&data[..digits]).ok().and_then(|d| d.parse::<usize>().ok())
+            && len <= MAX_MESSAGE_SIZE {
+            let start = digits + 1;
+            return data.get(start..start + len).map(|frame| (frame, start + len));
+        }
+    }
+
+    match data.iter().position(|&b| b == b'\n') {
+        Some(pos) => Some((&data[..pos], pos + 1)),
+        None if data.len() >= MAX_MESSAGE_SIZE => Some((&data[..MAX_MESSAGE_SIZE], MAX_MESSAGE_SIZE)),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octet_counted_frames() {
+        let data = b"5 hello6 world!";
+        assert_eq!(next_frame(data), Some((&b"hello"[..], 7)));
+        assert_eq!(next_frame(&data[7..]), Some((&b"world!"[..], 8)));
+        // The length says more bytes follow than have arrived
+        assert_eq!(next_frame(b"10 hello"), None);
+        assert_eq!(next_frame(b"12"), None);
+        // A counted frame may contain newlines
+        assert_eq!(next_frame(b"7 a\nb\nc\n9 "), Some((&b"a\nb\nc\n9"[..], 9)));
+    }
+
+    #[test]
+    fn newline_delimited_frames() {
+        assert_eq!(next_frame(b"<13>hello\nrest"), Some((&b"<13>hello"[..], 10)));
+        assert_eq!(next_frame(b"<13>no newline yet"), None);
+        // Digits not followed by a space are the start of a message, not a length
+        assert_eq!(next_frame(b"123abc\n"), Some((&b"123abc"[..], 7)));
+        // Too many digits or a length above the limit are not trusted either
+        assert_eq!(next_frame(b"1234567890 x\n"), Some((&b"1234567890 x"[..], 13)));
+        let too_long = format!("{} x\n", MAX_MESSAGE_SIZE + 1);
+        assert_eq!(next_frame(too_long.as_bytes()).map(|(_, used)| used), Some(too_long.len()));
+    }
+
+    #[test]
+    fn overlong_lines_are_cut() {
+        let data = vec![b'a'; MAX_MESSAGE_SIZE + 10];
+        let (frame, used) = next_frame(&data).unwrap();
+        assert_eq!((frame.len(), used), (MAX_MESSAGE_SIZE, MAX_MESSAGE_SIZE));
+        assert_eq!(next_frame(&data[..MAX_MESSAGE_SIZE - 1]), None);
+    }
+
+    #[test]
+    fn rfc5424() {
+        let message = parse_message("<165>1 2024-05-01T10:00:00Z web01 nginx 1234 ID47 [ex@1 a=\"x\\]\"][b@1] started\n");
+        assert_eq!(message.severity, "notice");
+        assert_eq!(message.timestamp.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(message.hostname.as_deref(), Some("web01"));
+        assert_eq!(message.app_name.as_deref(), Some("nginx"));
+        assert_eq!(message.proc_id.as_deref(), Some("1234"));
+        assert_eq!(message.structured_data.as_deref(), Some("[ex@1 a=\"x\\]\"][b@1]"));
+        assert_eq!(message.message, "started");
+
+        let nil = parse_message("<14>1 - - - - - -");
+        assert_eq!(nil.record(), "- info -:");
+        assert_eq!((nil.timestamp, nil.hostname, nil.app_name, nil.structured_data), (None, None, None, None));
+    }
+
+    #[test]
+    fn rfc3164() {
+        let message = parse_message("<11>Oct  1 22:14:15 host1 su[42]: 'su root' failed");
+        assert_eq!(message.severity, "err");
+        assert_eq!(message.timestamp.as_deref(), Some("Oct  1 22:14:15"));
+        assert_eq!(message.hostname.as_deref(), Some("host1"));
+        assert_eq!(message.record(), "Oct  1 22:14:15 err su[42]: 'su root' failed");
+
+        // The header is often left out; without a PRI the severity is notice
+        let bare = parse_message("app: hello");
+        assert_eq!((bare.severity, bare.app_name.as_deref(), bare.message.as_str()), ("notice", Some("app"), "hello"));
+        let text = parse_message("<1000>just text: with a colon later");
+        assert_eq!((text.app_name, text.message.as_str()), (None, "<1000>just text: with a colon later"));
+    }
+
+    #[test]
+    fn source_names_stay_one_path_segment() {
+        let message = parse_message("<14>1 - web/01 my app - - - x");
+        assert_eq!(message.source_name("10.0.0.1".parse().unwrap()), "syslog://web_01/my");
+        let anonymous = parse_message("hello");
+        assert_eq!(anonymous.source_name("10.0.0.1".parse().unwrap()), "syslog://10.0.0.1/-");
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::log_mgr::config::TemplatesConfig;
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::timestamp;
+
+/// Placeholder for the tokens that vary between the records of a template
+pub const WILDCARD: &str = "<*>";
+
+/// How often changed templates are sent to the dashboards
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A message template and the records that fit it, as sent to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct Template {
+    pub id: u64,
+    /// The tokens of the records, with [`WILDCARD`] where they differ
+    pub template: String,
+    pub count: u64,
+    /// Timestamps (Unix milliseconds) of the first and latest record, when they have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<i64>,
+    pub examples: Vec<String>,
+}
+
+/// The templates of a source, sent to the dashboards as a `templates` event
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceTemplates {
+    pub path: String,
+    /// Most frequent first; for live updates only the templates that changed
+    pub templates: Vec<Template>,
+    /// Records that fit no template after `max_templates` was reached
+    pub unmatched: u64,
+    /// Whether this is an update of the templates sent before
+    pub live: bool,
+}
+
+/// Mines message templates from the records of one source, after Drain (He et al., 2017):
+/// records are split into tokens, tokens with digits are taken as variables, and a record joins
+/// the most similar template with the same number of tokens and the same leading tokens, or
+/// starts a new one. Where a joining record differs, the template gets a wildcard.
+#[derive(Debug, Clone)]
+pub struct TemplateMiner {
+    config: TemplatesConfig,
+    templates: Vec<Template>,
+    // Template tokens, by index in `templates`
+    tokens: Vec<Vec<String>>,
+    // Indexes of the templates per token count and leading tokens
+    groups: HashMap<(usize, Vec<String>), Vec<usize>>,
+    unmatched: u64,
+    // Templates changed since the last update, and when that update is due
+    changed: BTreeSet<usize>,
+    due: Option<Instant>,
+}
+
+impl TemplateMiner {
+    pub fn new(config: &TemplatesConfig) -> TemplateMiner {
+        TemplateMiner {
+            config: config.clone(),
+            templates: Vec::new(),
+            tokens: Vec::new(),
+            groups: HashMap::new(),
+            unmatched: 0,
+            changed: BTreeSet::new(),
+            due: None,
+        }
+    }
+
+    /// Mines the existing records of a source; records without a timestamp are dated by the
+    /// record before them
+    pub fn mine(&mut self, records: &[LogRecord]) {
+        let mut last_seen = None;
+        for record in records {
+            last_seen = timestamp::parse_millis(&record.text).or(last_seen);
+            self.add(&record.text, last_seen);
+        }
+        self.changed.clear();
+    }
+
+    /// Adds a record to its template, or starts one; returns the template's id
+    pub fn add(&mut self, text: &str, seen: Option<i64>) -> Option<u64> {
+        let tokens = tokenize(text);
+        if tokens.is_empty() {
+            return None;
+        }
+        let key = (tokens.len(), tokens.iter().take(self.config.prefix_tokens).cloned().collect::<Vec<_>>());
+        let best = self.groups.get(&key).into_iter()
+            .flatten()
+            .map(|&idx| (idx, similarity(&self.tokens[idx], &tokens)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .filter(|(_, similarity)| *similarity >= self.config.similarity)
+            .map(|(idx, _)| idx);
+
+        let idx = match best {
+            Some(idx) => {
+                for (token, new) in self.tokens[idx].iter_mut().zip(&tokens) {
+                    if token != new {
+                        *token = WILDCARD.to_string();
+                    }
+                }
+                let template = &mut self.templates[idx];
+                template.template = self.tokens[idx].join(" ");
+                template.count += 1;
+                template.first_seen = template.first_seen.or(seen);
+                template.last_seen = seen.or(template.last_seen);
+                if template.examples.len() < self.config.examples && !template.examples.iter().any(|e| e == text) {
+                    template.examples.push(text.to_string());
+                }
+                idx
+            }
+            None if self.templates.len() >= self.config.max_templates => {
+                self.unmatched += 1;
+                return None;
+            }
+            None => {
+                let idx = self.templates.len();
+                self.templates.push(Template {
+                    id: idx as u64 + 1,
+                    template: tokens.join(" "),
+                    count: 1,
+                    first_seen: seen,
+                    last_seen: seen,
+                    examples: if self.config.examples > 0 { vec![text.to_string()] } else { Vec::new() },
+                });
+                self.tokens.push(tokens);
+                self.groups.entry(key).or_default().push(idx);
+                idx
+            }
+        };
+        self.changed.insert(idx);
+        Some(self.templates[idx].id)
+    }
+
+    /// Whether a record fits a template as it is now
+    pub fn matches(&self, id: u64, text: &str) -> bool {
+        let Some(template) = (id as usize).checked_sub(1).and_then(|idx| self.tokens.get(idx)) else {
+            return false;
+        };
+        let tokens = tokenize(text);
+        tokens.len() == template.len()
+            && template.iter().zip(&tokens).all(|(token, other)| token == WILDCARD || token == other)
+    }
+
+    /// Whether a template with this id exists
+    pub fn contains(&self, id: u64) -> bool {
+        self.template(id).is_some()
+    }
+
+    /// The template with this id
+    pub fn template(&self, id: u64) -> Option<&Template> {
+        (id as usize).checked_sub(1).and_then(|idx| self.templates.get(idx))
+    }
+
+    /// All templates, most frequent first
+    pub fn templates(&self) -> Vec<Template> {
+        let mut templates = self.templates.clone();
+        templates.sort_by(|a, b| b.count.cmp(&a.count).then(a.id.cmp(&b.id)));
+        templates
+    }
+}
+
+/// Shows only the records of one template, or all but them
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateFilter {
+    pub id: u64,
+    pub exclude: bool,
+}
+
+impl TemplateFilter {
+    /// Whether a record passes the filter
+    pub fn passes(&self, miner: &TemplateMiner, text: &str) -> bool {
+        miner.matches(self.id, text) != self.exclude
+    }
+}
+
+/// The miner of a source and the clients that asked for its templates
+#[derive(Debug)]
+struct LiveMiner {
+    miner: TemplateMiner,
+    clients: HashSet<u64>,
+}
+
+/// The template miners of the sources whose templates a dashboard asked for. Records tailed
+/// afterwards keep mining; changed templates are sent at most once per [`UPDATE_INTERVAL`].
+#[derive(Debug, Default)]
+pub struct LiveTemplates {
+    config: TemplatesConfig,
+    miners: HashMap<PathBuf, LiveMiner>,
+}
+
+impl LiveTemplates {
+    pub fn new(config: &TemplatesConfig) -> LiveTemplates {
+        LiveTemplates { config: config.clone(), miners: HashMap::new() }
+    }
+
+    /// An empty miner with the configured settings
+    pub fn miner(&self) -> TemplateMiner {
+        TemplateMiner::new(&self.config)
+    }
+
+    /// Keeps mining the new records of a source for a client with `miner`, after adding the
+    /// `missed` records that arrived while it mined the existing ones. The latest miner replaces
+    /// the previous one, as its template ids are the ones the dashboards received last.
+    pub fn track(&mut self, path: PathBuf, mut miner: TemplateMiner, client: u64, missed: &[LogRecord], now: Instant) {
+        for record in missed {
+            add_live(&mut miner, &record.text, now);
+        }
+        let mut clients = self.miners.remove(&path).map(|live| live.clients).unwrap_or_default();
+        clients.insert(client);
+        self.miners.insert(path, LiveMiner { miner, clients });
+    }
+
+    /// Stops mining for a client that went away; returns the sources no other client asked for,
+    /// whose miners are dropped
+    pub fn release(&mut self, client: u64) -> Vec<PathBuf> {
+        let mut released = Vec::new();
+        self.miners.retain(|path, live| {
+            live.clients.remove(&client);
+            if live.clients.is_empty() {
+                released.push(path.clone());
+            }
+            !live.clients.is_empty()
+        });
+        released
+    }
+
+    /// The miner of a source, if its templates are mined
+    pub fn get(&self, path: &Path) -> Option<&TemplateMiner> {
+        self.miners.get(path).map(|live| &live.miner)
+    }
+
+    /// Adds a new record of a source; without a timestamp it is dated now
+    pub fn on_record(&mut self, path: &Path, content: &str, now: Instant) {
+        if let Some(live) = self.miners.get_mut(path) {
+            add_live(&mut live.miner, content, now);
+        }
+    }
+
+    /// Stops mining the records of a source
+    pub fn forget_source(&mut self, path: &Path) {
+        self.miners.remove(path);
+    }
+
+    /// When the next update is due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.miners.values().filter_map(|live| live.miner.due).min()
+    }
+
+    /// The updates due at `now`
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<SourceTemplates> {
+        self.miners.iter_mut()
+            .map(|(path, live)| (path, &mut live.miner))
+            .filter(|(_, miner)| miner.due.is_some_and(|due| due <= now))
+            .map(|(path, miner)| {
+                miner.due = None;
+                let templates = std::mem::take(&mut miner.changed).into_iter()
+                    .map(|idx| miner.templates[idx].clone())
+                    .collect();
+                SourceTemplates {
+                    path: path.to_string_lossy().to_string(),
+                    templates,
+                    unmatched: miner.unmatched,
+                    live: true,
+                }
+            })
+            .collect()
+    }
+}
+
+// Mines a tailed record and schedules the update of the templates it changed
+fn add_live(miner: &mut TemplateMiner, content: &str, now: Instant) {
+    let seen = timestamp::parse_millis(content).unwrap_or_else(timestamp::now_millis);
+    miner.add(content, Some(seen));
+    miner.due.get_or_insert(now + UPDATE_INTERVAL);
+}
+
+/// The templates of a freshly mined source
+pub fn source_templates(path: &str, miner: &TemplateMiner) -> SourceTemplates {
+    SourceTemplates { path: path.to_string(), templates: miner.templates(), unmatched: miner.unmatched, live: false }
+}
+
+// The tokens of the first line of a record without its timestamp; tokens with digits are variables
+fn tokenize(text: &str) -> Vec<String> {
+    let line = text.lines().next().unwrap_or_default();
+    let (before, after) = match timestamp::span(line) {
+        // Brackets around the timestamp go with it, e.g. Apache's `[Sun Dec 04 04:47:44 2005]`
+        Some(span) => match (line[..span.start].strip_suffix('['), line[span.end..].strip_prefix(']')) {
+            (Some(before), Some(after)) => (before, after),
+            _ => (&line[..span.start], &line[span.end..]),
+        },
+        None => (line, ""),
+    };
+    before.split_whitespace()
+        .chain(after.split_whitespace())
+        .map(|token| if token.chars().any(|c| c.is_ascii_digit()) { WILDCARD.to_string() } else { token.to_string() })
+        .collect()
+}
+
+// Share of positions where a record fits a template: the same token, or a wildcard
+fn similarity(template: &[String], tokens: &[String]) -> f64 {
+    let fitting = template.iter().zip(tokens).filter(|(a, b)| a == b || a.as_str() == WILDCARD).count();
+    fitting as f64 / template.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn miner() -> TemplateMiner {
+        TemplateMiner::new(&TemplatesConfig::default())
+    }
+
+    #[test]
+    fn tokens_with_digits_are_variables() {
+        assert_eq!(tokenize("user 42 logged in from 10.0.0.1"), ["user", "<*>", "logged", "in", "from", "<*>"]);
+        assert_eq!(tokenize("  spaced\tout  "), ["spaced", "out"]);
+        assert!(tokenize("").is_empty());
+        // Only the first line of a multi-line record
+        assert_eq!(tokenize("Exception here\n  at Foo.bar(Foo.java:10)"), ["Exception", "here"]);
+    }
+
+    #[test]
+    fn timestamps_are_left_out() {
+        assert_eq!(tokenize("2024-05-01T10:00:00Z INFO started"), ["INFO", "started"]);
+        assert_eq!(tokenize("[Wed May 01 10:00:00 2024] [error] failed"), ["[error]", "failed"]);
+        assert_eq!(tokenize("host - - [01/May/2024:10:00:00 +0000] \"GET /\""), ["host", "-", "-", "\"GET", "/\""]);
+    }
+
+    #[test]
+    fn similar_records_share_a_template() {
+        let mut miner = miner();
+        let first = miner.add("connection from alice closed", None);
+        let second = miner.add("connection from bob closed", None);
+        assert_eq!(first, second);
+        let template = miner.template(first.unwrap()).unwrap();
+        assert_eq!((template.template.as_str(), template.count), ("connection from <*> closed", 2));
+        assert!(miner.matches(first.unwrap(), "connection from carol closed"));
+        assert!(!miner.matches(first.unwrap(), "connection from carol closed early"));
+
+        // A different token count or different leading tokens start another template
+        assert_ne!(miner.add("connection from alice closed early", None), first);
+        assert_ne!(miner.add("session from alice closed", None), first);
+    }
+
+    #[test]
+    fn max_templates_counts_the_rest_as_unmatched() {
+        let mut miner = TemplateMiner::new(&TemplatesConfig { max_templates: 1, ..TemplatesConfig::default() });
+        assert_eq!(miner.add("a b", None), Some(1));
+        assert_eq!(miner.add("x y z", None), None);
+        assert_eq!(source_templates("p", &miner).unmatched, 1);
+        assert!(!miner.contains(2));
+        assert!(!miner.matches(0, "a b"));
+    }
+
+    #[test]
+    fn mining_dates_records_by_the_one_before() {
+        let mut miner = miner();
+        miner.mine(&[
+            LogRecord::new(1, "2024-05-01T10:00:00Z job done"),
+            LogRecord::new(2, "job done"),
+        ]);
+        let template = &miner.templates()[0];
+        assert_eq!((template.count, template.first_seen, template.last_seen), (2, Some(1_714_557_600_000), Some(1_714_557_600_000)));
+        assert_eq!(template.examples, ["2024-05-01T10:00:00Z job done", "job done"]);
+    }
+
+    #[test]
+    fn filter_keeps_or_drops_a_template() {
+        let mut miner = miner();
+        let id = miner.add("disk full on sda", None).unwrap();
+        let only = TemplateFilter { id, exclude: false };
+        let without = TemplateFilter { id, exclude: true };
+        assert!(only.passes(&miner, "disk full on sda"));
+        assert!(!only.passes(&miner, "disk ok"));
+        assert!(without.passes(&miner, "disk ok"));
+    }
+
+    #[test]
+    fn live_miners_catch_up_and_are_released_with_their_clients() {
+        let mut live = LiveTemplates::default();
+        let path = PathBuf::from("/var/log/app.log");
+        let now = Instant::now();
+        let mut first = live.miner();
+        first.mine(&[LogRecord::new(1, "disk full on sda")]);
+        live.track(path.clone(), first, 1, &[LogRecord::new(2, "user alice logged in")], now);
+        assert_eq!(live.get(&path).unwrap().templates().len(), 2);
+
+        // A later request replaces the miner but keeps the earlier client
+        live.track(path.clone(), live.miner(), 2, &[], now);
+        assert!(live.get(&path).unwrap().templates().is_empty());
+        assert!(live.release(2).is_empty());
+        assert_eq!(live.release(1), vec![path.clone()]);
+        assert!(live.get(&path).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::notification;
+use crate::log_mgr::timestamp;
+
+/// Name prefix of merged timelines, e.g. `merged://checkout`
+pub const MERGED_SCHEME: &str = "merged://";
+
+/// Whether a client-supplied name refers to a merged timeline rather than a file
+pub fn is_merged_name(name: &str) -> bool {
+    name.starts_with(MERGED_SCHEME)
+}
+
+/// Tags for the files of a timeline: their file names, or the full paths when names repeat
+pub fn origins(paths: &[PathBuf]) -> Vec<String> {
+    let names: Vec<String> = paths.iter()
+        .map(|path| path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned()))
+        .collect();
+    let unique = names.iter().enumerate().all(|(i, name)| !names[..i].contains(name));
+    if unique {
+        names
+    } else {
+        paths.iter().map(|path| path.display().to_string()).collect()
+    }
+}
+
+/// Merges the records of several sources into one list ordered by their timestamps, each
+/// tagged with its origin. Records without a timestamp (continuations, banners) keep the
+/// timestamp of the record before them in their source; ties keep the order of the sources.
+pub fn merge_records(sources: Vec<(String, Vec<LogRecord>)>) -> Vec<LogRecord> {
+    let mut merged = Vec::new();
+    for (origin, records) in sources {
+        let mut last = i64::MIN;
+        for record in records {
+            last = timestamp::parse_millis(&record.text).unwrap_or(last);
+            merged.push((last, record.with_origin(origin.clone())));
+        }
+    }
+    merged.sort_by_key(|(at, _)| *at);
+    merged.into_iter().map(|(_, record)| record).collect()
+}
+
+/// A record held back until its reordering window has passed; ordered by timestamp, then arrival
+#[derive(Debug)]
+struct Pending {
+    at: i64,
+    seq: u64,
+    due: Instant,
+    record: LogRecord,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+/// A merged live view of several tailed files.
+///
+/// New records are held for the reordering window and released in timestamp order, so a
+/// writer that is slightly behind the others still lands in the right place.
+#[derive(Debug)]
+pub struct Timeline {
+    paths: Vec<PathBuf>,
+    origins: Vec<String>,
+    window: Duration,
+    // Timestamp of the latest record per file, for records without one
+    last: Vec<Option<i64>>,
+    pending: BinaryHeap<Reverse<Pending>>,
+    seq: u64,
+}
+
+impl Timeline {
+    pub fn new(paths: Vec<PathBuf>, window: Duration) -> Timeline {
+        let origins = origins(&paths);
+        let last = vec![None; paths.len()];
+        Timeline { paths, origins, window, last, pending: BinaryHeap::new(), seq: 0 }
+    }
+
+    /// The files merged into this timeline
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.iter().any(|p| p == path)
+    }
+
+    /// Takes a new record of one of the files
+    pub fn push(&mut self, path: &Path, record: &LogRecord, now: Instant) {
+        let Some(idx) = self.paths.iter().position(|p| p == path) else {
+            return;
+        };
+        // Without any timestamp yet, the arrival time is the best guess
+        let at = timestamp::parse_millis(&record.text)
+            .or(self.last[idx])
+            .unwrap_or_else(timestamp::now_millis);
+        self.last[idx] = Some(at);
+
+        self.seq += 1;
+        self.pending.push(Reverse(Pending {
+            at,
+            seq: self.seq,
+            due: notification::deadline_after(now, self.window),
+            record: record.clone().with_origin(self.origins[idx].clone()),
+        }));
+    }
+
+    /// When the earliest held record may be released
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.peek().map(|Reverse(pending)| pending.due)
+    }
+
+    /// Releases held records in timestamp order, up to the first one still in its window
+    pub fn release(&mut self, now: Instant) -> Vec<LogRecord> {
+        let mut records = Vec::new();
+        while let Some(Reverse(pending)) = self.pending.peek() && pending.due <= now {
+            if let Some(Reverse(pending)) = self.pending.pop() {
+                records.push(pending.record);
+            }
+        }
+        records
+    }
+}
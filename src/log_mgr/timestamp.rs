@@ -0,0 +1,238 @@
+use std::ops::Range;
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use regex::Regex;
+
+/// How far into a record a timestamp is looked for
+const SEARCH_PREFIX: usize = 128;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// ISO 8601 / RFC 3339, e.g. `2024-05-01T10:00:00.123Z` or `2024-05-01 10:00:00,123 +0200`
+static ISO: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2}):(\d{2})(?:[.,](\d{1,9}))?(?: ?(Z|[+-]\d{2}:?\d{2}))?").unwrap()
+});
+
+/// Apache access log, e.g. `01/May/2024:10:00:00 +0000`
+static APACHE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\d{2})/([A-Z][a-z]{2})/(\d{4}):(\d{2}):(\d{2}):(\d{2})(?: ([+-]\d{4}))?").unwrap()
+});
+
+/// Apache error log and ctime, e.g. `Wed May 01 10:00:00.123456 2024`
+static CTIME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Z][a-z]{2} ([A-Z][a-z]{2}) +(\d{1,2}) (\d{2}):(\d{2}):(\d{2})(?:\.(\d{1,9}))? (\d{4})").unwrap()
+});
+
+/// BSD syslog without a year, e.g. `May  1 10:00:00`
+static SYSLOG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([A-Z][a-z]{2}) +(\d{1,2}) (\d{2}):(\d{2}):(\d{2})").unwrap()
+});
+
+/// Milliseconds since the Unix epoch now
+pub fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Finds the timestamp near the start of a record and returns it as milliseconds since the
+/// Unix epoch. Timestamps without a zone are taken as UTC; syslog timestamps without a year
+/// are placed in the last twelve months.
+pub fn parse_millis(text: &str) -> Option<i64> {
+    let head = head(text);
+    if let Some(caps) = ISO.captures(head) {
+        let offset = caps.get(8).map_or(Some(0), |zone| zone_offset_minutes(zone.as_str()))?;
+        return to_millis(
+            (num(&caps[1])?, num(&caps[2])?, num(&caps[3])?),
+            (num(&caps[4])?, num(&caps[5])?, num(&caps[6])?),
+            caps.get(7).map_or(0, |f| fraction_millis(f.as_str())), offset,
+        );
+    }
+    if let Some(caps) = APACHE.captures(head) {
+        let offset = caps.get(7).map_or(Some(0), |zone| zone_offset_minutes(zone.as_str()))?;
+        return to_millis(
+            (num(&caps[3])?, month(&caps[2])?, num(&caps[1])?),
+            (num(&caps[4])?, num(&caps[5])?, num(&caps[6])?), 0, offset,
+        );
+    }
+    if let Some(caps) = CTIME.captures(head) {
+        return to_millis(
+            (num(&caps[7])?, month(&caps[1])?, num(&caps[2])?),
+            (num(&caps[3])?, num(&caps[4])?, num(&caps[5])?),
+            caps.get(6).map_or(0, |f| fraction_millis(f.as_str())), 0,
+        );
+    }
+    if let Some(caps) = SYSLOG.captures(head) {
+        let now = now_millis();
+        let (year, _, _) = civil_from_days(now.div_euclid(86_400_000));
+        let at = |year| to_millis(
+            (year, month(&caps[1])?, num(&caps[2])?),
+            (num(&caps[3])?, num(&caps[4])?, num(&caps[5])?), 0, 0,
+        );
+        // A date later than tomorrow was written last year
+        return at(year).map(|t| if t > now + 86_400_000 { at(year - 1).unwrap_or(t) } else { t });
+    }
+    None
+}
+
+/// Where the timestamp [`parse_millis`] reads is in a record
+pub fn span(text: &str) -> Option<Range<usize>> {
+    let head = head(text);
+    [&ISO, &APACHE, &CTIME, &SYSLOG].into_iter()
+        .find_map(|regex| regex.find(head))
+        .map(|m| m.range())
+}
+
+/// Formats microseconds since the Unix epoch as an RFC 3339 UTC timestamp
+pub fn format_micros(micros: i64) -> String {
+    let secs = micros.div_euclid(1_000_000);
+    let (days, day_secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year, month, day,
+        day_secs / 3600, day_secs % 3600 / 60, day_secs % 60,
+        micros.rem_euclid(1_000_000),
+    )
+}
+
+// The start of a record a timestamp is looked for in
+fn head(text: &str) -> &str {
+    let mut end = text.len().min(SEARCH_PREFIX);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+// Milliseconds since the epoch of a civil date and time of day at a UTC offset in minutes
+fn to_millis((year, month, day): (i64, i64, i64), (hour, minute, second): (i64, i64, i64), millis: i64, offset: i64) -> Option<i64> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset * 60;
+    Some(secs * 1000 + millis)
+}
+
+fn num(s: &str) -> Option<i64> {
+    s.parse().ok()
+}
+
+fn month(name: &str) -> Option<i64> {
+    MONTHS.iter().position(|m| *m == name).map(|idx| idx as i64 + 1)
+}
+
+// Milliseconds of a fractional second given by its digits, e.g. `123456` -> 123
+fn fraction_millis(digits: &str) -> i64 {
+    format!("{:0<3}", &digits[..digits.len().min(3)]).parse().unwrap_or(0)
+}
+
+// `Z`, `+02:00` or `-0500` as minutes east of UTC
+fn zone_offset_minutes(zone: &str) -> Option<i64> {
+    if zone == "Z" {
+        return Some(0);
+    }
+    let sign = if zone.starts_with('-') { -1 } else { 1 };
+    let digits: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4)?.parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Date of a number of days since 1970-01-01 in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-05-01T10:00:00Z
+    const MAY_FIRST: i64 = 1_714_557_600_000;
+
+    #[test]
+    fn iso_zones() {
+        assert_eq!(parse_millis("2024-05-01T10:00:00Z started"), Some(MAY_FIRST));
+        assert_eq!(parse_millis("2024-05-01T10:00:00 started"), Some(MAY_FIRST));
+        assert_eq!(parse_millis("2024-05-01T12:00:00+02:00 started"), Some(MAY_FIRST));
+        assert_eq!(parse_millis("2024-05-01 12:00:00 +0200 started"), Some(MAY_FIRST));
+        assert_eq!(parse_millis("2024-05-01T04:30:00-05:30 started"), Some(MAY_FIRST));
+    }
+
+    #[test]
+    fn iso_fractions() {
+        assert_eq!(parse_millis("2024-05-01T10:00:00.123Z"), Some(MAY_FIRST + 123));
+        assert_eq!(parse_millis("2024-05-01T10:00:00.123456789Z"), Some(MAY_FIRST + 123));
+        assert_eq!(parse_millis("2024-05-01 10:00:00,5"), Some(MAY_FIRST + 500));
+        assert_eq!(parse_millis("2024-05-01T10:00:00.07Z"), Some(MAY_FIRST + 70));
+        assert_eq!(parse_millis("2024-05-01T12:00:00.250+02:00"), Some(MAY_FIRST + 250));
+    }
+
+    #[test]
+    fn other_formats() {
+        assert_eq!(parse_millis("127.0.0.1 - - [01/May/2024:12:00:00 +0200] \"GET /\""), Some(MAY_FIRST));
+        assert_eq!(parse_millis("[01/May/2024:10:00:00] GET"), Some(MAY_FIRST));
+        assert_eq!(parse_millis("[Wed May 01 10:00:00.5 2024] [error] x"), Some(MAY_FIRST + 500));
+        assert_eq!(parse_millis("[Wed May  1 10:00:00 2024] [error] x"), Some(MAY_FIRST));
+    }
+
+    #[test]
+    fn calendar_edges() {
+        assert_eq!(parse_millis("2024-02-29T23:59:59Z"), Some(1_709_251_199_000));
+        assert_eq!(parse_millis("1969-12-31T23:59:59Z"), Some(-1000));
+        assert_eq!(parse_millis("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_millis("2024-05-01T24:00:00Z"), None);
+        assert_eq!(parse_millis("01/Foo/2024:10:00:00 +0000"), None);
+    }
+
+    #[test]
+    fn syslog_without_year_is_in_the_last_year() {
+        let now = now_millis();
+        for text in ["Jan  1 00:00:00 host app: x", "Dec 31 23:59:59 host app: x"] {
+            let t = parse_millis(text).unwrap();
+            assert!(t <= now + 86_400_000 && t > now - 367 * 86_400_000, "{} -> {}", text, t);
+        }
+    }
+
+    #[test]
+    fn only_the_start_of_a_record_is_searched() {
+        let late = format!("{} 2024-05-01T10:00:00Z", "x".repeat(SEARCH_PREFIX));
+        assert_eq!(parse_millis(&late), None);
+        // The prefix ends inside a multi-byte character
+        let text = format!("{}é 2024", "x".repeat(SEARCH_PREFIX - 1));
+        assert_eq!(parse_millis(&text), None);
+        assert_eq!(parse_millis("no timestamp here"), None);
+    }
+
+    #[test]
+    fn span_of_the_timestamp() {
+        assert_eq!(span("at 2024-05-01T10:00:00Z x"), Some(3..23));
+        assert_eq!(span("May  1 10:00:00 host"), Some(0..15));
+        assert_eq!(span("nothing"), None);
+    }
+
+    #[test]
+    fn format_micros_round_trip() {
+        assert_eq!(format_micros(MAY_FIRST * 1000 + 123_456), "2024-05-01T10:00:00.123456Z");
+        assert_eq!(format_micros(-1), "1969-12-31T23:59:59.999999Z");
+        assert_eq!(parse_millis(&format_micros(MAY_FIRST * 1000 + 999_999)), Some(MAY_FIRST + 999));
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use regex::Regex;
+use serde::Serialize;
+use crate::log_mgr::log_record::LogRecord;
+use crate::log_mgr::notification::Matcher;
+use crate::log_mgr::timestamp;
+
+/// How far into a record a level is looked for
+const SEARCH_PREFIX: usize = 128;
+
+/// Most buckets a histogram gets when the client leaves the bucket size to the server
+const MAX_BUCKETS: i64 = 120;
+
+/// Bucket sizes chosen from, in milliseconds: 1s to 1 week
+const BUCKET_STEPS: [i64; 14] = [
+    1_000, 5_000, 10_000, 30_000, 60_000, 300_000, 600_000, 1_800_000,
+    3_600_000, 10_800_000, 21_600_000, 43_200_000, 86_400_000, 604_800_000,
+];
+
+/// How often the counts of new records are sent to the dashboards
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Level words of common formats: syslog and journal severities, Log4j/Logback, Python, Apache
+static LEVEL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(emerg|emergency|alert|fatal|crit|critical|severe|err|error|warn|warning|notice|info|debug|trace)\b").unwrap()
+});
+
+/// Severity of a record, as far as its text tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Finds the level word near the start of a record, e.g. `ERROR`, `[warn]` or syslog's `crit`
+pub fn detect_level(text: &str) -> Option<Level> {
+    let mut end = text.len().min(SEARCH_PREFIX);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let word = LEVEL.find(&text[..end])?.as_str().to_lowercase();
+    Some(match word.as_str() {
+        "warn" | "warning" => Level::Warn,
+        "notice" | "info" => Level::Info,
+        "debug" => Level::Debug,
+        "trace" => Level::Trace,
+        _ => Level::Error,
+    })
+}
+
+/// Records of one time bucket
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VolumeBucket {
+    /// Start of the bucket in Unix milliseconds
+    pub start: i64,
+    pub total: u64,
+    /// Records per recognised level; records without one only count in `total`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub levels: BTreeMap<Level, u64>,
+}
+
+/// Record counts per time bucket of a source, sent to the dashboards as a `volume` event
+#[derive(Debug, Clone, Serialize)]
+pub struct Volume {
+    pub path: String,
+    /// Search the counted records match; all records when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub regex: bool,
+    pub bucket_ms: i64,
+    /// Buckets with records, oldest first
+    pub buckets: Vec<VolumeBucket>,
+    /// Matching records without a timestamp before them, which fit no bucket
+    #[serde(skip_serializing_if = "is_zero")]
+    pub untimed: u64,
+    /// Whether the buckets hold the counts of new records, to add to the ones sent before
+    pub live: bool,
+}
+
+/// Which records a volume counts
+#[derive(Debug, Clone)]
+pub struct VolumeQuery {
+    pattern: Option<String>,
+    regex: bool,
+    matcher: Option<Matcher>,
+}
+
+impl VolumeQuery {
+    /// Counts the records containing `pattern` (case-insensitive, or matching it as a regex);
+    /// all records when it is unset or empty
+    pub fn new(pattern: Option<String>, regex: bool) -> Result<VolumeQuery, String> {
+        let pattern = pattern.filter(|pattern| !pattern.is_empty());
+        let matcher = pattern.as_deref()
+            .map(|pattern| Matcher::new(pattern, regex))
+            .transpose()
+            .map_err(|e| format!("invalid regex: {}", e))?;
+        Ok(VolumeQuery { pattern, regex, matcher })
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        self.matcher.as_ref().is_none_or(|matcher| matcher.matches(text))
+    }
+
+    fn same_as(&self, other: &VolumeQuery) -> bool {
+        self.pattern == other.pattern && self.regex == other.regex
+    }
+}
+
+/// Counts the existing records of a source per bucket. Records without a timestamp count at the
+/// time of the record before them. Without `bucket_ms` the size is chosen with [`bucket_size`].
+pub fn histogram(path: &str, records: &[LogRecord], query: &VolumeQuery, bucket_ms: Option<i64>) -> Volume {
+    let mut last_seen = None;
+    let mut untimed = 0;
+    let mut timed = Vec::new();
+    for record in records {
+        last_seen = timestamp::parse_millis(&record.text).or(last_seen);
+        if !query.matches(&record.text) {
+            continue;
+        }
+        match last_seen {
+            Some(at) => timed.push((at, detect_level(&record.text))),
+            None => untimed += 1,
+        }
+    }
+
+    let bucket_ms = bucket_ms.map(|ms| ms.max(1)).unwrap_or_else(|| {
+        let first = timed.iter().map(|(at, _)| *at).min().unwrap_or_default();
+        let last = timed.iter().map(|(at, _)| *at).max().unwrap_or_default();
+        bucket_size(last - first)
+    });
+    let mut buckets = BTreeMap::new();
+    for (at, level) in timed {
+        count(&mut buckets, at, bucket_ms, level);
+    }
+
+    Volume {
+        path: path.to_string(),
+        pattern: query.pattern.clone(),
+        regex: query.regex,
+        bucket_ms,
+        buckets: buckets.into_values().collect(),
+        untimed,
+        live: false,
+    }
+}
+
+/// The smallest bucket size that splits `span_ms` into at most [`MAX_BUCKETS`] buckets
+pub fn bucket_size(span_ms: i64) -> i64 {
+    BUCKET_STEPS.iter()
+        .copied()
+        .find(|step| span_ms / step < MAX_BUCKETS)
+        .unwrap_or(BUCKET_STEPS[BUCKET_STEPS.len() - 1])
+}
+
+/// Counts of the new records of a source since the last update, for the dashboards that asked
+/// for its volume
+#[derive(Debug)]
+struct VolumeTracker {
+    path: PathBuf,
+    query: VolumeQuery,
+    bucket_ms: i64,
+    clients: HashSet<u64>,
+    pending: BTreeMap<i64, VolumeBucket>,
+    due: Option<Instant>,
+}
+
+impl VolumeTracker {
+    // Counts a matching record at `at`
+    fn add(&mut self, content: &str, at: i64, now: Instant) {
+        count(&mut self.pending, at, self.bucket_ms, detect_level(content));
+        self.due.get_or_insert(now + UPDATE_INTERVAL);
+    }
+}
+
+/// Keeps the volumes the dashboards asked for current while their sources are tailed.
+/// New records are counted as they arrive and sent at most once per [`UPDATE_INTERVAL`].
+#[derive(Debug, Default)]
+pub struct LiveVolumes {
+    trackers: Vec<VolumeTracker>,
+}
+
+impl LiveVolumes {
+    /// Starts counting the new records of a source for a client, beginning with `missed` ones
+    /// that arrived while its existing records were counted. Clients asking for the same volume
+    /// share one tracker.
+    pub fn track(&mut self, path: PathBuf, query: VolumeQuery, bucket_ms: i64, client: u64, missed: &[LogRecord], now: Instant) {
+        let tracked = self.trackers.iter_mut().find(|tracker| {
+            tracker.path == path && tracker.bucket_ms == bucket_ms && tracker.query.same_as(&query)
+        });
+        if let Some(tracker) = tracked {
+            // Its updates already counted the missed records
+            tracker.clients.insert(client);
+            return;
+        }
+        let mut tracker = VolumeTracker { path, query, bucket_ms, clients: HashSet::from([client]), pending: BTreeMap::new(), due: None };
+        for record in missed {
+            if tracker.query.matches(&record.text) {
+                let at = timestamp::parse_millis(&record.text).unwrap_or_else(timestamp::now_millis);
+                tracker.add(&record.text, at, now);
+            }
+        }
+        self.trackers.push(tracker);
+    }
+
+    /// Stops counting for a client that went away; trackers no other client uses are dropped
+    pub fn release(&mut self, client: u64) {
+        for tracker in &mut self.trackers {
+            tracker.clients.remove(&client);
+        }
+        self.trackers.retain(|tracker| !tracker.clients.is_empty());
+    }
+
+    /// Counts a new record of a source; without a timestamp it counts now
+    pub fn on_record(&mut self, path: &Path, content: &str, now: Instant) {
+        let mut at = None;
+        for tracker in self.trackers.iter_mut().filter(|tracker| tracker.path == path) {
+            if !tracker.query.matches(content) {
+                continue;
+            }
+            let at = *at.get_or_insert_with(|| timestamp::parse_millis(content).unwrap_or_else(timestamp::now_millis));
+            tracker.add(content, at, now);
+        }
+    }
+
+    /// Stops counting the records of a source
+    pub fn forget_source(&mut self, path: &Path) {
+        self.trackers.retain(|tracker| tracker.path != path);
+    }
+
+    /// When the next update is due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.trackers.iter().filter_map(|tracker| tracker.due).min()
+    }
+
+    /// The updates due at `now`
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<Volume> {
+        self.trackers.iter_mut()
+            .filter(|tracker| tracker.due.is_some_and(|due| due <= now))
+            .map(|tracker| {
+                tracker.due = None;
+                Volume {
+                    path: tracker.path.to_string_lossy().to_string(),
+                    pattern: tracker.query.pattern.clone(),
+                    regex: tracker.query.regex,
+                    bucket_ms: tracker.bucket_ms,
+                    buckets: std::mem::take(&mut tracker.pending).into_values().collect(),
+                    untimed: 0,
+                    live: true,
+                }
+            })
+            .collect()
+    }
+}
+
+// Adds a record at `at` to its bucket
+fn count(buckets: &mut BTreeMap<i64, VolumeBucket>, at: i64, bucket_ms: i64, level: Option<Level>) {
+    let start = at.div_euclid(bucket_ms) * bucket_ms;
+    let bucket = buckets.entry(start).or_insert_with(|| VolumeBucket { start, ..VolumeBucket::default() });
+    bucket.total += 1;
+    if let Some(level) = level {
+        *bucket.levels.entry(level).or_default() += 1;
+    }
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_found_near_the_start() {
+        assert_eq!(detect_level("2024-05-01 10:00:00 [ERROR] disk full"), Some(Level::Error));
+        assert_eq!(detect_level("<4>May  1 10:00:00 host app: warning: slow"), Some(Level::Warn));
+        assert_eq!(detect_level("nothing to see"), None);
+        assert_eq!(detect_level(&format!("{} error", "x".repeat(SEARCH_PREFIX))), None);
+    }
+
+    #[test]
+    fn histogram_counts_per_bucket() {
+        let records = [
+            LogRecord::new(1, "2024-05-01T10:00:01Z ERROR a"),
+            LogRecord::new(2, "  continuation"),
+            LogRecord::new(3, "2024-05-01T10:00:59Z INFO b"),
+            LogRecord::new(4, "2024-05-01T10:01:00Z INFO c"),
+        ];
+        let volume = histogram("app.log", &records, &VolumeQuery::new(None, false).unwrap(), Some(60_000));
+        let totals: Vec<u64> = volume.buckets.iter().map(|bucket| bucket.total).collect();
+        assert_eq!(totals, [3, 1]);
+        assert_eq!(volume.buckets[0].levels.get(&Level::Error), Some(&1));
+        assert_eq!(volume.untimed, 0);
+    }
+
+    #[test]
+    fn trackers_are_shared_and_released_with_their_clients() {
+        let mut volumes = LiveVolumes::default();
+        let path = PathBuf::from("/var/log/app.log");
+        let query = || VolumeQuery::new(Some(String::from("error")), false).unwrap();
+        let now = Instant::now();
+        let missed = [LogRecord::new(5, "2024-05-01T10:00:01Z ERROR missed"), LogRecord::new(6, "INFO other")];
+        volumes.track(path.clone(), query(), 60_000, 1, &missed, now);
+        volumes.track(path.clone(), query(), 60_000, 2, &missed, now);
+        assert_eq!(volumes.trackers.len(), 1);
+
+        let updates = volumes.flush_expired(now + UPDATE_INTERVAL);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].buckets[0].total, 1);
+
+        volumes.release(1);
+        assert_eq!(volumes.trackers.len(), 1);
+        volumes.release(2);
+        assert!(volumes.trackers.is_empty());
+    }
+}
@@ -1,6 +1,3 @@
-mod log_mgr;
-
-fn main(){
-    log_mgr::main();
+fn main() {
+    loggyxp::run();
 }
-